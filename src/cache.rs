@@ -36,6 +36,12 @@ pub fn insert(data: Arc<AtsData>) -> Symbol {
     k
 }
 
+//point a key from insert at new data, so that data which changes often, like the output of
+//ats/seq, doesn't make a key, and a pd symbol, for every change
+pub fn set(key: Symbol, data: &Arc<AtsData>) {
+    (*HASH).lock().unwrap().insert(key, Arc::downgrade(data));
+}
+
 pub fn get(key: Symbol) -> Option<Arc<AtsData>> {
     let mut out = None;
     let mut h = (*HASH).lock().unwrap();
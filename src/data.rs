@@ -1,7 +1,8 @@
+use crate::synth::{frame_pos, lerp};
 use ats_sys::ATS_HEADER;
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
 use std::slice;
 
 pub const NOISE_BANDS: usize = 25;
@@ -11,6 +12,7 @@ pub static NOISE_BAND_EDGES: &[f64; NOISE_BANDS + 1] = &[
     15500.0, 20000.0,
 ];
 
+#[derive(Clone, Copy)]
 pub enum AtsDataType {
     AmpFreq = 1,
     AmpFreqPhase = 2,
@@ -18,13 +20,17 @@ pub enum AtsDataType {
     AmpFreqPhaseNoise = 4,
 }
 
+#[derive(Clone)]
 pub struct Peak {
     pub amp: f64,
     pub freq: f64,
     pub noise_energy: Option<f64>,
     pub phase: Option<f64>,
+    //index of the critical band this peak was assigned to for noise distribution
+    pub band: usize,
 }
 
+#[derive(Clone)]
 pub struct AtsData {
     pub header: ATS_HEADER,
     pub frames: Box<[Box<[Peak]>]>,
@@ -32,13 +38,405 @@ pub struct AtsData {
     pub file_type: AtsDataType,
     pub source: String,
     partials: usize,
+    //per frame total amplitude and amplitude per critical band
+    amp_sums: Box<[f64]>,
+    band_amp_sums: Box<[[f64; NOISE_BANDS]]>,
+    //the band each partial spends the most amplitude in
+    partial_bands: Box<[usize]>,
+    noise_scale: NoiseScale,
+}
+
+//per frame features, see frame_stats
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FrameStats {
+    //total partial amplitude
+    pub amp: f64,
+    //amplitude weighted mean and standard deviation of the partial frequencies
+    pub centroid: f64,
+    pub spread: f64,
+    //noise power over sine power, 0 without sines
+    pub noise_ratio: f64,
+}
+
+//how band energies become the peaks' noise energy, different encoders expect different scaling
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NoiseScale {
+    //an rms amplitude, the scaling of the ATS tools
+    Rms,
+    //the square of the rms amplitude
+    Power,
+    //the energy share as stored
+    Raw,
+}
+
+impl NoiseScale {
+    fn apply(self, value: f64, window_size: f64) -> f64 {
+        match self {
+            NoiseScale::Rms => energy_rms(value, window_size),
+            NoiseScale::Power => value / (window_size * 0.04f64),
+            NoiseScale::Raw => value,
+        }
+    }
+}
+
+//the critical band a frequency falls in, anything outside of the bands goes in the last one
+pub fn band_of(freq: f64) -> usize {
+    NOISE_BAND_EDGES[0..NOISE_BANDS]
+        .iter()
+        .zip(NOISE_BAND_EDGES[1..].iter())
+        .position(|(lo, hi)| *lo <= freq && freq < *hi)
+        .unwrap_or(NOISE_BANDS - 1)
 }
 
 fn energy_rms(value: f64, window_size: f64) -> f64 {
     (value / (window_size * 0.04f64)).sqrt()
 }
 
+//share each band's energy between the peaks in that band, in proportion to their amplitude, using
+//the band assignments of this frame
+fn distribute_noise(
+    frame: &mut [Peak],
+    energy: &[f64; NOISE_BANDS],
+    window_size: f64,
+    scale: NoiseScale,
+) {
+    let mut sums = [0f64; NOISE_BANDS];
+    for p in frame.iter() {
+        sums[p.band] += p.amp;
+    }
+    for p in frame.iter_mut() {
+        let s = sums[p.band];
+        p.noise_energy = Some(if s > 0f64 {
+            scale.apply(p.amp * energy[p.band] / s, window_size)
+        } else {
+            0f64
+        });
+    }
+}
+
+//the band energy that gives an rms amplitude
+pub fn rms_energy(rms: f64, window_size: f64) -> f64 {
+    rms * rms * window_size * 0.04f64
+}
+
+//band energies from the peaks' noise, the inverse of distributing rms scaled band energy over
+//the peaks
+pub fn bands_from_noise(frames: &[Box<[Peak]>], window_size: f64) -> Vec<[f64; NOISE_BANDS]> {
+    frames
+        .iter()
+        .map(|f| {
+            let mut e = [0f64; NOISE_BANDS];
+            for p in f.iter() {
+                e[p.band] += rms_energy(p.noise_energy.unwrap_or(0f64), window_size);
+            }
+            e
+        })
+        .collect()
+}
+
+//a point of an imported partial, id identifies the partial from frame to frame
+#[derive(Clone, Copy, Debug)]
+pub struct TrackPoint {
+    pub id: u64,
+    pub freq: f64,
+    pub amp: f64,
+    pub phase: Option<f64>,
+    pub noise: Option<f64>,
+}
+
+//frame length of imported tracks when there is only one frame
+const TRACK_FRAME_SECONDS: f64 = 0.01f64;
+
+//the duration of frames at these times, assuming they are evenly spaced
+pub fn tracks_duration(times: &[f64]) -> f64 {
+    match times {
+        [] => 0f64,
+        [_] => TRACK_FRAME_SECONDS,
+        [first, .., last] => {
+            let hop = (last - first) / (times.len() - 1) as f64;
+            if hop > 0f64 && hop.is_finite() {
+                hop * times.len() as f64
+            } else {
+                TRACK_FRAME_SECONDS * times.len() as f64
+            }
+        }
+    }
+}
+
+//frames a track must be silent for before it is given to a new partial, so the synths can fade
+//out at the old partial's frequency and in at the new one's
+const TRACK_REUSE_GAP: usize = 2;
+
+//the hop, in samples, of frames spread evenly over dur seconds, with from_peaks the window is
+//twice this
+pub fn frame_samps(frames: usize, dur: f64, sr: f64) -> f64 {
+    if frames == 0 {
+        0f64
+    } else {
+        (dur * sr / frames as f64).round()
+    }
+}
+
+//limits on imported data, far beyond what analyses make, so bad input can't ask for huge buffers
+const IMPORT_MAX_FRAMES: usize = 1 << 20;
+const IMPORT_MAX_PARTIALS: usize = 1 << 14;
+const IMPORT_MAX_POINTS: usize = 1 << 24;
+
+//check the size of data before it is imported, the synths need two frames to interpolate between
+pub fn check_import_size(frames: usize, partials: usize) -> Result<(), String> {
+    if frames < 2 {
+        Err(format!("need at least 2 frames, found {}", frames))
+    } else if frames > IMPORT_MAX_FRAMES || partials > IMPORT_MAX_PARTIALS {
+        Err(format!(
+            "{} frames of {} partials is more than the limit of {} frames of {} partials",
+            frames, partials, IMPORT_MAX_FRAMES, IMPORT_MAX_PARTIALS
+        ))
+    } else if frames
+        .checked_mul(partials)
+        .map_or(true, |n| n > IMPORT_MAX_POINTS)
+    {
+        Err(format!(
+            "{} frames of {} partials is more than the limit of {} points",
+            frames, partials, IMPORT_MAX_POINTS
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn amp_sums(frames: &[Box<[Peak]>]) -> (Box<[f64]>, Box<[[f64; NOISE_BANDS]]>) {
+    let mut bands = vec![[0f64; NOISE_BANDS]; frames.len()];
+    for (f, b) in frames.iter().zip(bands.iter_mut()) {
+        for p in f.iter() {
+            b[p.band] += p.amp;
+        }
+    }
+    (
+        bands.iter().map(|b| b.iter().sum()).collect(),
+        bands.into_boxed_slice(),
+    )
+}
+
+fn partial_bands(frames: &[Box<[Peak]>], partials: usize) -> Box<[usize]> {
+    let mut sums = vec![[0f64; NOISE_BANDS]; partials];
+    for f in frames.iter() {
+        for (s, p) in sums.iter_mut().zip(f.iter()) {
+            s[p.band] += p.amp;
+        }
+    }
+    sums.iter()
+        .map(|s| {
+            s.iter()
+                .enumerate()
+                .fold((0, 0f64), |m, (b, a)| if *a > m.1 { (b, *a) } else { m })
+                .0
+        })
+        .collect()
+}
+
 impl AtsData {
+    //data from frames of peaks, the partial count comes from the header
+    pub fn new(
+        header: ATS_HEADER,
+        frames: Vec<Box<[Peak]>>,
+        noise: Option<Box<[[f64; NOISE_BANDS]]>>,
+        file_type: AtsDataType,
+        source: String,
+    ) -> Self {
+        let (sums, band_sums) = amp_sums(&frames);
+        let partial_bands = partial_bands(&frames, header.par as usize);
+        Self {
+            header,
+            frames: frames.into_boxed_slice(),
+            noise,
+            file_type,
+            source,
+            partials: header.par as usize,
+            amp_sums: sums,
+            band_amp_sums: band_sums,
+            partial_bands,
+            noise_scale: NoiseScale::Rms,
+        }
+    }
+
+    //data from frames of partials that come and go, evenly spaced over dur seconds. partials
+    //are given tracks as they are born and a track is reused once it has been silent long
+    //enough. silent points keep the frequency of the partial beside them. fails if the tracks
+    //would be too big or too short, see check_import_size
+    pub fn from_tracks(
+        frames: &[Vec<TrackPoint>],
+        dur: f64,
+        sr: f64,
+        source: String,
+    ) -> Result<Self, String> {
+        let mut active: std::collections::HashMap<u64, usize> = std::collections::HashMap::new();
+        let mut last_used: Vec<usize> = Vec::new();
+        let mut grid: Vec<Vec<(usize, TrackPoint)>> = Vec::with_capacity(frames.len());
+        for (i, points) in frames.iter().enumerate() {
+            active.retain(|id, _| points.iter().any(|p| p.id == *id));
+            let mut placed = Vec::with_capacity(points.len());
+            for p in points.iter() {
+                let track = match active.get(&p.id) {
+                    Some(t) => *t,
+                    None => {
+                        let free =
+                            (0..last_used.len()).find(|t| last_used[*t] + TRACK_REUSE_GAP < i);
+                        let t = free.unwrap_or_else(|| {
+                            last_used.push(0);
+                            last_used.len() - 1
+                        });
+                        active.insert(p.id, t);
+                        t
+                    }
+                };
+                last_used[track] = i;
+                placed.push((track, *p));
+            }
+            grid.push(placed);
+        }
+        let tracks = last_used.len();
+        check_import_size(frames.len(), tracks).map_err(|e| format!("{}: {}", source, e))?;
+        let with_phase = frames.iter().flatten().any(|p| p.phase.is_some());
+        let with_noise = frames.iter().flatten().any(|p| p.noise.is_some());
+        let mut peaks: Vec<Vec<Option<Peak>>> = vec![vec![None; tracks]; frames.len()];
+        for (i, placed) in grid.iter().enumerate() {
+            for (t, p) in placed.iter() {
+                peaks[i][*t] = Some(Peak {
+                    amp: p.amp,
+                    freq: p.freq,
+                    noise_energy: if with_noise {
+                        Some(p.noise.unwrap_or(0f64))
+                    } else {
+                        None
+                    },
+                    phase: if with_phase {
+                        Some(p.phase.unwrap_or(0f64))
+                    } else {
+                        None
+                    },
+                    band: band_of(p.freq),
+                });
+            }
+        }
+        //silent points right after a partial keep its frequency, the others take the frequency of
+        //the next partial in the track
+        let silent = |freq: f64| Peak {
+            amp: 0f64,
+            freq,
+            noise_energy: if with_noise { Some(0f64) } else { None },
+            phase: if with_phase { Some(0f64) } else { None },
+            band: band_of(freq),
+        };
+        let mut filled: Vec<Vec<Peak>> = vec![Vec::with_capacity(tracks); frames.len()];
+        for t in 0..tracks {
+            let mut next = vec![0f64; frames.len()];
+            let mut f = 0f64;
+            for i in (0..frames.len()).rev() {
+                if let Some(p) = &peaks[i][t] {
+                    f = p.freq;
+                }
+                next[i] = f;
+            }
+            //the frequency of the previous point and whether it was a partial's
+            let mut prev: Option<(f64, bool)> = None;
+            for i in 0..frames.len() {
+                let (p, placed) = match peaks[i][t].take() {
+                    Some(p) => (p, true),
+                    None => match prev {
+                        Some((f, true)) => (silent(f), false),
+                        _ if next[i] > 0f64 => (silent(next[i]), false),
+                        _ => (silent(prev.map_or(0f64, |p| p.0)), false),
+                    },
+                };
+                prev = Some((p.freq, placed));
+                filled[i].push(p);
+            }
+        }
+        let frames: Vec<Box<[Peak]>> = filled.into_iter().map(|f| f.into_boxed_slice()).collect();
+        let noise = if with_noise {
+            let ws = 2f64 * frame_samps(frames.len(), dur, sr);
+            Some(bands_from_noise(&frames, ws).into_boxed_slice())
+        } else {
+            None
+        };
+        let mut d = Self::from_peaks(frames, noise, dur, sr, source);
+        if with_phase {
+            d.file_type = if with_noise {
+                AtsDataType::AmpFreqPhaseNoise
+            } else {
+                AtsDataType::AmpFreqPhase
+            };
+            d.header.typ = d.file_type as usize as f64;
+        }
+        Ok(d)
+    }
+
+    //the seconds at the start of a frame
+    pub fn frame_time(&self, frame: usize) -> f64 {
+        if self.header.sr > 0f64 {
+            frame as f64 * self.header.fs / self.header.sr
+        } else {
+            0f64
+        }
+    }
+
+    //amplitude and frequency only data from frames of (freq, amp) per partial, evenly spaced over
+    //dur seconds
+    pub fn synthetic(frames: &[Vec<(f64, f64)>], dur: f64, sr: f64, source: String) -> Self {
+        let peaks: Vec<Box<[Peak]>> = frames
+            .iter()
+            .map(|f| {
+                f.iter()
+                    .map(|(freq, amp)| Peak {
+                        amp: *amp,
+                        freq: *freq,
+                        noise_energy: None,
+                        phase: None,
+                        band: band_of(*freq),
+                    })
+                    .collect()
+            })
+            .collect();
+        Self::from_peaks(peaks, None, dur, sr, source)
+    }
+
+    //data from peaks, evenly spaced over dur seconds, with a header to match. noise holds raw
+    //band energies, see window_size
+    pub fn from_peaks(
+        frames: Vec<Box<[Peak]>>,
+        noise: Option<Box<[[f64; NOISE_BANDS]]>>,
+        dur: f64,
+        sr: f64,
+        source: String,
+    ) -> Self {
+        let partials = frames.get(0).map_or(0, |f| f.len());
+        let max = |get: fn(&Peak) -> f64| {
+            frames
+                .iter()
+                .flat_map(|f| f.iter())
+                .map(get)
+                .fold(0f64, f64::max)
+        };
+        let file_type = if noise.is_some() {
+            AtsDataType::AmpFreqNoise
+        } else {
+            AtsDataType::AmpFreq
+        };
+        let header = ATS_HEADER {
+            mag: 123f64,
+            sr,
+            fs: frame_samps(frames.len(), dur, sr),
+            ws: 2f64 * frame_samps(frames.len(), dur, sr),
+            par: partials as f64,
+            fra: frames.len() as f64,
+            ma: max(|p| p.amp),
+            mf: max(|p| p.freq),
+            dur,
+            typ: file_type as usize as f64,
+        };
+        Self::new(header, frames, noise, file_type, source)
+    }
+
     pub fn partials(&self) -> usize {
         self.partials
     }
@@ -47,10 +445,403 @@ impl AtsData {
         self.noise.is_some()
     }
 
+    //the residual energy of a critical band in a frame, 0 without noise
+    pub fn band_energy(&self, frame: usize, band: usize) -> f64 {
+        self.noise.as_ref().map(|n| n[frame][band]).unwrap_or(0f64)
+    }
+
+    //band energy as an rms amplitude, the same scaling the synths use for noise
+    pub fn band_rms(&self, frame: usize, band: usize) -> f64 {
+        energy_rms(self.band_energy(frame, band), self.header.ws)
+    }
+
+    //the band of a partial in a frame, or the band it is loudest in over all frames
+    pub fn partial_band(&self, partial: usize, frame: Option<usize>) -> usize {
+        match frame {
+            Some(f) => self.frames[f][partial].band,
+            None => self.partial_bands[partial],
+        }
+    }
+
+    //reassign every peak to the band of its own frequency, frame by frame, and redistribute the
+    //noise energy of each band over the partials now in it
+    pub fn recompute_bands(&mut self) {
+        for (i, frame) in self.frames.iter_mut().enumerate() {
+            for p in frame.iter_mut() {
+                p.band = band_of(p.freq);
+            }
+            if let Some(noise) = &self.noise {
+                distribute_noise(frame, &noise[i], self.header.ws, self.noise_scale);
+            }
+        }
+        let (sums, band_sums) = amp_sums(&self.frames);
+        self.amp_sums = sums;
+        self.band_amp_sums = band_sums;
+        self.partial_bands = partial_bands(&self.frames, self.partials);
+    }
+
+    pub fn noise_scale(&self) -> NoiseScale {
+        self.noise_scale
+    }
+
+    //recompute the peaks' noise energy from the band energies with another scaling
+    pub fn set_noise_scale(&mut self, scale: NoiseScale) {
+        self.noise_scale = scale;
+        if let Some(noise) = &self.noise {
+            for (frame, energy) in self.frames.iter_mut().zip(noise.iter()) {
+                distribute_noise(frame, energy, self.header.ws, scale);
+            }
+        }
+    }
+
+    //sum of all partial amplitudes in a frame
+    pub fn amp_sum(&self, frame: usize) -> f64 {
+        self.amp_sums[frame]
+    }
+
+    //mean power of a frame, the sines plus the noise bands
+    pub fn frame_power(&self, frame: usize) -> f64 {
+        let sines: f64 = self.frames[frame]
+            .iter()
+            .map(|p| p.amp * p.amp / 2f64)
+            .sum();
+        let noise: f64 = (0..NOISE_BANDS)
+            .map(|b| self.band_rms(frame, b).powi(2))
+            .sum();
+        sines + noise
+    }
+
+    //summary features of a frame
+    pub fn frame_stats(&self, frame: usize) -> FrameStats {
+        let peaks = &self.frames[frame];
+        let amp = self.amp_sum(frame);
+        let (centroid, spread) = if amp > 0f64 {
+            let centroid = peaks.iter().map(|p| p.freq * p.amp).sum::<f64>() / amp;
+            let var = peaks
+                .iter()
+                .map(|p| (p.freq - centroid).powi(2) * p.amp)
+                .sum::<f64>()
+                / amp;
+            (centroid, var.sqrt())
+        } else {
+            (0f64, 0f64)
+        };
+        let sines: f64 = peaks.iter().map(|p| p.amp * p.amp / 2f64).sum();
+        let noise = self.frame_power(frame) - sines;
+        FrameStats {
+            amp,
+            centroid,
+            spread,
+            noise_ratio: if sines > 0f64 { noise / sines } else { 0f64 },
+        }
+    }
+
+    //sum of partial amplitudes in each critical band of a frame
+    pub fn band_amp_sums(&self, frame: usize) -> &[f64; NOISE_BANDS] {
+        &self.band_amp_sums[frame]
+    }
+
+    //amplitude weighted average band assignment for each partial, over all frames
+    pub fn band_map_average(&self) -> Vec<f64> {
+        let mut sums = vec![(0f64, 0f64); self.partials];
+        for f in self.frames.iter() {
+            for (s, p) in sums.iter_mut().zip(f.iter()) {
+                s.0 += p.band as f64 * p.amp;
+                s.1 += p.amp;
+            }
+        }
+        sums.iter()
+            .map(|s| if s.1 > 0f64 { s.0 / s.1 } else { 0f64 })
+            .collect()
+    }
+
+    //amplitude weighted pitch class histogram, starting at C, over the given frames
+    pub fn chroma(&self, frames: std::ops::Range<usize>) -> [f64; 12] {
+        let mut chroma = [0f64; 12];
+        for f in self.frames[frames].iter() {
+            for p in f.iter().filter(|p| p.amp > 0f64 && p.freq > 0f64) {
+                let note = (69f64 + 12f64 * (p.freq / 440f64).log2()).round() as isize;
+                chroma[note.rem_euclid(12) as usize] += p.amp;
+            }
+        }
+        chroma
+    }
+
+    //frames where the total amplitude rises by more than threshold times its mean, at least
+    //min_gap seconds apart, the first frame is always included. onsets are at least 2 frames
+    //apart and from the end, the fewest an ats file can hold, so every segment can be written
+    pub fn onsets(&self, threshold: f64, min_gap: f64) -> Vec<usize> {
+        let frames = self.frames.len();
+        if frames == 0 {
+            return Vec::new();
+        }
+        let mean = self.amp_sums.iter().sum::<f64>() / frames as f64;
+        let gap = std::cmp::max(
+            (min_gap * self.header.fra / self.header.dur).ceil() as usize,
+            2,
+        );
+        let mut onsets = vec![0];
+        for i in 1..frames.saturating_sub(1) {
+            let rise = self.amp_sums[i] - self.amp_sums[i - 1];
+            if rise > threshold * mean && i - onsets[onsets.len() - 1] >= gap {
+                onsets.push(i);
+            }
+        }
+        onsets
+    }
+
+    //a copy of a range of frames
+    pub fn slice(&self, frames: std::ops::Range<usize>) -> AtsData {
+        let mut header = self.header;
+        let len = frames.end - frames.start;
+        header.dur = self.header.dur * len as f64 / self.header.fra;
+        header.fra = len as f64;
+        Self {
+            header,
+            frames: self.frames[frames.clone()].to_vec().into_boxed_slice(),
+            noise: self
+                .noise
+                .as_ref()
+                .map(|n| n[frames.clone()].to_vec().into_boxed_slice()),
+            file_type: self.file_type,
+            source: format!("{}-{}", self.source, frames.start),
+            partials: self.partials,
+            amp_sums: self.amp_sums[frames.clone()].to_vec().into_boxed_slice(),
+            band_amp_sums: self.band_amp_sums[frames.clone()]
+                .to_vec()
+                .into_boxed_slice(),
+            partial_bands: partial_bands(&self.frames[frames], self.partials),
+            noise_scale: self.noise_scale,
+        }
+    }
+
+    //the header as it should be written for the current frames, edits can leave the counts,
+    //maxima and type stale
+    pub fn written_header(&self) -> ATS_HEADER {
+        let mut h = self.header;
+        let phase = match self.file_type {
+            AtsDataType::AmpFreqPhase | AtsDataType::AmpFreqPhaseNoise => true,
+            _ => false,
+        };
+        h.typ = match (phase, self.noise.is_some()) {
+            (false, false) => AtsDataType::AmpFreq,
+            (true, false) => AtsDataType::AmpFreqPhase,
+            (false, true) => AtsDataType::AmpFreqNoise,
+            (true, true) => AtsDataType::AmpFreqPhaseNoise,
+        } as i32 as f64;
+        h.par = self.partials as f64;
+        h.fra = self.frames.len() as f64;
+        h.ma = self
+            .frames
+            .iter()
+            .flat_map(|f| f.iter())
+            .fold(0f64, |m, p| m.max(p.amp));
+        h.mf = self
+            .frames
+            .iter()
+            .flat_map(|f| f.iter())
+            .fold(0f64, |m, p| m.max(p.freq));
+        h
+    }
+
+    //write in the ATS file format, noise is written from the band energies that were read
+    pub fn write<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut f = BufWriter::new(File::create(path)?);
+        let h = &self.written_header();
+        let phase = h.typ == AtsDataType::AmpFreqPhase as i32 as f64
+            || h.typ == AtsDataType::AmpFreqPhaseNoise as i32 as f64;
+        for v in &[
+            h.mag, h.sr, h.fs, h.ws, h.par, h.fra, h.ma, h.mf, h.dur, h.typ,
+        ] {
+            f.write_f64::<LittleEndian>(*v)?;
+        }
+        for (i, frame) in self.frames.iter().enumerate() {
+            f.write_f64::<LittleEndian>(i as f64 * h.fs / h.sr)?;
+            for p in frame.iter() {
+                f.write_f64::<LittleEndian>(p.amp)?;
+                f.write_f64::<LittleEndian>(p.freq)?;
+                if phase {
+                    f.write_f64::<LittleEndian>(p.phase.unwrap_or(0f64))?;
+                }
+            }
+            if let Some(noise) = &self.noise {
+                for e in noise[i].iter() {
+                    f.write_f64::<LittleEndian>(*e)?;
+                }
+            }
+        }
+        f.flush()
+    }
+
+    //a copy of self with every peak that has a counterpart in other, at the same time and within
+    //cents of the same frequency, silenced. the residual is shared between peaks by amplitude, so
+    //each band keeps the share of the peaks that survive. fails if other has no frames or
+    //partials to match
+    pub fn difference(&self, other: &AtsData, cents: f64) -> Result<AtsData, String> {
+        if other.frames.is_empty() || other.partials == 0 {
+            return Err(format!("{}: no partials to subtract", other.source));
+        }
+        let mut out = self.clone();
+        let ratio = (cents / 1200f64).exp2();
+        let frames_b = other.frames.len();
+        for (i, frame) in out.frames.iter_mut().enumerate() {
+            let t = i as f64 * self.header.dur / self.header.fra;
+            let j = (t * other.header.fra / other.header.dur).round() as usize;
+            let fb = &other.frames[std::cmp::min(j, frames_b - 1)];
+            let mut total = [0f64; NOISE_BANDS];
+            let mut kept = [0f64; NOISE_BANDS];
+            for p in frame.iter_mut() {
+                total[p.band] += p.amp;
+                let matched = fb
+                    .iter()
+                    .any(|q| q.amp > 0f64 && q.freq <= p.freq * ratio && q.freq * ratio >= p.freq);
+                if matched {
+                    p.amp = 0f64;
+                    if p.noise_energy.is_some() {
+                        p.noise_energy = Some(0f64);
+                    }
+                } else {
+                    kept[p.band] += p.amp;
+                }
+            }
+            if let Some(noise) = out.noise.as_mut() {
+                for (b, e) in noise[i].iter_mut().enumerate() {
+                    if total[b] > 0f64 {
+                        *e *= kept[b] / total[b];
+                    }
+                }
+            }
+        }
+        let (sums, band_sums) = amp_sums(&out.frames);
+        out.amp_sums = sums;
+        out.band_amp_sums = band_sums;
+        out.partial_bands = partial_bands(&out.frames, out.partials);
+        out.source = format!("{}-minus-{}", self.source, other.source);
+        Ok(out)
+    }
+
+    //the frames of self and other interpolated by t, 0 is self and 1 is other. both are
+    //stretched over the larger frame count and the duration is interpolated too. partials that
+    //only one of them has fade from silence at that partial's frequency
+    pub fn morph(&self, other: &AtsData, t: f64) -> AtsData {
+        let frames = std::cmp::max(self.frames.len(), other.frames.len());
+        let partials = std::cmp::max(self.partials, other.partials);
+        let with_noise = self.noise.is_some() || other.noise.is_some();
+        //the peaks and band energies of d at u, 0..1 through its frames, scaled by gain
+        let sample = |d: &AtsData, u: f64, gain: f64| {
+            if d.frames.is_empty() {
+                return (Vec::new(), [0f64; NOISE_BANDS]);
+            }
+            let (p0, p1, fract) = frame_pos(u * (d.frames.len() - 1) as f64, d.frames.len());
+            let peaks: Vec<Peak> = d.frames[p0]
+                .iter()
+                .zip(d.frames[p1].iter())
+                .map(|(a, b)| Peak {
+                    amp: lerp(a.amp, b.amp, fract) * gain,
+                    freq: lerp(a.freq, b.freq, fract),
+                    noise_energy: match (a.noise_energy, b.noise_energy) {
+                        (Some(a), Some(b)) => Some(lerp(a, b, fract) * gain),
+                        _ => None,
+                    },
+                    phase: None,
+                    band: 0,
+                })
+                .collect();
+            let mut bands = [0f64; NOISE_BANDS];
+            if let Some(noise) = &d.noise {
+                for (b, e) in bands.iter_mut().enumerate() {
+                    *e = lerp(noise[p0][b], noise[p1][b], fract) * gain;
+                }
+            }
+            (peaks, bands)
+        };
+        let mut peaks = Vec::with_capacity(frames);
+        let mut noise = Vec::with_capacity(frames);
+        for i in 0..frames {
+            let u = if frames > 1 {
+                i as f64 / (frames - 1) as f64
+            } else {
+                0f64
+            };
+            let (a, na) = sample(self, u, 1f64 - t);
+            let (b, nb) = sample(other, u, t);
+            let frame: Box<[Peak]> = (0..partials)
+                .map(|p| {
+                    let mut peak = match (a.get(p), b.get(p)) {
+                        (Some(a), Some(b)) => Peak {
+                            amp: a.amp + b.amp,
+                            freq: lerp(a.freq, b.freq, t),
+                            noise_energy: match (a.noise_energy, b.noise_energy) {
+                                (None, None) => None,
+                                (a, b) => Some(a.unwrap_or(0f64) + b.unwrap_or(0f64)),
+                            },
+                            ..a.clone()
+                        },
+                        (Some(p), None) | (None, Some(p)) => p.clone(),
+                        (None, None) => unreachable!(),
+                    };
+                    peak.band = band_of(peak.freq);
+                    peak
+                })
+                .collect();
+            peaks.push(frame);
+            let mut bands = [0f64; NOISE_BANDS];
+            for (b, e) in bands.iter_mut().enumerate() {
+                *e = na[b] + nb[b];
+            }
+            noise.push(bands);
+        }
+        let mut out = AtsData::from_peaks(
+            peaks,
+            if with_noise {
+                Some(noise.into_boxed_slice())
+            } else {
+                None
+            },
+            lerp(self.header.dur, other.header.dur, t),
+            self.header.sr,
+            format!("{}-morph-{}", self.source, other.source),
+        );
+        out.noise_scale = self.noise_scale;
+        out
+    }
+
+    //multiply the amplitude of each partial by its weight, partials past the end of weights
+    //are unchanged
+    pub fn scale_amps(&mut self, weights: &[f64]) {
+        for frame in self.frames.iter_mut() {
+            for (p, w) in frame.iter_mut().zip(weights.iter()) {
+                p.amp *= w;
+            }
+        }
+        let (sums, band_sums) = amp_sums(&self.frames);
+        self.amp_sums = sums;
+        self.band_amp_sums = band_sums;
+        self.partial_bands = partial_bands(&self.frames, self.partials);
+        self.header.ma = self
+            .frames
+            .iter()
+            .flat_map(|f| f.iter())
+            .map(|p| p.amp)
+            .fold(0f64, f64::max);
+    }
+
     pub fn try_read<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
-        let mut header: std::mem::MaybeUninit<ATS_HEADER> = std::mem::MaybeUninit::uninit();
         let source = path.as_ref().to_string_lossy().into_owned();
-        let mut file = File::open(path)?;
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        Self::from_reader(std::io::BufReader::new(file), len, source)
+    }
+
+    //parse ATS data held in memory, no filesystem access
+    pub fn from_bytes(bytes: &[u8], source: String) -> std::io::Result<Self> {
+        Self::from_reader(std::io::Cursor::new(bytes), bytes.len() as u64, source)
+    }
+
+    //len is the total size of the input, the header is checked against it before anything is
+    //allocated so that bad input can't ask for huge buffers
+    fn from_reader<R: Read + Seek>(mut file: R, len: u64, source: String) -> std::io::Result<Self> {
+        let mut header: std::mem::MaybeUninit<ATS_HEADER> = std::mem::MaybeUninit::uninit();
         unsafe {
             let s = slice::from_raw_parts_mut(
                 &mut header as *mut _ as *mut u8,
@@ -78,28 +869,48 @@ impl AtsData {
                 }
             };
 
+            //the synths interpolate between pairs of frames
+            if header.fra.is_nan() || header.fra < 2f64 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "ats data needs at least 2 frames, the header has {}",
+                        header.fra
+                    ),
+                ));
+            }
             let partials = header.par as usize;
+            let frame_count = header.fra as usize;
+            let peak_size = match file_type {
+                AtsDataType::AmpFreqPhase | AtsDataType::AmpFreqPhaseNoise => 3,
+                _ => 2,
+            };
+            let noise_size = match file_type {
+                AtsDataType::AmpFreqNoise | AtsDataType::AmpFreqPhaseNoise => NOISE_BANDS,
+                _ => 0,
+            };
+            let expected = (partials as u64)
+                .checked_mul(peak_size)
+                .and_then(|v| v.checked_add(1 + noise_size as u64))
+                .and_then(|v| v.checked_mul(frame_count as u64))
+                .and_then(|v| v.checked_mul(std::mem::size_of::<f64>() as u64))
+                .and_then(|v| v.checked_add(std::mem::size_of::<ATS_HEADER>() as u64));
+            if expected.map_or(true, |e| e > len) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "header partial and frame counts don't match the data size",
+                ));
+            }
             let mut frames = Vec::new();
             let mut noise = Vec::new();
-            let mut partialband: Vec<usize> = std::iter::repeat(0usize)
-                .take(header.par as usize)
-                .collect();
-
-            let bands: Vec<(usize, f64, f64)> = NOISE_BAND_EDGES[0..NOISE_BANDS]
-                .iter()
-                .zip(NOISE_BAND_EDGES[1..].iter())
-                .enumerate()
-                .map(|v| (v.0, *((v.1).0), *((v.1).1)))
-                .collect();
-            for _f in 0..header.fra as usize {
-                let mut band_amp_sum = [0f64; NOISE_BANDS];
 
+            for _f in 0..frame_count {
                 //skip frame time
                 file.seek(SeekFrom::Current(std::mem::size_of::<f64>() as i64))?;
 
                 let mut frame_peaks = Vec::new();
 
-                for p in 0..partials {
+                for _p in 0..partials {
                     let mut amp_freq = [0f64; 2];
                     file.read_f64_into::<LittleEndian>(&mut amp_freq)?;
                     let mut peak = Peak {
@@ -107,16 +918,11 @@ impl AtsData {
                         freq: amp_freq[1],
                         noise_energy: None,
                         phase: None,
+                        band: 0,
                     };
 
-                    //find noise band
-                    let band = bands
-                        .iter()
-                        .find(|&b| b.1 <= peak.freq && peak.freq < b.2)
-                        .unwrap_or(&(NOISE_BANDS - 1, 0f64, 0f64))
-                        .0;
-                    partialband[p] = band;
-                    band_amp_sum[band] += peak.amp;
+                    //find noise band, from this frame's frequency
+                    peak.band = band_of(peak.freq);
 
                     match file_type {
                         AtsDataType::AmpFreqPhase | AtsDataType::AmpFreqPhaseNoise => {
@@ -130,17 +936,7 @@ impl AtsData {
                     AtsDataType::AmpFreqNoise | AtsDataType::AmpFreqPhaseNoise => {
                         let mut nframe = [0f64; 25];
                         file.read_f64_into::<LittleEndian>(&mut nframe)?;
-
-                        //compute energy per parital
-                        for (p, b) in frame_peaks.iter_mut().zip(partialband.iter()) {
-                            let s = band_amp_sum[*b];
-                            let e = nframe[*b];
-                            p.noise_energy = Some(if s > 0f64 {
-                                energy_rms(p.amp * e / s, header.ws)
-                            } else {
-                                0f64
-                            });
-                        }
+                        distribute_noise(&mut frame_peaks, &nframe, header.ws, NoiseScale::Rms);
 
                         //store
                         noise.push(nframe);
@@ -164,14 +960,344 @@ impl AtsData {
             } else {
                 None
             };
-            Ok(Self {
-                header,
-                frames: frames.into_boxed_slice(),
-                noise,
-                file_type,
-                source,
-                partials,
+            Ok(Self::new(header, frames, noise, file_type, source))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //an ats header with a 1024 sample window, typ 1 is amp and freq, 3 adds noise
+    fn header_bytes(par: f64, fra: f64, typ: f64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for v in &[
+            123f64, 44100f64, 512f64, 1024f64, par, fra, 1f64, 1000f64, 1f64, typ,
+        ] {
+            bytes.write_f64::<LittleEndian>(*v).unwrap();
+        }
+        bytes
+    }
+
+    #[test]
+    fn from_bytes_rejects_bad_input() {
+        assert!(AtsData::from_bytes(&[], "empty".into()).is_err());
+        assert!(AtsData::from_bytes(&[0u8; 80], "zeros".into()).is_err());
+        //counts far larger than the data must fail before allocating
+        let bytes = header_bytes(1e15f64, 1e15f64, 1f64);
+        assert!(AtsData::from_bytes(&bytes, "huge".into()).is_err());
+        //a single frame has nothing to interpolate with
+        let mut bytes = header_bytes(1f64, 1f64, 1f64);
+        for v in &[0f64, 0.5f64, 440f64] {
+            bytes.write_f64::<LittleEndian>(*v).unwrap();
+        }
+        assert!(AtsData::from_bytes(&bytes, "single".into()).is_err());
+        assert!(AtsData::from_bytes(&header_bytes(1f64, 0f64, 1f64), "none".into()).is_err());
+    }
+
+    #[test]
+    fn from_bytes_reads_frames() {
+        let mut bytes = header_bytes(1f64, 2f64, 1f64);
+        for (t, amp, freq) in &[(0f64, 0.5f64, 440f64), (0.5f64, 0.25f64, 880f64)] {
+            bytes.write_f64::<LittleEndian>(*t).unwrap();
+            bytes.write_f64::<LittleEndian>(*amp).unwrap();
+            bytes.write_f64::<LittleEndian>(*freq).unwrap();
+        }
+        let d = AtsData::from_bytes(&bytes, "mem".into()).unwrap();
+        assert_eq!(d.partials(), 1);
+        assert_eq!(d.frames.len(), 2);
+        assert_eq!(d.frames[1][0].freq, 880f64);
+        assert!(AtsData::from_bytes(&bytes[..bytes.len() - 8], "short".into()).is_err());
+    }
+
+    //the residual distribution of the reference ATS implementation, band_energy_to_res in
+    //csound's ats opcodes, with the bands found fresh for every frame. partials above the top
+    //band edge get no noise
+    fn reference_noise(
+        frames: &[Vec<(f64, f64)>],
+        noise: &[[f64; NOISE_BANDS]],
+        ws: f64,
+    ) -> Vec<Vec<f64>> {
+        frames
+            .iter()
+            .zip(noise.iter())
+            .map(|(f, e)| {
+                let mut band_sum = [0f64; NOISE_BANDS];
+                let mut par_band = vec![None; f.len()];
+                for (j, (amp, freq)) in f.iter().enumerate() {
+                    for k in 0..NOISE_BANDS {
+                        if *freq >= NOISE_BAND_EDGES[k] && *freq < NOISE_BAND_EDGES[k + 1] {
+                            band_sum[k] += amp;
+                            par_band[j] = Some(k);
+                            break;
+                        }
+                    }
+                }
+                f.iter()
+                    .zip(par_band.iter())
+                    .map(|((amp, _), b)| match b {
+                        Some(b) if band_sum[*b] > 0f64 => {
+                            (amp / band_sum[*b] * e[*b] / (ws * 0.04f64)).sqrt()
+                        }
+                        _ => 0f64,
+                    })
+                    .collect()
             })
+            .collect()
+    }
+
+    #[test]
+    fn noise_matches_reference() {
+        //partial 1 moves from band 1 to band 8 in the second frame, partial 2 is above 20kHz
+        let frames = vec![
+            vec![(0.5f64, 150f64), (0.25f64, 180f64), (0.1f64, 21000f64)],
+            vec![(0.5f64, 150f64), (0.25f64, 1000f64), (0.1f64, 21000f64)],
+        ];
+        let mut energy = [0f64; NOISE_BANDS];
+        for (b, e) in energy.iter_mut().enumerate() {
+            *e = (b + 1) as f64;
         }
+        let noise = vec![energy; 2];
+
+        let mut bytes = header_bytes(3f64, 2f64, 3f64);
+        for (t, (f, e)) in frames.iter().zip(noise.iter()).enumerate() {
+            bytes.write_f64::<LittleEndian>(t as f64).unwrap();
+            for (amp, freq) in f.iter() {
+                bytes.write_f64::<LittleEndian>(*amp).unwrap();
+                bytes.write_f64::<LittleEndian>(*freq).unwrap();
+            }
+            for v in e.iter() {
+                bytes.write_f64::<LittleEndian>(*v).unwrap();
+            }
+        }
+
+        let d = AtsData::from_bytes(&bytes, "noise".into()).unwrap();
+        let expected = reference_noise(&frames, &noise, 1024f64);
+        for (f, e) in d.frames.iter().zip(expected.iter()) {
+            for (p, e) in f.iter().zip(e.iter()).take(2) {
+                assert!((p.noise_energy.unwrap() - e).abs() < 1e-12);
+            }
+        }
+        //unlike the reference, partials above the top edge share the last band's noise
+        assert_eq!(expected[0][2], 0f64);
+        assert_eq!(d.frames[0][2].band, NOISE_BANDS - 1);
+        let top = energy_rms(NOISE_BANDS as f64, 1024f64);
+        assert!((d.frames[1][2].noise_energy.unwrap() - top).abs() < 1e-12);
+        assert_eq!(d.frames[0][1].band, 1);
+        assert_eq!(d.frames[1][1].band, 8);
+    }
+
+    #[test]
+    fn noise_scale_modes() {
+        let frames = vec![vec![(440f64, 0.5f64), (450f64, 0.5f64)]; 2];
+        let mut energy = [0f64; NOISE_BANDS];
+        energy[band_of(440f64)] = 2f64;
+        let noise = vec![energy; 2].into_boxed_slice();
+        let peaks = AtsData::synthetic(&frames, 0.02f64, 44100f64, "scale".into())
+            .frames
+            .into_vec();
+        let mut d = AtsData::from_peaks(peaks, Some(noise), 0.02f64, 44100f64, "scale".into());
+        d.recompute_bands();
+        let rms = d.frames[0][0].noise_energy.unwrap();
+        assert!((rms - energy_rms(1f64, d.header.ws)).abs() < 1e-12);
+        d.set_noise_scale(NoiseScale::Power);
+        assert!((d.frames[1][0].noise_energy.unwrap() - rms * rms).abs() < 1e-12);
+        d.set_noise_scale(NoiseScale::Raw);
+        assert_eq!(d.frames[1][1].noise_energy, Some(1f64));
+        //survives rebanding
+        d.recompute_bands();
+        assert_eq!(d.frames[0][1].noise_energy, Some(1f64));
+    }
+
+    #[test]
+    fn write_regenerates_header() {
+        let frames = vec![vec![(440f64, 0.5f64), (880f64, 0.25f64)]; 4];
+        let mut d = AtsData::synthetic(&frames, 0.4f64, 44100f64, "write".into());
+        //an edit that leaves the header stale
+        d.frames[2][1].amp = 0.75f64;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("write.ats");
+        d.write(&path).unwrap();
+        let r = AtsData::try_read(&path).unwrap();
+        assert_eq!(r.partials(), 2);
+        assert_eq!(r.frames.len(), 4);
+        assert_eq!(r.header.ma, 0.75f64);
+        assert_eq!(r.header.mf, 880f64);
+        assert_eq!(r.header.typ, 1f64);
+        assert_eq!(r.frames[2][1].amp, 0.75f64);
+        assert_eq!(r.frames[3][0].freq, 440f64);
+    }
+
+    #[test]
+    fn synthetic_builds_header() {
+        let frames = vec![vec![(440f64, 0.5f64), (50f64, 0f64)]; 3];
+        let d = AtsData::synthetic(&frames, 0.3f64, 44100f64, "synth".into());
+        assert_eq!(d.partials(), 2);
+        assert_eq!(d.frames.len(), 3);
+        assert_eq!(d.header.ma, 0.5f64);
+        assert_eq!(d.frames[0][0].band, band_of(440f64));
+        assert_eq!(d.frames[0][1].band, 0);
+        assert_eq!(d.amp_sum(2), 0.5f64);
+    }
+
+    #[test]
+    fn difference_needs_partials() {
+        let frames = vec![vec![(440f64, 0.5f64), (660f64, 0.5f64)]; 2];
+        let a = AtsData::synthetic(&frames, 0.2f64, 44100f64, "a".into());
+        let b = AtsData::synthetic(&[vec![(441f64, 0.5f64)]], 0.2f64, 44100f64, "b".into());
+        let d = a.difference(&b, 10f64).unwrap();
+        assert_eq!(d.frames[1][0].amp, 0f64);
+        assert_eq!(d.frames[1][1].amp, 0.5f64);
+
+        let empty = AtsData::synthetic(&[], 0f64, 44100f64, "empty".into());
+        assert!(a.difference(&empty, 10f64).is_err());
+        let silent = AtsData::synthetic(&[vec![]], 0.1f64, 44100f64, "silent".into());
+        assert!(a.difference(&silent, 10f64).is_err());
+
+        //the residual of a band goes with the peaks that are removed from it
+        let peaks = AtsData::synthetic(
+            &[
+                vec![(440f64, 0.25f64), (450f64, 0.75f64)],
+                vec![(660f64, 1f64), (2000f64, 1f64)],
+            ],
+            0.2f64,
+            44100f64,
+            "a".into(),
+        )
+        .frames
+        .into_vec();
+        let mut energy = [0f64; NOISE_BANDS];
+        energy[band_of(440f64)] = 4f64;
+        energy[band_of(660f64)] = 2f64;
+        energy[band_of(2000f64)] = 1f64;
+        let a = AtsData::from_peaks(
+            peaks,
+            Some(vec![energy; 2].into_boxed_slice()),
+            0.2f64,
+            44100f64,
+            "a".into(),
+        );
+        let b = AtsData::synthetic(
+            &vec![vec![(450f64, 1f64), (660f64, 1f64)]; 2],
+            0.2f64,
+            44100f64,
+            "b".into(),
+        );
+        let d = a.difference(&b, 10f64).unwrap();
+        assert_eq!(d.band_energy(0, band_of(440f64)), 1f64);
+        assert_eq!(d.band_energy(1, band_of(660f64)), 0f64);
+        assert_eq!(d.band_energy(1, band_of(2000f64)), 1f64);
+    }
+
+    #[test]
+    fn onset_segments_round_trip() {
+        //under 10 frames a second the gap rounds to a single frame, with rises on every other
+        //frame and on the last one
+        let frames: Vec<Vec<(f64, f64)>> = (0..9)
+            .map(|i| vec![(440f64, if i % 2 == 0 { 1f64 } else { 0.1f64 })])
+            .collect();
+        let d = AtsData::synthetic(&frames, 1.8f64, 44100f64, "onsets".into());
+        let mut bounds = d.onsets(0.1f64, 0.1f64);
+        assert_eq!(bounds, vec![0, 2, 4, 6]);
+        bounds.push(d.frames.len());
+        let dir = tempfile::tempdir().unwrap();
+        for (i, w) in bounds.windows(2).enumerate() {
+            let path = dir.path().join(format!("segment-{}.ats", i));
+            d.slice(w[0]..w[1]).write(&path).unwrap();
+            let r = AtsData::try_read(&path).unwrap();
+            assert_eq!(r.frames.len(), w[1] - w[0]);
+        }
+    }
+
+    #[test]
+    fn morph_interpolates_frames() {
+        let a = AtsData::synthetic(
+            &[vec![(100f64, 1f64)], vec![(200f64, 1f64)]],
+            1f64,
+            44100f64,
+            "a".into(),
+        );
+        let b = AtsData::synthetic(
+            &vec![vec![(400f64, 0.5f64), (1000f64, 0.2f64)]; 3],
+            2f64,
+            44100f64,
+            "b".into(),
+        );
+        let m = a.morph(&b, 0f64);
+        assert_eq!((m.frames.len(), m.partials()), (3, 2));
+        assert_eq!(m.header.dur, 1f64);
+        //a is stretched over b's three frames, the partial a lacks is silent
+        assert_eq!(m.frames[1][0].freq, 150f64);
+        assert_eq!(m.frames[1][0].amp, 1f64);
+        assert_eq!(m.frames[1][1].amp, 0f64);
+        assert_eq!(m.frames[1][1].freq, 1000f64);
+
+        let m = a.morph(&b, 0.5f64);
+        assert_eq!(m.header.dur, 1.5f64);
+        assert_eq!(m.frames[2][0].freq, 300f64);
+        assert_eq!(m.frames[2][0].amp, 0.75f64);
+        assert_eq!(m.frames[2][1].amp, 0.1f64);
+        assert_eq!(m.frames[2][0].band, band_of(300f64));
+
+        let mut m = a.morph(&b, 1f64);
+        assert_eq!(m.frames[0][0].freq, 400f64);
+        assert_eq!(m.frames[0][0].amp, 0.5f64);
+        m.scale_amps(&[2f64]);
+        assert_eq!(m.frames[0][0].amp, 1f64);
+        assert_eq!(m.frames[0][1].amp, 0.2f64);
+        assert_eq!(m.header.ma, 1f64);
+        assert_eq!(m.amp_sum(0), 1.2f64);
+    }
+
+    #[test]
+    fn frame_stats_features() {
+        let frames = vec![
+            vec![(100f64, 0.5f64), (300f64, 0.5f64)],
+            vec![(100f64, 0f64), (300f64, 0f64)],
+        ];
+        let mut d = AtsData::synthetic(&frames, 0.2f64, 44100f64, "stats".into());
+        let s = d.frame_stats(0);
+        assert_eq!(s.amp, 1f64);
+        assert!((s.centroid - 200f64).abs() < 1e-9);
+        assert!((s.spread - 100f64).abs() < 1e-9);
+        assert_eq!(s.noise_ratio, 0f64);
+        assert_eq!(d.frame_stats(1).centroid, 0f64);
+
+        //noise with as much power as the sines
+        let ws = d.header.ws;
+        let mut noise = vec![[0f64; NOISE_BANDS]; 2];
+        noise[0][0] = rms_energy(0.25f64.sqrt(), ws);
+        d.noise = Some(noise.into_boxed_slice());
+        assert!((d.frame_stats(0).noise_ratio - 1f64).abs() < 1e-9);
+    }
+
+    #[test]
+    fn from_tracks_reuses_silent_tracks() {
+        let point = |id: u64, freq: f64| TrackPoint {
+            id,
+            freq,
+            amp: 0.5f64,
+            phase: None,
+            noise: None,
+        };
+        //partial 1 dies after the first frame, 2 is born too soon to take its track, 3 late enough
+        let frames = vec![
+            vec![point(0, 100f64), point(1, 200f64)],
+            vec![point(0, 100f64), point(2, 300f64)],
+            vec![point(0, 100f64)],
+            vec![point(0, 100f64)],
+            vec![point(0, 100f64), point(3, 400f64)],
+        ];
+        let d = AtsData::from_tracks(&frames, 0.5f64, 44100f64, "tracks".into()).unwrap();
+        assert_eq!(d.partials(), 3);
+        assert_eq!(d.frames[4][1].freq, 400f64);
+        assert_eq!(d.frames[4][1].amp, 0.5f64);
+        //fade out at the old frequency, then wait at the new one
+        assert_eq!(d.frames[1][1].freq, 200f64);
+        assert_eq!(d.frames[1][1].amp, 0f64);
+        assert_eq!(d.frames[2][1].freq, 400f64);
+        assert_eq!(d.frames[0][2].freq, 300f64);
+        assert!(!d.has_noise());
     }
 }
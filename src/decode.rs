@@ -0,0 +1,228 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+//decoded audio, one vec of samples per channel
+pub struct Sound {
+    pub sample_rate: u32,
+    pub channels: Vec<Vec<f32>>,
+}
+
+//how to turn a multichannel sound into the mono signal the analyzer expects
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChannelSelect {
+    Channel(usize),
+    Mixdown,
+}
+
+impl Sound {
+    pub fn to_mono(&self, select: ChannelSelect) -> Result<Vec<f32>, String> {
+        match select {
+            ChannelSelect::Channel(c) => self.channels.get(c).cloned().ok_or_else(|| {
+                format!(
+                    "channel {} out of range, the source has {}",
+                    c,
+                    self.channels.len()
+                )
+            }),
+            ChannelSelect::Mixdown => {
+                let len = self.channels.iter().map(|c| c.len()).max().unwrap_or(0);
+                let scale = 1f32 / std::cmp::max(self.channels.len(), 1) as f32;
+                let mut out = vec![0f32; len];
+                for c in self.channels.iter() {
+                    for (o, s) in out.iter_mut().zip(c.iter()) {
+                        *o += s * scale;
+                    }
+                }
+                Ok(out)
+            }
+        }
+    }
+}
+
+//decode the first audio track of any soundfile symphonia supports
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Sound, String> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| format!("failed to open {}: {}", path.display(), e))?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| format!("failed to probe {}: {}", path.display(), e))?;
+    let mut format = probed.format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| format!("no audio track in {}", path.display()))?;
+    let track_id = track.id;
+    let sample_rate = track
+        .codec_params
+        .sample_rate
+        .ok_or_else(|| format!("unknown sample rate for {}", path.display()))?;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| format!("failed to decode {}: {}", path.display(), e))?;
+
+    let mut channels: Vec<Vec<f32>> = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(p) => p,
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(format!("failed to read {}: {}", path.display(), e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(d) => d,
+            //skip corrupt packets
+            Err(Error::DecodeError(_)) => continue,
+            Err(e) => return Err(format!("failed to decode {}: {}", path.display(), e)),
+        };
+        let spec = *decoded.spec();
+        let count = spec.channels.count();
+        if channels.len() < count {
+            channels.resize(count, Vec::new());
+        }
+        let mut buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buf.copy_interleaved_ref(decoded);
+        for frame in buf.samples().chunks(count) {
+            for (c, s) in channels.iter_mut().zip(frame.iter()) {
+                c.push(*s);
+            }
+        }
+    }
+    if channels.is_empty() {
+        return Err(format!("no audio decoded from {}", path.display()));
+    }
+    Ok(Sound {
+        sample_rate,
+        channels,
+    })
+}
+
+//is this a 16 bit pcm wav, which the analyzer reads directly, anything else gets decoded first
+pub fn is_wav16<P: AsRef<Path>>(path: P) -> bool {
+    matches!(wav_format(path), Ok((1, 16)))
+}
+
+//the format tag and bits per sample from the fmt chunk of a wav
+fn wav_format<P: AsRef<Path>>(path: P) -> std::io::Result<(u16, u16)> {
+    let mut f = BufReader::new(File::open(path)?);
+    let mut id = [0u8; 4];
+    f.read_exact(&mut id)?;
+    let _ = f.read_u32::<LittleEndian>()?;
+    let mut wave = [0u8; 4];
+    f.read_exact(&mut wave)?;
+    if &id != b"RIFF" || &wave != b"WAVE" {
+        return Err(std::io::ErrorKind::InvalidData.into());
+    }
+    loop {
+        f.read_exact(&mut id)?;
+        let len = f.read_u32::<LittleEndian>()?;
+        if &id == b"fmt " {
+            let tag = f.read_u16::<LittleEndian>()?;
+            f.seek(SeekFrom::Current(12))?;
+            let bits = f.read_u16::<LittleEndian>()?;
+            return Ok((tag, bits));
+        }
+        //chunks are padded to an even length
+        f.seek(SeekFrom::Current((len + (len & 1)) as i64))?;
+    }
+}
+
+//write mono 16 bit pcm wav, the most widely readable format for the analyzer
+pub fn write_wav16<P: AsRef<Path>>(
+    path: P,
+    samples: &[f32],
+    sample_rate: u32,
+) -> std::io::Result<()> {
+    let mut f = BufWriter::new(File::create(path)?);
+    let data_len = (samples.len() * 2) as u32;
+    f.write_all(b"RIFF")?;
+    f.write_u32::<LittleEndian>(36 + data_len)?;
+    f.write_all(b"WAVEfmt ")?;
+    f.write_u32::<LittleEndian>(16)?;
+    f.write_u16::<LittleEndian>(1)?; //PCM
+    f.write_u16::<LittleEndian>(1)?;
+    f.write_u32::<LittleEndian>(sample_rate)?;
+    f.write_u32::<LittleEndian>(sample_rate * 2)?;
+    f.write_u16::<LittleEndian>(2)?;
+    f.write_u16::<LittleEndian>(16)?;
+    f.write_all(b"data")?;
+    f.write_u32::<LittleEndian>(data_len)?;
+    for s in samples {
+        let s = (s.clamp(-1f32, 1f32) * i16::MAX as f32).round() as i16;
+        f.write_i16::<LittleEndian>(s)?;
+    }
+    f.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stereo() -> Sound {
+        Sound {
+            sample_rate: 44100,
+            channels: vec![vec![1f32, 0.5f32, 0f32], vec![0f32, 0.5f32, -1f32]],
+        }
+    }
+
+    #[test]
+    fn select_and_mixdown() {
+        let s = stereo();
+        assert_eq!(
+            s.to_mono(ChannelSelect::Channel(1)).unwrap(),
+            vec![0f32, 0.5f32, -1f32]
+        );
+        assert!(s.to_mono(ChannelSelect::Channel(2)).is_err());
+        assert_eq!(
+            s.to_mono(ChannelSelect::Mixdown).unwrap(),
+            vec![0.5f32, 0.5f32, -0.5f32]
+        );
+    }
+
+    #[test]
+    fn wav16_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mono.wav");
+        write_wav16(&path, &[0f32, 0.5f32, -0.5f32, 1f32], 22050).unwrap();
+        let s = read(&path).unwrap();
+        assert_eq!(s.sample_rate, 22050);
+        assert_eq!(s.channels.len(), 1);
+        assert_eq!(s.channels[0].len(), 4);
+        assert!((s.channels[0][1] - 0.5f32).abs() < 1e-3);
+        assert!((s.channels[0][2] + 0.5f32).abs() < 1e-3);
+        assert!(is_wav16(&path));
+    }
+
+    #[test]
+    fn float_wav_needs_decoding() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("float.wav");
+        crate::render::write_wav(&path, &[0f32, 0.25f32, -0.25f32], 44100).unwrap();
+        assert!(!is_wav16(&path));
+        assert!(!is_wav16(dir.path().join("missing.wav")));
+        let s = read(&path).unwrap();
+        assert_eq!(s.channels[0].len(), 3);
+        assert!((s.channels[0][1] - 0.25f32).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::data::{NOISE_BANDS, NOISE_BAND_EDGES};
+use crate::synth::flush_denormal;
+
+//simple RBJ band pass
+#[derive(Default, Clone, Copy)]
+struct BandPass {
+    b0: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl BandPass {
+    fn new(center: f64, q: f64, sample_rate: f64) -> Self {
+        let w0 = 2f64 * std::f64::consts::PI * center / sample_rate;
+        let alpha = w0.sin() / (2f64 * q);
+        let a0 = 1f64 + alpha;
+        Self {
+            b0: alpha / a0,
+            b2: -alpha / a0,
+            a1: -2f64 * w0.cos() / a0,
+            a2: (1f64 - alpha) / a0,
+            ..Default::default()
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        //the feedback decays towards subnormals in silence
+        let y =
+            flush_denormal(self.b0 * x + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2);
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+fn coeff(ms: f64, sample_rate: f64) -> f64 {
+    if ms <= 0f64 {
+        0f64
+    } else {
+        (-1f64 / (ms * 0.001f64 * sample_rate)).exp()
+    }
+}
+
+//a critical band filterbank with envelope followers, used to duck resynthesis bands against a
+//live input
+pub struct Ducker {
+    filters: [BandPass; NOISE_BANDS],
+    env: [f64; NOISE_BANDS],
+    attack: f64,
+    release: f64,
+    sample_rate: f64,
+}
+
+impl Ducker {
+    pub fn new(sample_rate: f64) -> Self {
+        let mut filters = [BandPass::default(); NOISE_BANDS];
+        for (i, f) in filters.iter_mut().enumerate() {
+            let lo = NOISE_BAND_EDGES[i];
+            let hi = NOISE_BAND_EDGES[i + 1];
+            let center = ((lo + hi) * 0.5f64).min(sample_rate * 0.45f64);
+            *f = BandPass::new(center, center / (hi - lo), sample_rate);
+        }
+        Self {
+            filters,
+            env: [0f64; NOISE_BANDS],
+            attack: 0f64,
+            release: 0f64,
+            sample_rate,
+        }
+    }
+
+    //rebuild the filters for a new rate, the envelopes carry over and set_times recomputes the
+    //follower coefficients
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        let env = self.env;
+        *self = Self::new(sample_rate);
+        self.env = env;
+    }
+
+    pub fn set_times(&mut self, attack_ms: f64, release_ms: f64) {
+        self.attack = coeff(attack_ms, self.sample_rate);
+        self.release = coeff(release_ms, self.sample_rate);
+    }
+
+    pub fn process(&mut self, x: f64) {
+        for (f, e) in self.filters.iter_mut().zip(self.env.iter_mut()) {
+            let v = f.process(x).abs();
+            let c = if v > *e { self.attack } else { self.release };
+            *e = flush_denormal(v + c * (*e - v));
+        }
+    }
+
+    //gain to apply to content in the given band
+    pub fn gain(&self, band: usize, amount: f64) -> f64 {
+        (1f64 - amount * self.env[band].min(1f64)).max(0f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SR: f64 = 44100f64;
+
+    fn sine(freq: f64, i: usize) -> f64 {
+        (2f64 * std::f64::consts::PI * freq * i as f64 / SR).sin()
+    }
+
+    #[test]
+    fn sine_ducks_its_band() {
+        let mut d = Ducker::new(SR);
+        d.set_times(5f64, 50f64);
+        let band = 10;
+        let center = (NOISE_BAND_EDGES[band] + NOISE_BAND_EDGES[band + 1]) * 0.5f64;
+        for i in 0..(SR as usize / 4) {
+            d.process(0.5f64 * sine(center, i));
+        }
+        let reduction = |b: usize| 1f64 - d.gain(b, 1f64);
+        assert!(reduction(band) > 0.1f64, "{}", reduction(band));
+        for far in &[2, 20] {
+            assert!(reduction(band) > 4f64 * reduction(*far), "{} {}", band, far);
+        }
+        //amount scales the reduction
+        assert_eq!(d.gain(band, 0f64), 1f64);
+        assert!((1f64 - d.gain(band, 0.5f64) - reduction(band) * 0.5f64).abs() < 1e-9);
+    }
+
+    #[test]
+    fn zero_times_track_instantly() {
+        let mut d = Ducker::new(SR);
+        d.set_times(0f64, 0f64);
+        let mut filters = d.filters;
+        for i in 0..1000 {
+            //a burst then silence, so both the attack and the release are followed
+            let x = if i < 500 { sine(1000f64, i) } else { 0f64 };
+            d.process(x);
+            for (f, e) in filters.iter_mut().zip(d.env.iter()) {
+                assert_eq!(*e, f.process(x).abs());
+            }
+        }
+    }
+}
@@ -1,2 +1,141 @@
+pub mod about;
+pub mod bands;
 pub mod data;
+pub mod seq;
 pub mod sinnoi;
+pub mod spat;
+
+use pd_ext::atom::Atom;
+use pd_ext::symbol::Symbol;
+use std::convert::TryInto;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+
+lazy_static::lazy_static! {
+    static ref ALL: Symbol = "all".try_into().unwrap();
+}
+
+//parse an `<index|all> <float>` argument list, as used by per partial parameter messages
+pub fn index_value_args(list: &[Atom], len: usize) -> Result<(Option<usize>, f64), String> {
+    let (index, vals) = index_values_args(list, len, 1)?;
+    Ok((index, vals[0]))
+}
+
+//parse an `<index|all> <float>...` argument list with count floats
+pub fn index_values_args(
+    list: &[Atom],
+    len: usize,
+    count: usize,
+) -> Result<(Option<usize>, Vec<f64>), String> {
+    if list.len() != count + 1 {
+        return Err(format!("expected {} arguments", count + 1));
+    }
+    let mut index = None;
+    if let Some(i) = list[0].get_int() {
+        let i = i as usize;
+        if i > len {
+            return Err(format!("partial index {} out of range", i));
+        }
+        index = Some(i);
+    } else {
+        let s = list[0].get_symbol();
+        if s.is_none() || s.unwrap() != *ALL {
+            return Err("expect first arg to be an index or 'all'".into());
+        }
+    }
+    let vals: Vec<f64> = list[1..]
+        .iter()
+        .filter_map(|a| a.get_float())
+        .map(|v| v as f64)
+        .collect();
+    if vals.len() != count {
+        return Err("expect the remaining args to be floats".into());
+    }
+    Ok((index, vals))
+}
+
+//pd's limit on path lengths
+const MAXPDSTRING: usize = 1000;
+
+//find a relative path in the canvas directory or pd's search path, like soundfiler does, names
+//that aren't found, like new files to write, are placed in the canvas directory
+pub fn resolve_path(canvas: *mut pd_sys::t_canvas, name: &str) -> String {
+    if canvas.is_null() || std::path::Path::new(name).is_absolute() {
+        return name.into();
+    }
+    let cname = match CString::new(name) {
+        Ok(n) => n,
+        Err(_) => return name.into(),
+    };
+    let mut dir = vec![0 as c_char; MAXPDSTRING];
+    let mut base: *mut c_char = std::ptr::null_mut();
+    unsafe {
+        let fd = pd_sys::canvas_open(
+            canvas,
+            cname.as_ptr(),
+            "\0".as_ptr() as *const c_char,
+            dir.as_mut_ptr(),
+            &mut base,
+            MAXPDSTRING as std::os::raw::c_uint,
+            1,
+        );
+        if fd < 0 || base.is_null() {
+            let dir = pd_sys::canvas_getdir(canvas);
+            if dir.is_null() {
+                return name.into();
+            }
+            return std::path::Path::new(&*CStr::from_ptr((*dir).s_name).to_string_lossy())
+                .join(name)
+                .to_string_lossy()
+                .into_owned();
+        }
+        pd_sys::sys_close(fd);
+        let dir = CStr::from_ptr(dir.as_ptr()).to_string_lossy().into_owned();
+        let base = CStr::from_ptr(base).to_string_lossy().into_owned();
+        std::path::Path::new(&dir)
+            .join(base)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+//the values of the named garray
+pub fn read_array(name: Symbol) -> Result<Vec<f64>, String> {
+    unsafe {
+        let array =
+            pd_sys::pd_findbyclass(name.inner(), pd_sys::garray_class) as *mut pd_sys::t_garray;
+        if array.is_null() {
+            return Err(format!("array {} not found", name));
+        }
+        let mut size: std::os::raw::c_int = 0;
+        let mut vec: *mut pd_sys::t_word = std::ptr::null_mut();
+        if pd_sys::garray_getfloatwords(array, &mut size, &mut vec) == 0 {
+            return Err(format!("bad template for array {}", name));
+        }
+        let words = std::slice::from_raw_parts(vec, size as usize);
+        Ok(words.iter().map(|w| w.w_float as f64).collect())
+    }
+}
+
+//resize the named garray to fit values and fill it
+pub fn write_array(name: Symbol, values: &[f64]) -> Result<(), String> {
+    unsafe {
+        let array =
+            pd_sys::pd_findbyclass(name.inner(), pd_sys::garray_class) as *mut pd_sys::t_garray;
+        if array.is_null() {
+            return Err(format!("array {} not found", name));
+        }
+        pd_sys::garray_resize_long(array, values.len() as std::os::raw::c_long);
+        let mut size: std::os::raw::c_int = 0;
+        let mut vec: *mut pd_sys::t_word = std::ptr::null_mut();
+        if pd_sys::garray_getfloatwords(array, &mut size, &mut vec) == 0 {
+            return Err(format!("bad template for array {}", name));
+        }
+        let words = std::slice::from_raw_parts_mut(vec, size as usize);
+        for (w, v) in words.iter_mut().zip(values.iter()) {
+            w.w_float = *v as pd_sys::t_float;
+        }
+        pd_sys::garray_redraw(array);
+    }
+    Ok(())
+}
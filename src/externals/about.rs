@@ -0,0 +1,57 @@
+use pd_ext::builder::ControlExternalBuilder;
+use pd_ext::external::ControlExternal;
+use pd_ext::outlet::{OutletSend, OutletType};
+use pd_ext::symbol::Symbol;
+use pd_ext_macros::external;
+use std::convert::TryInto;
+
+lazy_static::lazy_static! {
+    static ref VERSION: Symbol = "version".try_into().unwrap();
+    static ref FILE_TYPES: Symbol = "file_types".try_into().unwrap();
+    static ref FEATURES: Symbol = "features".try_into().unwrap();
+    static ref CACHE: Symbol = "cache".try_into().unwrap();
+    static ref CRATE_VERSION: Symbol = env!("CARGO_PKG_VERSION").try_into().unwrap();
+    static ref CACHE_BACKEND: Symbol = "weak_map".try_into().unwrap();
+    static ref OFFLINE: Symbol = "offline".try_into().unwrap();
+    static ref SIMD: Symbol = "simd".try_into().unwrap();
+}
+
+//the version and capabilities of this build, shared by the version selector of each external
+pub fn send_version(outlet: &dyn OutletSend) {
+    outlet.send_anything(*VERSION, &[(*CRATE_VERSION).into()]);
+    outlet.send_anything(
+        *FILE_TYPES,
+        &[1f32.into(), 2f32.into(), 3f32.into(), 4f32.into()],
+    );
+    //the cargo features this was built with
+    let mut features = Vec::new();
+    if cfg!(feature = "offline") {
+        features.push((*OFFLINE).into());
+    }
+    if cfg!(feature = "simd") {
+        features.push((*SIMD).into());
+    }
+    outlet.send_anything(*FEATURES, &features);
+    outlet.send_anything(*CACHE, &[(*CACHE_BACKEND).into()]);
+}
+
+external! {
+    #[name="ats/about"]
+    pub struct AtsAboutExternal {
+        outlet: Box<dyn OutletSend>,
+    }
+
+    impl ControlExternal for AtsAboutExternal {
+        fn new(builder: &mut dyn ControlExternalBuilder<Self>) -> Result<Self, String> {
+            let outlet = builder.new_message_outlet(OutletType::AnyThing);
+            Ok(Self { outlet })
+        }
+    }
+
+    impl AtsAboutExternal {
+        #[bang]
+        pub fn bang(&mut self) {
+            send_version(self.outlet.as_ref());
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::data::{AtsData, NOISE_BANDS};
+use crate::synth::{frame_pos, lerp, ArcAtomic, LOAD_ORDERING, STORE_ORDERING};
+use atomic::Atomic;
+use pd_ext::builder::SignalProcessorExternalBuilder;
+use pd_ext::external::{SignalProcessor, SignalProcessorExternal};
+use pd_ext::post::PdPost;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Arc;
+
+const DSP_RECV_MAX: usize = 32;
+
+//outputs the residual energy of each critical band at the position of the input, one outlet per
+//band, for driving filter banks or custom residual synthesis
+pub struct AtsBandsProcessor {
+    current: Option<Arc<AtsData>>,
+    data_recv: Receiver<Option<Arc<AtsData>>>,
+    rms: ArcAtomic<bool>,
+}
+
+impl SignalProcessor for AtsBandsProcessor {
+    fn process(
+        &mut self,
+        _frames: usize,
+        inputs: &[&mut [pd_sys::t_float]],
+        outputs: &mut [&mut [pd_sys::t_float]],
+    ) {
+        let mut cnt = 0;
+        while let Ok(c) = self.data_recv.try_recv() {
+            self.current = c;
+            cnt = cnt + 1;
+            if cnt > DSP_RECV_MAX {
+                break;
+            }
+        }
+
+        let c = match &self.current {
+            Some(c) if c.has_noise() && c.frames.len() > 1 => c,
+            _ => {
+                for out in outputs.iter_mut() {
+                    for v in out.iter_mut() {
+                        *v = 0f32.into();
+                    }
+                }
+                return;
+            }
+        };
+
+        let rms = self.rms.load(LOAD_ORDERING);
+        let pmul = c.header.fra / c.header.dur;
+        let frames = c.frames.len();
+        for i in 0..outputs[0].len() {
+            let pos = (inputs[0][i] as f64) * pmul;
+            let in_range = pos >= 0f64 && pos < frames.saturating_sub(1) as f64;
+            let (p0, p1, fract) = frame_pos(pos, frames);
+            for (b, out) in outputs.iter_mut().enumerate() {
+                out[i] = if in_range {
+                    if rms {
+                        lerp(c.band_rms(p0, b), c.band_rms(p1, b), fract)
+                    } else {
+                        lerp(c.band_energy(p0, b), c.band_energy(p1, b), fract)
+                    }
+                } else {
+                    0f64
+                } as pd_sys::t_float;
+            }
+        }
+    }
+}
+
+pd_ext_macros::external! {
+    #[name = "ats/bands~"]
+    pub struct AtsBandsExternal {
+        data_send: SyncSender<Option<Arc<AtsData>>>,
+        rms: ArcAtomic<bool>,
+        post: Box<dyn PdPost>,
+    }
+
+    impl AtsBandsExternal {
+        #[sel]
+        pub fn ats_data(&mut self, key: pd_ext::symbol::Symbol) {
+            let d = crate::cache::get(key);
+            if let Some(d) = &d {
+                if !d.has_noise() {
+                    self.post.post_error(format!("{} has no noise, bands will be silent", d.source));
+                }
+            }
+            let _ = self.data_send.try_send(d);
+        }
+
+        //the same as ats_data, there is no crossfade to skip, so ats/seq can drive this too
+        #[sel]
+        pub fn ats_target(&mut self, key: pd_ext::symbol::Symbol) {
+            self.ats_data(key);
+        }
+
+        #[sel]
+        pub fn clear(&mut self) {
+            let _ = self.data_send.send(None);
+        }
+
+        //output rms amplitudes, the default, or the raw band energies
+        #[sel]
+        pub fn rms(&mut self, v: pd_sys::t_float) {
+            self.rms.store(v != 0f32, STORE_ORDERING);
+        }
+    }
+
+    impl SignalProcessorExternal for AtsBandsExternal {
+        fn new(builder: &mut dyn SignalProcessorExternalBuilder<Self>) -> Result<(Self, Box<dyn SignalProcessor>), String> {
+            let (data_send, data_recv) = sync_channel(32);
+            for _ in 0..NOISE_BANDS {
+                builder.new_signal_outlet();
+            }
+            let rms = Arc::new(Atomic::new(true));
+            Ok(
+                (
+                    Self {
+                        data_send,
+                        rms: rms.clone(),
+                        post: builder.poster()
+                    },
+                    Box::new(AtsBandsProcessor {
+                        current: None,
+                        data_recv,
+                        rms,
+                    })
+                )
+            )
+        }
+    }
+}
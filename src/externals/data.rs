@@ -1,5 +1,6 @@
 use ats_sys::ANARGS;
 use clap::{App, AppSettings, Arg};
+use pd_ext::atom::Atom;
 use pd_ext::builder::ControlExternalBuilder;
 use pd_ext::clock::Clock;
 use pd_ext::external::ControlExternal;
@@ -16,7 +17,8 @@ use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Arc;
 use std::sync::Mutex;
 
-use crate::data::AtsData;
+use crate::data::{AtsData, NoiseScale};
+use crate::decode::ChannelSelect;
 
 external! {
     #[name="ats/data"]
@@ -27,8 +29,34 @@ external! {
         clock: Clock,
         post: Box<dyn PdPost>,
         waiting: AtomicUsize,
-        file_send: Sender<Result<(AtsData, String), String>>,
-        file_recv: Receiver<Result<(AtsData, String), String>>,
+        //milliseconds between checks for finished jobs
+        poll_ms: f64,
+        //named times, in seconds, into the data
+        markers: Vec<(Symbol, f64)>,
+        //data being built by synthesize_data and set_point
+        draft: Option<Draft>,
+        //noise energy scaling for loaded data
+        noise_scale: NoiseScale,
+        //the residual soundfile of the last analysis, removed when replaced
+        residual: Option<tempfile::TempPath>,
+        //bumped by cancel, jobs started before it give up
+        generation: Arc<AtomicUsize>,
+        //bumped by flush, analyses that haven't started give up
+        flushes: Arc<AtomicUsize>,
+        //analyses waiting on another analysis to finish
+        anal_queued: Arc<AtomicUsize>,
+        //id given to the next job
+        next_job: usize,
+        //the data of the last export_segments or open_bundle, the cache only holds weak
+        //references so the segment keys live as long as this
+        segments: Vec<(Symbol, Arc<AtsData>)>,
+        //relative paths are resolved against this canvas and the search path
+        canvas: *mut pd_sys::t_canvas,
+        //bound for open_dialog callbacks
+        obj: *mut pd_sys::t_object,
+        dialog_recv: Symbol,
+        file_send: Sender<JobDone>,
+        file_recv: Receiver<JobDone>,
     }
 
     impl ControlExternal for AtsDataExternal {
@@ -38,6 +66,11 @@ external! {
             let clock = Clock::new(builder.obj(), atsdataexternal_poll_done_trampoline);
             let (file_send, file_recv) = channel();
             let post = builder.poster();
+            let obj = builder.obj();
+            let dialog_recv: Symbol = format!("ats_data_{:x}", obj as usize).as_str().try_into().map_err(stringify)?;
+            unsafe {
+                pd_sys::pd_bind(obj as *mut pd_sys::t_pd, dialog_recv.inner());
+            }
             Ok(Self {
                 data_outlet,
                 info_outlet,
@@ -45,6 +78,19 @@ external! {
                 clock,
                 post,
                 waiting: Default::default(),
+                poll_ms: POLL_MS,
+                markers: Vec::new(),
+                draft: None,
+                noise_scale: NoiseScale::Rms,
+                residual: None,
+                generation: Arc::new(AtomicUsize::new(0)),
+                flushes: Arc::new(AtomicUsize::new(0)),
+                anal_queued: Arc::new(AtomicUsize::new(0)),
+                next_job: 0,
+                segments: Vec::new(),
+                canvas: unsafe { pd_sys::canvas_getcurrent() },
+                obj,
+                dialog_recv,
                 file_send,
                 file_recv
             })
@@ -75,9 +121,870 @@ external! {
             }
         }
 
+        #[sel]
+        pub fn dump_band_map(&mut self, args: &[Atom]) {
+            if let Some((_, f)) = &self.current {
+                let avg = args.len() > 0 && args[0].get_symbol() == Some(*AVERAGE);
+                if avg {
+                    let bands: Vec<Atom> = f.band_map_average().into_iter().map(|b| b.into()).collect();
+                    self.info_outlet.send_anything(*BAND_MAP_AVERAGE, &bands);
+                } else {
+                    for (i, frame) in f.frames.iter().enumerate() {
+                        let mut v: Vec<Atom> = Vec::with_capacity(frame.len() + 1);
+                        v.push((i as f64).into());
+                        v.extend(frame.iter().map(|p| Atom::from(p.band as f64)));
+                        self.info_outlet.send_anything(*BAND_MAP, &v);
+                    }
+                }
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //output `peak <frame> <partial> <freq> <amp> <noise_energy> <phase>` for every partial of
+        //a frame
+        #[sel]
+        pub fn get_frame(&mut self, frame: pd_sys::t_float) {
+            if let Some((_, f)) = &self.current {
+                if let Some((frame, peaks)) = to_index(frame).and_then(|i| f.frames.get(i).map(|p| (i, p))) {
+                    for (i, p) in peaks.iter().enumerate() {
+                        send_peak(self.info_outlet.as_ref(), frame, i, p);
+                    }
+                } else {
+                    self.post.post_error(format!("frame index {} out of range", frame));
+                }
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //output a `peak` for every frame of a partial
+        #[sel]
+        pub fn get_partial(&mut self, partial: pd_sys::t_float) {
+            if let Some((_, f)) = &self.current {
+                if let Some(partial) = to_index(partial).filter(|p| *p < f.partials()) {
+                    for (i, frame) in f.frames.iter().enumerate() {
+                        send_peak(self.info_outlet.as_ref(), i, partial, &frame[partial]);
+                    }
+                } else {
+                    self.post.post_error(format!("partial index {} out of range", partial));
+                }
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //output the `peak` of a partial in a frame
+        #[sel]
+        pub fn get_point(&mut self, frame: pd_sys::t_float, partial: pd_sys::t_float) {
+            if let Some((_, f)) = &self.current {
+                let point = to_index(frame).zip(to_index(partial));
+                match point.and_then(|(i, p)| f.frames.get(i).and_then(|peaks| peaks.get(p)).map(|peak| (i, p, peak))) {
+                    Some((i, p, peak)) => send_peak(self.info_outlet.as_ref(), i, p, peak),
+                    None => self.post.post_error(format!("no point at frame {} partial {}", frame, partial)),
+                }
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //output a `peak` for frames and partials in the inclusive ranges, every frame_step frame
+        //of every partial_step partial, the steps default to 1
+        #[sel]
+        pub fn dump_range(&mut self, args: &[Atom]) {
+            if let Some((_, f)) = &self.current {
+                let ints: Vec<Option<usize>> = args.iter().map(|a| a.get_float().and_then(to_index)).collect();
+                let (bounds, steps) = ints.split_at(std::cmp::min(ints.len(), 4));
+                let steps = match steps {
+                    [] => Some((1, 1)),
+                    [Some(nf), Some(np)] if *nf > 0 && *np > 0 => Some((*nf, *np)),
+                    _ => None,
+                };
+                match (bounds, steps) {
+                    ([Some(fs), Some(fe), Some(ps), Some(pe)], Some(steps)) if fs <= fe && ps <= pe => {
+                        let frames = *fs..std::cmp::min(fe.saturating_add(1), f.frames.len());
+                        let partials = *ps..std::cmp::min(pe.saturating_add(1), f.partials());
+                        self.dump_points(f, frames, partials, steps);
+                    }
+                    _ => self.post.post_error(
+                        "dump_range expects <frame_start> <frame_end> <partial_start> <partial_end> [frame_step partial_step]".into(),
+                    ),
+                }
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //output a `peak` for every n_frames frame of every n_partials partial
+        #[sel]
+        pub fn dump_every(&mut self, n_frames: pd_sys::t_float, n_partials: pd_sys::t_float) {
+            let steps = match (to_index(n_frames), to_index(n_partials)) {
+                (Some(nf), Some(np)) if nf > 0 && np > 0 => (nf, np),
+                _ => {
+                    self.post.post_error("dump_every expects steps of at least 1".into());
+                    return;
+                }
+            };
+            if let Some((_, f)) = &self.current {
+                self.dump_points(f, 0..f.frames.len(), 0..f.partials(), steps);
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        fn dump_points(
+            &self,
+            f: &AtsData,
+            frames: std::ops::Range<usize>,
+            partials: std::ops::Range<usize>,
+            (nf, np): (usize, usize),
+        ) {
+            for i in frames.step_by(nf) {
+                for p in partials.clone().step_by(np) {
+                    send_peak(self.info_outlet.as_ref(), i, p, &f.frames[i][p]);
+                }
+            }
+        }
+
+        //output `band_of <partial> <band>`, the band in the given frame or, without one, the
+        //band the partial is loudest in
+        #[sel]
+        pub fn band_of(&mut self, args: &[Atom]) {
+            if let Some((_, f)) = &self.current {
+                let ints: Vec<Option<i32>> = args.iter().map(|a| a.get_int()).collect();
+                let (partial, frame) = match ints.as_slice() {
+                    [Some(p)] => (*p, None),
+                    [Some(p), Some(i)] if *i >= 0 && (*i as usize) < f.frames.len() => (*p, Some(*i as usize)),
+                    _ => {
+                        self.post.post_error("band_of expects <partial> [frame] within the data".into());
+                        return;
+                    }
+                };
+                if partial < 0 || partial as usize >= f.partials() {
+                    self.post.post_error(format!("partial index {} out of range", partial));
+                    return;
+                }
+                let band = f.partial_band(partial as usize, frame);
+                self.info_outlet.send_anything(*BAND_OF, &[(partial as f64).into(), (band as f64).into()]);
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //how band energies scale to partial noise energy, for loaded data and the current data,
+        //which is cached anew if it changes
+        #[sel]
+        pub fn noise_scale(&mut self, scale: Symbol) {
+            let scale = if scale == *RMS {
+                NoiseScale::Rms
+            } else if scale == *POWER {
+                NoiseScale::Power
+            } else if scale == *RAW {
+                NoiseScale::Raw
+            } else {
+                self.post.post_error("noise_scale expects rms, power or raw".into());
+                return;
+            };
+            self.noise_scale = scale;
+            if let Some((_, f)) = &self.current {
+                if f.noise_scale() != scale {
+                    let mut f = (**f).clone();
+                    f.set_noise_scale(scale);
+                    self.set_current(f);
+                }
+            }
+        }
+
+        //reassign partials to bands from their frequency in every frame, caching the result as
+        //new current data
+        #[sel]
+        pub fn recompute_bands(&mut self) {
+            if let Some((_, f)) = &self.current {
+                let mut f = (**f).clone();
+                f.recompute_bands();
+                self.set_current(f);
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //pitch class energy over the whole data, a single frame or a range of frames
+        #[sel]
+        pub fn chroma(&mut self, args: &[Atom]) {
+            if let Some((_, f)) = &self.current {
+                let frames = f.frames.len();
+                let range = match (args.get(0).map(|a| a.get_int()), args.get(1).map(|a| a.get_int())) {
+                    (None, None) => Some(0..frames),
+                    (Some(Some(i)), None) if i >= 0 => Some(i as usize..i as usize + 1),
+                    (Some(Some(s)), Some(Some(e))) if s >= 0 && e >= s => Some(s as usize..e as usize + 1),
+                    _ => None,
+                };
+                match range {
+                    Some(r) if args.len() <= 2 && r.end <= frames => {
+                        let chroma: Vec<Atom> = f.chroma(r).iter().map(|v| Atom::from(*v)).collect();
+                        self.info_outlet.send_anything(*CHROMA, &chroma);
+                    }
+                    _ => self.post.post_error("chroma expects [frame] or [start end] within the data".into()),
+                }
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //render the partials of a that don't have a match in b to a wav file, the current data
+        //is left alone
+        #[sel]
+        pub fn render_diff(&mut self, args: &[Atom]) {
+            let syms: Vec<Symbol> = args.iter().take(3).filter_map(|a| a.get_symbol()).collect();
+            let cents = if args.len() > 3 { args[3].get_float() } else { Some(DIFF_CENTS) };
+            if syms.len() != 3 || cents.is_none() || args.len() > 4 {
+                self.post.post_error("render_diff expects <key_a> <key_b> <path> [cents]".into());
+                return;
+            }
+            let (a, b) = match (crate::cache::get(syms[0]), crate::cache::get(syms[1])) {
+                (Some(a), Some(b)) => (a, b),
+                _ => {
+                    self.post.post_error("render_diff: data key not found".into());
+                    return;
+                }
+            };
+            let path = crate::externals::resolve_path(self.canvas, syms[2].as_ref());
+            let cents = cents.unwrap() as f64;
+            self.queue_write(move || {
+                let diff = a.difference(&b, cents)?;
+                let sr = diff.header.sr;
+                let samples = crate::render::render(&diff, sr);
+                crate::render::write_wav(&path, &samples, sr as u32).map_err(stringify)?;
+                Ok(path)
+            });
+        }
+
+        //draw the partials of the current data, time by frequency with amplitude as intensity
+        #[sel]
+        pub fn export_png(&mut self, args: &[Atom]) {
+            let dims: Vec<Option<i32>> = args.iter().skip(1).map(|a| a.get_int()).collect();
+            let size = match dims.as_slice() {
+                [] => Some((PNG_WIDTH, PNG_HEIGHT)),
+                [Some(w)] if *w > 0 => Some((*w as u32, PNG_HEIGHT)),
+                [Some(w), Some(h)] if *w > 0 && *h > 0 => Some((*w as u32, *h as u32)),
+                _ => None,
+            };
+            let (path, (width, height)) = match (args.get(0).and_then(|a| a.get_symbol()), size) {
+                (Some(p), Some(s)) => (p, s),
+                _ => {
+                    self.post.post_error("export_png expects <path> [width] [height]".into());
+                    return;
+                }
+            };
+            if let Some((_, f)) = &self.current {
+                let f = f.clone();
+                let path = crate::externals::resolve_path(self.canvas, path.as_ref());
+                self.queue_write(move || {
+                    crate::render::write_png(&path, &f, width, height).map_err(stringify)?;
+                    Ok(path)
+                });
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //the per frame total amplitude of the current data, scaled to 0..1, into an array
+        #[sel]
+        pub fn env_to_array(&mut self, name: Symbol) {
+            if let Some((_, f)) = &self.current {
+                let env: Vec<f64> = (0..f.frames.len()).map(|i| f.amp_sum(i)).collect();
+                let max = env.iter().cloned().fold(0f64, f64::max);
+                let env: Vec<f64> = if max > 0f64 {
+                    env.iter().map(|v| v / max).collect()
+                } else {
+                    env
+                };
+                if let Err(e) = crate::externals::write_array(name, &env) {
+                    self.post.post_error(e);
+                }
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //write a trajectory of the current data into an array, one value per frame:
+        //`freq|amp|noise|phase <partial>`, `band <band>` for a noise band's energy or `energy`
+        //for the total power of each frame
+        #[sel]
+        pub fn to_array(&mut self, args: &[Atom]) {
+            if let Some((_, f)) = &self.current {
+                let name = args.get(0).and_then(|a| a.get_symbol());
+                let what = args.get(1).and_then(|a| a.get_symbol());
+                let index = args.get(2).and_then(|a| a.get_float()).and_then(to_index);
+                let (name, what) = match (name, what) {
+                    (Some(n), Some(w)) => (n, w),
+                    _ => {
+                        self.post.post_error("to_array expects <arrayname> <what> [index]".into());
+                        return;
+                    }
+                };
+                let frames = 0..f.frames.len();
+                let values: Result<Vec<f64>, String> = if what == *ENERGY {
+                    Ok(frames.map(|i| f.frame_power(i)).collect())
+                } else if what == *BAND {
+                    match index.filter(|b| *b < crate::data::NOISE_BANDS) {
+                        Some(b) => Ok(frames.map(|i| f.band_energy(i, b)).collect()),
+                        None => Err("to_array band expects a band index".into()),
+                    }
+                } else {
+                    let get: Option<fn(&crate::data::Peak) -> f64> = if what == *FREQ {
+                        Some(|p| p.freq)
+                    } else if what == *AMP {
+                        Some(|p| p.amp)
+                    } else if what == *NOISE {
+                        Some(|p| p.noise_energy.unwrap_or(0f64))
+                    } else if what == *PHASE {
+                        Some(|p| p.phase.unwrap_or(0f64))
+                    } else {
+                        None
+                    };
+                    match (get, index.filter(|p| *p < f.partials())) {
+                        (Some(get), Some(p)) => Ok(f.frames.iter().map(|frame| get(&frame[p])).collect()),
+                        (Some(_), None) => Err(format!("to_array {} expects a partial index", what)),
+                        (None, _) => Err(format!("to_array doesn't know {}, expects freq, amp, noise, phase, band or energy", what)),
+                    }
+                };
+                if let Err(e) = values.and_then(|v| crate::externals::write_array(name, &v)) {
+                    self.post.post_error(e);
+                }
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //output `stats <frame> <amp> <centroid> <spread> <noise_ratio>` for every frame or, given a
+        //prefix, write them into the arrays <prefix>-amp, <prefix>-centroid, <prefix>-spread
+        //and <prefix>-noise_ratio
+        #[sel]
+        pub fn stats(&mut self, args: &[Atom]) {
+            if let Some((_, f)) = &self.current {
+                let stats: Vec<crate::data::FrameStats> = (0..f.frames.len()).map(|i| f.frame_stats(i)).collect();
+                match args.get(0).and_then(|a| a.get_symbol()) {
+                    Some(prefix) => {
+                        let arrays: [(&str, fn(&crate::data::FrameStats) -> f64); 4] = [
+                            ("amp", |s| s.amp),
+                            ("centroid", |s| s.centroid),
+                            ("spread", |s| s.spread),
+                            ("noise_ratio", |s| s.noise_ratio),
+                        ];
+                        for (suffix, get) in arrays.iter() {
+                            let values: Vec<f64> = stats.iter().map(get).collect();
+                            let name = format!("{}-{}", prefix, suffix);
+                            let res = name
+                                .as_str()
+                                .try_into()
+                                .map_err(stringify)
+                                .and_then(|name| crate::externals::write_array(name, &values));
+                            if let Err(e) = res {
+                                self.post.post_error(e);
+                            }
+                        }
+                    }
+                    None => {
+                        for (i, s) in stats.iter().enumerate() {
+                            self.info_outlet.send_anything(
+                                *STATS,
+                                &[(i as f64).into(), s.amp.into(), s.centroid.into(), s.spread.into(), s.noise_ratio.into()],
+                            );
+                        }
+                    }
+                }
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //output `f0 <frame> <freq> <confidence>` for every frame, searching min..max hz, or write
+        //the f0 trajectory into arrays: `estimate_f0 [min max] [f0_array] [confidence_array]`,
+        //without a confidence array the confidences are output as `f0_confidence <values...>`
+        #[sel]
+        pub fn estimate_f0(&mut self, args: &[Atom]) {
+            if let Some((_, f)) = &self.current {
+                let range: Vec<f64> = args.iter().filter_map(|a| a.get_float()).map(|v| v as f64).collect();
+                let arrays: Vec<Symbol> = args.iter().filter_map(|a| a.get_symbol()).collect();
+                let (min, max) = match range.as_slice() {
+                    [] if arrays.len() <= 2 => (F0_MIN_HZ, F0_MAX_HZ),
+                    [min, max] if *min > 0f64 && min < max && arrays.len() <= 2 => (*min, *max),
+                    _ => {
+                        self.post.post_error("estimate_f0 expects [min max] [f0_array] [confidence_array]".into());
+                        return;
+                    }
+                };
+                let track = crate::pitch::track(f, min, max);
+                if let Some(array) = arrays.first() {
+                    let values: Vec<f64> = track.iter().map(|f0| f0.freq).collect();
+                    if let Err(e) = crate::externals::write_array(*array, &values) {
+                        self.post.post_error(e);
+                        return;
+                    }
+                    let confidence: Vec<f64> = track.iter().map(|f0| f0.confidence).collect();
+                    match arrays.get(1) {
+                        Some(array) => {
+                            if let Err(e) = crate::externals::write_array(*array, &confidence) {
+                                self.post.post_error(e);
+                            }
+                        }
+                        None => {
+                            let v: Vec<Atom> = confidence.into_iter().map(Atom::from).collect();
+                            self.info_outlet.send_anything(*F0_CONFIDENCE, &v);
+                        }
+                    }
+                } else {
+                    for (i, f0) in track.iter().enumerate() {
+                        self.info_outlet.send_anything(*F0, &[(i as f64).into(), f0.freq.into(), f0.confidence.into()]);
+                    }
+                }
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //output `inharmonicity <frame> <f0> <inharmonicity>` and `deviation <frame> <cents>...`,
+        //each partial's distance from its nearest harmonic, for every frame. the f0 comes from
+        //an array with one value per frame or is estimated
+        #[sel]
+        pub fn harmonicity(&mut self, args: &[Atom]) {
+            if let Some((_, f)) = &self.current {
+                let f0: Vec<f64> = match args.get(0).and_then(|a| a.get_symbol()) {
+                    Some(array) => match crate::externals::read_array(array) {
+                        Ok(v) if v.len() >= f.frames.len() => v,
+                        Ok(_) => {
+                            self.post.post_error(format!("array {} has fewer values than frames", array));
+                            return;
+                        }
+                        Err(e) => {
+                            self.post.post_error(e);
+                            return;
+                        }
+                    },
+                    None => crate::pitch::track(f, F0_MIN_HZ, F0_MAX_HZ).iter().map(|f0| f0.freq).collect(),
+                };
+                for (i, (frame, f0)) in f.frames.iter().zip(f0.iter()).enumerate() {
+                    let inharm = crate::pitch::inharmonicity(frame, *f0);
+                    self.info_outlet.send_anything(*INHARMONICITY, &[(i as f64).into(), (*f0).into(), inharm.into()]);
+                    let mut v: Vec<Atom> = Vec::with_capacity(frame.len() + 1);
+                    v.push((i as f64).into());
+                    v.extend(frame.iter().map(|p| Atom::from(crate::pitch::harmonic_deviation(p.freq, *f0))));
+                    self.info_outlet.send_anything(*DEVIATION, &v);
+                }
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //write the current data as csv, see text::write_csv for the layout
+        #[sel]
+        pub fn export_csv(&mut self, path: Symbol) {
+            if let Some((_, f)) = &self.current {
+                let f = f.clone();
+                let path = crate::externals::resolve_path(self.canvas, path.as_ref());
+                self.queue_write(move || {
+                    crate::text::write_csv(&f, &path).map_err(stringify)?;
+                    Ok(path)
+                });
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //write the current data as json, see text::write_json for the layout
+        #[sel]
+        pub fn export_json(&mut self, path: Symbol) {
+            if let Some((_, f)) = &self.current {
+                let f = f.clone();
+                let path = crate::externals::resolve_path(self.canvas, path.as_ref());
+                self.queue_write(move || {
+                    crate::text::write_json(&f, &path).map_err(stringify)?;
+                    Ok(path)
+                });
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //load data from a csv table of `frame,partial,freq,amp[,noise]` rows, see text::read_csv
+        #[sel]
+        pub fn import_csv(&mut self, path: Symbol) {
+            let path = crate::externals::resolve_path(self.canvas, path.as_ref());
+            self.queue_job(move || crate::text::read_csv(&path).map(|d| (d, path)));
+        }
+
+        //load data from json, see text::read_json
+        #[sel]
+        pub fn import_json(&mut self, path: Symbol) {
+            let path = crate::externals::resolve_path(self.canvas, path.as_ref());
+            self.queue_job(move || crate::text::read_json(&path).map(|d| (d, path)));
+        }
+
+        //load 1TRC or 1HRM sdif tracks
+        #[sel]
+        pub fn open_sdif(&mut self, path: Symbol) {
+            let path = crate::externals::resolve_path(self.canvas, path.as_ref());
+            self.queue_job(move || crate::sdif::read(&path).map(|d| (d, path)));
+        }
+
+        //load loris bandwidth-enhanced partials, the bandwidth becomes noise
+        #[sel]
+        pub fn open_loris(&mut self, path: Symbol) {
+            let path = crate::externals::resolve_path(self.canvas, path.as_ref());
+            self.queue_job(move || crate::sdif::read_loris(&path).map(|d| (d, path)));
+        }
+
+        //load a spear par-text-frame-format export
+        #[sel]
+        pub fn open_spear(&mut self, path: Symbol) {
+            let path = crate::externals::resolve_path(self.canvas, path.as_ref());
+            self.queue_job(move || crate::text::read_spear(&path).map(|d| (d, path)));
+        }
+
+        //write the current data as 1TRC sdif tracks, without noise
+        #[sel]
+        pub fn export_sdif(&mut self, path: Symbol) {
+            if let Some((_, f)) = &self.current {
+                let f = f.clone();
+                let path = crate::externals::resolve_path(self.canvas, path.as_ref());
+                self.queue_write(move || {
+                    crate::sdif::write(&f, &path).map_err(stringify)?;
+                    Ok(path)
+                });
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //write the current data to an ats file, analyses otherwise only live in a tempdir
+        #[sel]
+        pub fn save(&mut self, path: Symbol) {
+            if let Some((_, f)) = &self.current {
+                let f = f.clone();
+                let path = crate::externals::resolve_path(self.canvas, path.as_ref());
+                self.queue_write(move || {
+                    f.write(&path).map_err(stringify)?;
+                    Ok(path)
+                });
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //copy the residual soundfile of the last analysis to path
+        #[sel]
+        pub fn save_residual(&mut self, path: Symbol) {
+            if let Some(r) = &self.residual {
+                let from = r.to_path_buf();
+                let path = crate::externals::resolve_path(self.canvas, path.as_ref());
+                self.queue_write(move || {
+                    std::fs::copy(&from, &path).map_err(stringify)?;
+                    Ok(path)
+                });
+            } else {
+                self.post.post_error("no residual from an analysis".into());
+            }
+        }
+
+        //split the current data at its onsets, writing each segment to an ats file in dir and
+        //outputting `segment <index> <key>` for each
+        #[sel]
+        pub fn export_segments(&mut self, dir: Symbol) {
+            if let Some((_, f)) = &self.current {
+                let f = f.clone();
+                let dir = crate::externals::resolve_path(self.canvas, dir.as_ref());
+                self.spawn_job(move || JobDone::Segments(write_segments(&f, Path::new(&dir))));
+            } else {
+                self.post.post_error("no data loaded".into());
+            }
+        }
+
+        //release the data of the last export_segments, their keys stop resolving
+        #[sel]
+        pub fn segments_clear(&mut self) {
+            self.segments.clear();
+        }
+
+        //write the current data and the segments of this object to ats files in dir along with
+        //a manifest.txt of the files, the current key and the markers
+        #[sel]
+        pub fn export_bundle(&mut self, dir: Symbol) {
+            let mut entries: Vec<(String, Arc<AtsData>)> = Vec::new();
+            for (k, d) in self.current.iter().chain(self.segments.iter()) {
+                if !entries.iter().any(|e| e.0 == k.to_string()) {
+                    entries.push((k.to_string(), d.clone()));
+                }
+            }
+            let current: Option<String> = self.current.as_ref().map(|(k, _)| (*k).into());
+            let markers: Vec<(String, f64)> = self.markers.iter().map(|(n, s)| ((*n).into(), *s)).collect();
+            let dir = crate::externals::resolve_path(self.canvas, dir.as_ref());
+            self.queue_write(move || write_bundle(Path::new(&dir), &entries, current, &markers));
+        }
+
+        //read a bundle written by export_bundle back in, its current data becomes the current
+        //data, the rest are output as segments and its markers replace the markers
+        #[sel]
+        pub fn open_bundle(&mut self, dir: Symbol) {
+            let dir: String = dir.into();
+            let manifest = Path::new(&dir).join("manifest.txt");
+            let manifest = crate::externals::resolve_path(self.canvas, &manifest.to_string_lossy());
+            self.spawn_job(move || JobDone::Bundle(read_bundle(Path::new(&manifest))));
+        }
+
+        //add or move a marker
+        #[sel]
+        pub fn marker_add(&mut self, args: &[Atom]) {
+            match (args.get(0).and_then(|a| a.get_symbol()), args.get(1).and_then(|a| a.get_float())) {
+                (Some(name), Some(secs)) if args.len() == 2 && secs >= 0f32 => {
+                    let secs = secs as f64;
+                    if let Some(m) = self.markers.iter_mut().find(|m| m.0 == name) {
+                        m.1 = secs;
+                    } else {
+                        self.markers.push((name, secs));
+                    }
+                }
+                _ => self.post.post_error("marker_add expects <name> <seconds>".into()),
+            }
+        }
+
+        #[sel]
+        pub fn marker_remove(&mut self, name: Symbol) {
+            self.markers.retain(|m| m.0 != name);
+        }
+
+        #[sel]
+        pub fn marker_clear(&mut self) {
+            self.markers.clear();
+        }
+
+        //output `marker <name> <seconds>` for each marker in time order
+        #[sel]
+        pub fn marker_list(&mut self) {
+            let mut markers = self.markers.clone();
+            markers.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+            for (name, secs) in markers {
+                self.info_outlet.send_anything(*MARKER, &[name.into(), secs.into()]);
+            }
+        }
+
+        //output `goto <seconds> <normalized>` for a marker, normalized is relative to the
+        //duration of the current data
+        #[sel]
+        pub fn marker_goto(&mut self, name: Symbol) {
+            if let Some(secs) = self.marker(name) {
+                let norm = match &self.current {
+                    Some((_, f)) if f.header.dur > 0f64 => (secs / f.header.dur).min(1f64),
+                    _ => 0f64,
+                };
+                self.info_outlet.send_anything(*GOTO, &[secs.into(), norm.into()]);
+            }
+        }
+
+        #[sel]
+        pub fn cue(&mut self, name: Symbol) {
+            self.marker_goto(name);
+        }
+
+        //output `loop <start> <end>`, in seconds, between two markers for driving a line or
+        //phasor into sinnoi~
+        #[sel]
+        pub fn loop_between(&mut self, args: &[Atom]) {
+            match (args.get(0).and_then(|a| a.get_symbol()), args.get(1).and_then(|a| a.get_symbol())) {
+                (Some(a), Some(b)) if args.len() == 2 => {
+                    if let (Some(a), Some(b)) = (self.marker(a), self.marker(b)) {
+                        self.info_outlet.send_anything(*LOOP, &[a.min(b).into(), a.max(b).into()]);
+                    }
+                }
+                _ => self.post.post_error("loop_between expects <marker_a> <marker_b>".into()),
+            }
+        }
+
+        //start building data from messages, every point starts at 0 Hz and silent
+        #[sel]
+        pub fn synthesize_data(&mut self, args: &[Atom]) {
+            let ints: Vec<Option<i32>> = args.iter().skip(1).take(2).map(|a| a.get_int()).collect();
+            let dur = match args.get(3).map(|a| a.get_float()) {
+                None => Some(None),
+                Some(Some(d)) if d > 0f32 => Some(Some(d as f64)),
+                _ => None,
+            };
+            match (args.get(0).and_then(|a| a.get_symbol()), ints.as_slice(), dur) {
+                (Some(name), [Some(frames), Some(partials)], Some(dur))
+                    if *frames > 1 && *partials > 0 && args.len() <= 4 =>
+                {
+                    let (frames, partials) = (*frames as usize, *partials as usize);
+                    self.draft = Some(Draft {
+                        name: name.into(),
+                        frames: vec![vec![(0f64, 0f64); partials]; frames],
+                        dur: dur.unwrap_or(frames as f64 * DRAFT_FRAME_SECONDS),
+                    });
+                }
+                _ => self.post.post_error("synthesize_data expects <name> <frames> <partials> [dur seconds]".into()),
+            }
+        }
+
+        #[sel]
+        pub fn set_point(&mut self, args: &[Atom]) {
+            let draft = match self.draft.as_mut() {
+                Some(d) => d,
+                None => {
+                    self.post.post_error("set_point needs synthesize_data first".into());
+                    return;
+                }
+            };
+            let v: Vec<f64> = args.iter().filter_map(|a| a.get_float()).map(|v| v as f64).collect();
+            match v.as_slice() {
+                [f, p, freq, amp] if args.len() == 4 && *f >= 0f64 && *p >= 0f64 => {
+                    match draft.frames.get_mut(*f as usize).and_then(|f| f.get_mut(*p as usize)) {
+                        Some(point) => *point = (freq.max(0f64), amp.max(0f64)),
+                        None => self.post.post_error("set_point frame or partial out of range".into()),
+                    }
+                }
+                _ => self.post.post_error("set_point expects <frame> <partial> <freq> <amp>".into()),
+            }
+        }
+
+        //build the data, cache it and make it current
+        #[sel]
+        pub fn synthesize_done(&mut self) {
+            if let Some(d) = self.draft.take() {
+                let sr = pd_ext::pd::sample_rate() as f64;
+                self.set_current(AtsData::synthetic(&d.frames, d.dur, sr, d.name));
+            } else {
+                self.post.post_error("synthesize_done needs synthesize_data first".into());
+            }
+        }
+
+        //generate test data:
+        //  harmonic <f0> <count> <decay> [dur seconds]
+        //  noiseburst <decay seconds> [dur seconds]
+        //  sweep <from hz> <to hz> [dur seconds]
+        #[sel]
+        pub fn gen(&mut self, args: &[Atom]) {
+            let kind = args.get(0).and_then(|a| a.get_symbol());
+            let v: Vec<f64> = args.iter().skip(1).filter_map(|a| a.get_float()).map(|v| v as f64).collect();
+            if kind.is_none() || v.len() + 1 != args.len() {
+                self.post.post_error("gen expects harmonic, noiseburst or sweep followed by floats".into());
+                return;
+            }
+            let kind = kind.unwrap();
+            let sr = pd_ext::pd::sample_rate() as f64;
+            //an optional trailing duration after the required args
+            let dur = |required: usize| match v.len() - required {
+                0 => Some(GEN_DUR_SECONDS),
+                1 if v[required] > 0f64 => Some(v[required]),
+                _ => None,
+            };
+            let data = if kind == *HARMONIC && (v.len() == 3 || v.len() == 4) {
+                dur(3).filter(|_| v[0] > 0f64 && v[1] >= 1f64).map(|d| crate::gen::harmonic(v[0], v[1] as usize, v[2], d, sr))
+            } else if kind == *NOISEBURST && (v.len() == 1 || v.len() == 2) {
+                dur(1).map(|d| crate::gen::noiseburst(v[0], d, sr))
+            } else if kind == *SWEEP && (v.len() == 2 || v.len() == 3) {
+                dur(2).filter(|_| v[0] > 0f64 && v[1] > 0f64).map(|d| crate::gen::sweep(v[0], v[1], d, sr))
+            } else {
+                None
+            };
+            match data {
+                Some(d) => self.set_current(d),
+                None => self.post.post_error(format!(
+                    "bad arguments for gen {}, expected harmonic <f0> <count> <decay>, noiseburst <decay> or sweep <from> <to>, each with an optional duration",
+                    kind
+                )),
+            }
+        }
+
+        //cache data, make it current and output it
+        fn set_current(&mut self, f: AtsData) {
+            let f = Arc::new(f);
+            let k = crate::cache::insert(f.clone());
+            self.current = Some((k, f));
+            self.bang();
+        }
+
+        fn marker(&self, name: Symbol) -> Option<f64> {
+            let m = self.markers.iter().find(|m| m.0 == name).map(|m| m.1);
+            if m.is_none() {
+                self.post.post_error(format!("no marker named {}", name));
+            }
+            m
+        }
+
         #[sel]
         pub fn open(&mut self, filename: Symbol) {
-            self.queue_job(move || AtsData::try_read(filename).map_err(stringify).map(|r| (r, filename.into())))
+            let filename = crate::externals::resolve_path(self.canvas, filename.as_ref());
+            let cancel = self.cancel_token();
+            self.queue_job(move || {
+                cancel.check()?;
+                let r = AtsData::try_read(&filename).map_err(stringify)?;
+                cancel.check()?;
+                Ok((r, filename))
+            })
+        }
+
+        //pick an ats file to open with pd's file browser
+        #[sel]
+        pub fn open_dialog(&mut self) {
+            let dir = unsafe {
+                let dir = pd_sys::canvas_getdir(self.canvas);
+                if dir.is_null() {
+                    CString::default()
+                } else {
+                    std::ffi::CStr::from_ptr((*dir).s_name).to_owned()
+                }
+            };
+            unsafe {
+                pd_sys::sys_vgui(
+                    "pdtk_openpanel {%s} {%s}\n\0".as_ptr() as *const std::os::raw::c_char,
+                    (*self.dialog_recv.inner()).s_name,
+                    dir.as_ptr(),
+                );
+            }
+        }
+
+        //the file chosen with open_dialog
+        #[sel]
+        pub fn callback(&mut self, filename: Symbol) {
+            self.open(filename);
+        }
+
+        //abandon the reads and analyses that are in flight, an analysis that has already started
+        //runs to completion but its result is discarded, the current data stays
+        #[sel]
+        pub fn cancel(&mut self) {
+            self.generation.fetch_add(1, Ordering::SeqCst);
+        }
+
+        //output `jobs <pending> <analyses waiting to start>`
+        #[sel]
+        pub fn jobs(&mut self) {
+            let pending = self.waiting.load(Ordering::SeqCst) as f64;
+            let queued = self.anal_queued.load(Ordering::SeqCst) as f64;
+            self.info_outlet.send_anything(*JOBS, &[pending.into(), queued.into()]);
+        }
+
+        //discard the analyses that are waiting on another analysis to finish
+        #[sel]
+        pub fn flush(&mut self) {
+            self.flushes.fetch_add(1, Ordering::SeqCst);
+        }
+
+        #[sel]
+        pub fn open_url(&mut self, url: Symbol) {
+            let url: String = url.into();
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                self.post.post_error(format!("open_url expects an http(s) url, got {}", url));
+                return;
+            }
+            let s = self.file_send.clone();
+            let cancel = self.cancel_token();
+            self.queue_job(move || {
+                let bytes = download(&url, URL_MAX_BYTES, &cancel, |read, total| {
+                    let _ = s.send(JobDone::Progress(read, total));
+                })?;
+                cancel.check()?;
+                AtsData::from_bytes(&bytes, url.clone()).map_err(stringify).map(|r| (r, url))
+            })
         }
 
         #[sel]
@@ -99,95 +1006,429 @@ external! {
                 .map(|a| (*a).try_into())
                 .collect::<Result<Vec<String>, _>>();
             if let Ok(args) = args {
-                self.queue_job(|| {
-                    let args = extract_args("anal_file", args);
-                    match args {
-                        Ok((f, mut args)) => {
-                            if !Path::new(&f).exists() {
-                                Err(format!("file does not exist: {}", f))
+                let (f, mut args, opts) = match extract_args("anal_file", args) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        self.post.post_error(e);
+                        return;
+                    }
+                };
+                let f = crate::externals::resolve_path(self.canvas, &f);
+                let s = self.file_send.clone();
+                let cancel = self.cancel_token();
+                let flush = CancelToken::new(&self.flushes);
+                let queued = Queued::new(&self.anal_queued);
+                let done_cancel = self.cancel_token();
+                let name = f.clone();
+                //no data means the analysis was flushed before it started
+                let job = move || -> Result<Option<(AtsData, String)>, String> {
+                    if !Path::new(&f).exists() {
+                        Err(format!("file does not exist: {}", f))
+                    } else {
+                        if let Ok(dir) = tempfile::tempdir() {
+                            //create temp path, based on original file name if possible
+                            let outpath = dir.path().join(format!("{}.ats", Path::new(&f).file_stem().unwrap_or(std::ffi::OsStr::new("out")).to_string_lossy()));
+                            //the analyzer only takes mono, so write the selected channel or mix,
+                            //formats it can't read are decoded and mixed down unless a
+                            //channel was selected
+                            let select = match opts.channel {
+                                None if !crate::decode::is_wav16(&f) => Some(ChannelSelect::Mixdown),
+                                select => select,
+                            };
+                            let _ = s.send(JobDone::AnalProgress(AnalStage::Decode));
+                            let source = match select {
+                                Some(select) => {
+                                    let path = dir.path().join("source.wav");
+                                    let sound = crate::decode::read(&f)?;
+                                    let mono = sound.to_mono(select)?;
+                                    crate::decode::write_wav16(&path, &mono, sound.sample_rate).map_err(stringify)?;
+                                    path.to_string_lossy().into_owned()
+                                }
+                                None => f.clone(),
+                            };
+                            let infile = CString::new(source).unwrap().into_raw();
+                            let outfile = to_cstring(outpath.clone());
+                            //ATS seems to always want the residual file in the same place
+                            //let resfile = to_cstring(dir.path().join("atsa_res.wav"));
+                            let mut resfile = ats_sys::ATSA_RES_FILE.to_vec();
+                            resfile.retain(|&x| x != b'\0'); // remove Nul
+                            let respath = PathBuf::from(String::from_utf8_lossy(&resfile).into_owned());
+                            let resfile = CString::new(resfile).unwrap();
+                            let resfile:Result<CString, String> = Ok(resfile);
+                            if outfile.is_err() || resfile.is_err() {
+                                Err("cannot get out or resfile paths".into())
                             } else {
-                                if let Ok(dir) = tempfile::tempdir() {
-                                    //create temp path, based on original file name if possible
-                                    let outpath = dir.path().join(format!("{}.ats", Path::new(&f).file_stem().unwrap_or(std::ffi::OsStr::new("out")).to_string_lossy()));
-                                    let infile = CString::new(f.clone()).unwrap().into_raw();
-                                    let outfile = to_cstring(outpath.clone());
-                                    //ATS seems to always want the residual file in the same place
-                                    //let resfile = to_cstring(dir.path().join("atsa_res.wav"));
-                                    let mut resfile = ats_sys::ATSA_RES_FILE.to_vec();
-                                    resfile.retain(|&x| x != b'\0'); // remove Nul
-                                    let resfile = CString::new(resfile).unwrap();
-                                    let resfile:Result<CString, String> = Ok(resfile);
-                                    if outfile.is_err() || resfile.is_err() {
-                                        Err("cannot get out or resfile paths".into())
-                                    } else {
-                                        let outfile = outfile.unwrap().into_raw();
-                                        let resfile = resfile.unwrap().into_raw();
-                                        unsafe {
-                                            let v = {
-                                                //all analysis uses the same residual file so we
-                                                //must lock
-                                                let _ = ANAL_MUTEX.lock().unwrap();
-                                                ats_sys::main_anal(infile, outfile, &mut args, resfile)
+                                let outfile = outfile.unwrap().into_raw();
+                                let resfile = resfile.unwrap().into_raw();
+                                unsafe {
+                                    let (v, kept) = {
+                                        //all analysis uses the same residual file so we
+                                        //must lock, until it has been copied
+                                        let _lock = ANAL_MUTEX.lock().unwrap();
+                                        drop(queued);
+                                        //canceled or flushed while waiting for another analysis
+                                        if cancel.canceled() || flush.canceled() {
+                                            (None, Ok(()))
+                                        } else {
+                                            //don't keep a stale residual if this analysis fails
+                                            let _ = std::fs::remove_file(&respath);
+                                            let _ = s.send(JobDone::AnalProgress(AnalStage::Track));
+                                            let v = ats_sys::main_anal(infile, outfile, &mut args, resfile);
+                                            let kept = if v == 0 && !cancel.canceled() {
+                                                let _ = s.send(JobDone::AnalProgress(AnalStage::Residual));
+                                                keep_residual(&respath, opts.res_out, &s)
+                                            } else {
+                                                Ok(())
                                             };
-                                            //cleanup constructed cstring
-                                            let _ = CString::from_raw(infile);
-                                            let _ = CString::from_raw(outfile);
-                                            let _ = CString::from_raw(resfile);
-                                            match v {
-                                                0 => AtsData::try_read(outpath).map_err(stringify).map(|r| (r, f)),
-                                                e @ _ => Err(format!("failed to analyize file: {} with error num: {}", f, e))
+                                            if v == 0 {
+                                                let _ = s.send(JobDone::AnalProgress(AnalStage::Done));
                                             }
+                                            (Some(v), kept)
                                         }
+                                    };
+                                    //cleanup constructed cstring
+                                    let _ = CString::from_raw(infile);
+                                    let _ = CString::from_raw(outfile);
+                                    let _ = CString::from_raw(resfile);
+                                    //the analysis is still good without its residual
+                                    if let Err(err) = kept {
+                                        let _ = s.send(JobDone::Warning(err));
+                                    }
+                                    cancel.check()?;
+                                    match v {
+                                        None => Ok(None),
+                                        Some(0) => AtsData::try_read(outpath).map_err(stringify).map(|r| Some((r, f))),
+                                        Some(e) => Err(format!("failed to analyize file: {} with error num: {}", f, e))
                                     }
-                                } else {
-                                    Err("failed to create tempdir".into())
                                 }
                             }
-                        },
-                        Err(e) => {
-                            Err(e)
+                        } else {
+                            Err("failed to create tempdir".into())
                         }
                     }
+                };
+                self.spawn_job(move || match job() {
+                    _ if done_cancel.canceled() => JobDone::Canceled("canceled".into()),
+                    Ok(None) => JobDone::Canceled(format!("flushed before analyzing {}", name)),
+                    Ok(Some(res)) => JobDone::Data(Ok(res)),
+                    Err(err) => JobDone::Data(Err(err)),
                 });
             } else {
                 self.post.post_error("failed to convert args to a string array".into());
             }
         }
 
+        #[sel]
+        pub fn version(&mut self) {
+            crate::externals::about::send_version(self.info_outlet.as_ref());
+        }
+
+        #[sel]
+        pub fn poll_interval(&mut self, ms: pd_sys::t_float) {
+            if ms > 0f32 {
+                self.poll_ms = ms as f64;
+            } else {
+                self.post.post_error("poll_interval must be greater than 0".into());
+            }
+        }
+
+        fn cancel_token(&self) -> CancelToken {
+            CancelToken::new(&self.generation)
+        }
+
+        //a job that reads data, its result is dropped if cancel is called before it finishes
         fn queue_job<F: 'static + Send + FnOnce() -> Result<(AtsData, String), String>>(&mut self, job: F) {
+            let cancel = self.cancel_token();
+            self.spawn_job(move || {
+                let res = job();
+                if cancel.canceled() {
+                    JobDone::Canceled("canceled".into())
+                } else {
+                    JobDone::Data(res)
+                }
+            });
+        }
+
+        //a job that only writes a file, returning the path
+        fn queue_write<F: 'static + Send + FnOnce() -> Result<String, String>>(&mut self, job: F) {
+            self.spawn_job(move || JobDone::Wrote(job()));
+        }
+
+        //outputs `job <id>`, the same id is output with `job_done <id> <ok>` when it finishes
+        fn spawn_job<F: 'static + Send + FnOnce() -> JobDone>(&mut self, job: F) {
             let s = self.file_send.clone();
+            let id = self.next_job;
+            self.next_job += 1;
             self.waiting.fetch_add(1, Ordering::SeqCst);
-            std::thread::spawn(move || s.send(job()));
-            self.clock.delay(1f64);
+            std::thread::spawn(move || s.send(JobDone::Finished(id, Box::new(job()))));
+            self.info_outlet.send_anything(*JOB, &[(id as f64).into()]);
+            self.clock.delay(self.poll_ms);
         }
 
         #[tramp]
         pub fn poll_done(&mut self) {
-            let mut waiting = 1;
-            if let Ok(res) = self.file_recv.try_recv() {
-                waiting = self.waiting.fetch_sub(1, Ordering::SeqCst) - 1;
-                self.current = match res {
-                    Ok((f, filename)) => {
-                        self.post.post(format!("read {}", filename));
-                        //store in cache
-                        let c = Arc::new(f);
-                        let k = crate::cache::insert(c.clone());
-                        Some((k, c))
-                    },
-                    Err(err) => {
+            //deliver everything that has finished since the last tick
+            while let Ok(done) = self.file_recv.try_recv() {
+                //progress, residuals and warnings don't finish a job
+                let (id, done) = match done {
+                    JobDone::Progress(read, total) => {
+                        let mut args: Vec<Atom> = vec![(read as f64).into()];
+                        if let Some(total) = total {
+                            args.push((total as f64).into());
+                        }
+                        self.info_outlet.send_anything(*PROGRESS, &args);
+                        continue;
+                    }
+                    JobDone::Residual(path) => {
+                        self.residual = Some(path);
+                        continue;
+                    }
+                    JobDone::AnalProgress(stage) => {
+                        self.info_outlet.send_anything(*ANAL_PROGRESS, &[stage.progress().into()]);
+                        continue;
+                    }
+                    JobDone::Warning(err) => {
                         self.post.post_error(err);
-                        None
+                        continue;
                     }
+                    JobDone::Finished(id, done) => (id, *done),
+                    _ => unreachable!(),
                 };
-                self.bang();
+                self.waiting.fetch_sub(1, Ordering::SeqCst);
+                let ok = self.finish_job(done);
+                self.info_outlet.send_anything(*JOB_DONE, &[(id as f64).into(), (ok as i32 as f64).into()]);
             }
-            if waiting != 0 {
-                self.clock.delay(1f64);
+            if self.waiting.load(Ordering::SeqCst) != 0 {
+                self.clock.delay(self.poll_ms);
             }
         }
+
+        //deliver a finished job, returning whether it succeeded
+        fn finish_job(&mut self, done: JobDone) -> bool {
+            let res = match done {
+                JobDone::Data(res) => res,
+                JobDone::Canceled(why) => {
+                    self.post.post(why);
+                    return false;
+                }
+                JobDone::Wrote(res) => {
+                    return match res {
+                        Ok(path) => {
+                            self.post.post(format!("wrote {}", path));
+                            true
+                        }
+                        Err(err) => {
+                            self.post.post_error(err);
+                            false
+                        }
+                    };
+                }
+                JobDone::Segments(res) => {
+                    return match res {
+                        Ok(segments) => {
+                            self.segments.clear();
+                            for (i, (f, path)) in segments.into_iter().enumerate() {
+                                self.post.post(format!("wrote {}", path));
+                                let f = Arc::new(f);
+                                let k = crate::cache::insert(f.clone());
+                                self.segments.push((k, f));
+                                self.info_outlet.send_anything(*SEGMENT, &[(i as f64).into(), k.into()]);
+                            }
+                            true
+                        }
+                        Err(err) => {
+                            self.post.post_error(err);
+                            false
+                        }
+                    };
+                }
+                JobDone::Bundle(res) => {
+                    return match res {
+                        Ok(bundle) => {
+                            self.post.post(format!("read {}", bundle.path));
+                            self.segments.clear();
+                            for (key, mut f) in bundle.data.into_iter() {
+                                if f.noise_scale() != self.noise_scale {
+                                    f.set_noise_scale(self.noise_scale);
+                                }
+                                let f = Arc::new(f);
+                                let k = crate::cache::insert(f.clone());
+                                if bundle.current.as_ref() == Some(&key) {
+                                    self.current = Some((k, f));
+                                } else {
+                                    let i = self.segments.len();
+                                    self.segments.push((k, f));
+                                    self.info_outlet.send_anything(*SEGMENT, &[(i as f64).into(), k.into()]);
+                                }
+                            }
+                            self.markers.clear();
+                            for (name, secs) in bundle.markers.into_iter() {
+                                match name.as_str().try_into() {
+                                    Ok(name) => self.markers.push((name, secs)),
+                                    Err(_) => self.post.post_error(format!("bad marker name {}", name)),
+                                }
+                            }
+                            self.bang();
+                            true
+                        }
+                        Err(err) => {
+                            self.post.post_error(err);
+                            false
+                        }
+                    };
+                }
+                _ => unreachable!(),
+            };
+            self.current = match res {
+                Ok((mut f, filename)) => {
+                    self.post.post(format!("read {}", filename));
+                    if f.noise_scale() != self.noise_scale {
+                        f.set_noise_scale(self.noise_scale);
+                    }
+                    //store in cache
+                    let c = Arc::new(f);
+                    let k = crate::cache::insert(c.clone());
+                    Some((k, c))
+                },
+                Err(err) => {
+                    self.post.post_error(err);
+                    None
+                }
+            };
+            let ok = self.current.is_some();
+            self.bang();
+            ok
+        }
+    }
+}
+
+const POLL_MS: f64 = 1f64;
+const PNG_WIDTH: u32 = 800;
+const PNG_HEIGHT: u32 = 400;
+
+//onset detection for export_segments
+const ONSET_THRESHOLD: f64 = 0.5f64;
+const ONSET_GAP_SECONDS: f64 = 0.1f64;
+
+enum JobDone {
+    Data(Result<(AtsData, String), String>),
+    Wrote(Result<String, String>),
+    Segments(Result<Vec<(AtsData, String)>, String>),
+    Bundle(Result<Bundle, String>),
+    //bytes read so far and the total, if the server told us
+    Progress(u64, Option<u64>),
+    //a copy of the residual of the last analysis
+    Residual(tempfile::TempPath),
+    //the stage an analysis has reached
+    AnalProgress(AnalStage),
+    //the result of the job with the given id
+    Finished(usize, Box<JobDone>),
+    //an error that doesn't fail the job it came from
+    Warning(String),
+    //a read dropped by cancel or flush, with why, the current data is kept
+    Canceled(String),
+}
+
+impl Drop for AtsDataExternal {
+    fn drop(&mut self) {
+        self.segments.clear();
+        unsafe {
+            pd_sys::pd_unbind(self.obj as *mut pd_sys::t_pd, self.dialog_recv.inner());
+        }
+    }
+}
+
+//a float message argument as an index, if it is one
+fn to_index(v: pd_sys::t_float) -> Option<usize> {
+    if v >= 0f32 && v.fract() == 0f32 {
+        Some(v as usize)
+    } else {
+        None
+    }
+}
+
+fn send_peak(outlet: &dyn OutletSend, frame: usize, partial: usize, p: &crate::data::Peak) {
+    outlet.send_anything(
+        *PEAK,
+        &[
+            (frame as f64).into(),
+            (partial as f64).into(),
+            p.freq.into(),
+            p.amp.into(),
+            p.noise_energy.unwrap_or(0f64).into(),
+            p.phase.unwrap_or(0f64).into(),
+        ],
+    );
+}
+
+//lets a worker know that cancel was called after it was queued
+struct CancelToken {
+    generation: Arc<AtomicUsize>,
+    started: usize,
+}
+
+impl CancelToken {
+    fn new(generation: &Arc<AtomicUsize>) -> Self {
+        Self {
+            generation: generation.clone(),
+            started: generation.load(Ordering::SeqCst),
+        }
+    }
+
+    fn canceled(&self) -> bool {
+        self.generation.load(Ordering::SeqCst) != self.started
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if self.canceled() {
+            Err("canceled".into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+//counts a job as queued until dropped
+struct Queued(Arc<AtomicUsize>);
+
+impl Queued {
+    fn new(count: &Arc<AtomicUsize>) -> Self {
+        count.fetch_add(1, Ordering::SeqCst);
+        Queued(count.clone())
+    }
+}
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
     }
 }
 
+//open_url limits
+const URL_MAX_BYTES: u64 = 256 * 1024 * 1024;
+const URL_TIMEOUT_MS: u64 = 10_000;
+const URL_PROGRESS_BYTES: u64 = 1 << 18;
+
+//length of generated data when no duration is given
+const GEN_DUR_SECONDS: f64 = 1f64;
+
+//frame spacing for synthesize_data when no duration is given
+const DRAFT_FRAME_SECONDS: f64 = 0.01f64;
+
+//frames of (freq, amp) per partial
+struct Draft {
+    name: String,
+    frames: Vec<Vec<(f64, f64)>>,
+    dur: f64,
+}
+
+//estimate_f0 search range when none is given
+const F0_MIN_HZ: f64 = 50f64;
+const F0_MAX_HZ: f64 = 2000f64;
+
+//default tolerance when matching partials between analyses
+const DIFF_CENTS: f32 = 50f32;
+
 lazy_static::lazy_static! {
     static ref SAMPLE_RATE: Symbol = "sample_rate".try_into().unwrap();
     static ref FRAME_SIZE: Symbol = "frame_samps".try_into().unwrap();
@@ -198,6 +1439,38 @@ lazy_static::lazy_static! {
     static ref FREQ_MAX: Symbol = "freq_max".try_into().unwrap();
     static ref DUR_SECONDS: Symbol = "dur_sec".try_into().unwrap();
     static ref FILE_TYPE: Symbol = "file_type".try_into().unwrap();
+    static ref HARMONIC: Symbol = "harmonic".try_into().unwrap();
+    static ref NOISEBURST: Symbol = "noiseburst".try_into().unwrap();
+    static ref SWEEP: Symbol = "sweep".try_into().unwrap();
+    static ref BAND_OF: Symbol = "band_of".try_into().unwrap();
+    static ref BAND_MAP: Symbol = "band_map".try_into().unwrap();
+    static ref BAND_MAP_AVERAGE: Symbol = "band_map_average".try_into().unwrap();
+    static ref AVERAGE: Symbol = "average".try_into().unwrap();
+    static ref CHROMA: Symbol = "chroma".try_into().unwrap();
+    static ref SEGMENT: Symbol = "segment".try_into().unwrap();
+    static ref MARKER: Symbol = "marker".try_into().unwrap();
+    static ref GOTO: Symbol = "goto".try_into().unwrap();
+    static ref LOOP: Symbol = "loop".try_into().unwrap();
+    static ref PROGRESS: Symbol = "progress".try_into().unwrap();
+    static ref ANAL_PROGRESS: Symbol = "anal_progress".try_into().unwrap();
+    static ref FREQ: Symbol = "freq".try_into().unwrap();
+    static ref AMP: Symbol = "amp".try_into().unwrap();
+    static ref NOISE: Symbol = "noise".try_into().unwrap();
+    static ref PHASE: Symbol = "phase".try_into().unwrap();
+    static ref BAND: Symbol = "band".try_into().unwrap();
+    static ref ENERGY: Symbol = "energy".try_into().unwrap();
+    static ref F0: Symbol = "f0".try_into().unwrap();
+    static ref F0_CONFIDENCE: Symbol = "f0_confidence".try_into().unwrap();
+    static ref INHARMONICITY: Symbol = "inharmonicity".try_into().unwrap();
+    static ref DEVIATION: Symbol = "deviation".try_into().unwrap();
+    static ref STATS: Symbol = "stats".try_into().unwrap();
+    static ref PEAK: Symbol = "peak".try_into().unwrap();
+    static ref JOBS: Symbol = "jobs".try_into().unwrap();
+    static ref JOB: Symbol = "job".try_into().unwrap();
+    static ref JOB_DONE: Symbol = "job_done".try_into().unwrap();
+    static ref RMS: Symbol = "rms".try_into().unwrap();
+    static ref POWER: Symbol = "power".try_into().unwrap();
+    static ref RAW: Symbol = "raw".try_into().unwrap();
 
     pub static ref DATA_KEY: Symbol = "ats_data".try_into().unwrap();
     static ref ANAL_MUTEX: Mutex<()> = Mutex::new(());
@@ -339,9 +1612,32 @@ fn create_app(cmd_name: &str) -> App {
             .possible_values(&["1", "2", "3", "4"])
             .help("Options: 1=amp.and freq. only, 2=amp.,freq. and phase, 3=amp.,freq. and residual, 4=amp.,freq.,phase, and residual")
         )
+        .arg(Arg::with_name("res_out")
+            .long("res_out")
+            .takes_value(true)
+            .help("path to keep a copy of the residual soundfile at")
+        )
+        .arg(Arg::with_name("channel")
+            .long("channel")
+            .takes_value(true)
+            .conflicts_with("mixdown")
+            .help("analyze only this channel of a multichannel source, starting at 0")
+        )
+        .arg(Arg::with_name("mixdown")
+            .long("mixdown")
+            .help("analyze the average of all the channels of the source")
+        )
 }
 
-fn extract_args(cmd_name: &str, args: Vec<String>) -> Result<(String, ANARGS), String> {
+//anal_file options that aren't passed on to the analyzer
+struct AnalOptions {
+    //where to copy the residual to
+    res_out: Option<String>,
+    //decode the source and analyze a mono version of it
+    channel: Option<ChannelSelect>,
+}
+
+fn extract_args(cmd_name: &str, args: Vec<String>) -> Result<(String, ANARGS, AnalOptions), String> {
     let mut app = create_app(cmd_name);
     let matches = app.clone().get_matches_from_safe(args);
 
@@ -400,7 +1696,18 @@ fn extract_args(cmd_name: &str, args: Vec<String>) -> Result<(String, ANARGS), S
             if let Some(v) = m.value_of("file_type") {
                 oargs.type_ = v.parse::<c_int>().map_err(stringify)?;
             }
-            Ok((source, oargs))
+            let channel = if m.is_present("mixdown") {
+                Some(ChannelSelect::Mixdown)
+            } else if let Some(v) = m.value_of("channel") {
+                Some(ChannelSelect::Channel(v.parse::<usize>().map_err(stringify)?))
+            } else {
+                None
+            };
+            let opts = AnalOptions {
+                res_out: m.value_of("res_out").map(|v| v.into()),
+                channel,
+            };
+            Ok((source, oargs, opts))
         }
         Err(m) => {
             let mut help = Vec::new();
@@ -415,6 +1722,138 @@ fn extract_args(cmd_name: &str, args: Vec<String>) -> Result<(String, ANARGS), S
     }
 }
 
+fn write_segments(f: &AtsData, dir: &Path) -> Result<Vec<(AtsData, String)>, String> {
+    let stem = Path::new(&f.source)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "segment".into());
+    let mut bounds = f.onsets(ONSET_THRESHOLD, ONSET_GAP_SECONDS);
+    bounds.push(f.frames.len());
+    let mut segments = Vec::new();
+    for (i, w) in bounds.windows(2).enumerate() {
+        let s = f.slice(w[0]..w[1]);
+        let path = dir.join(format!("{}-{}.ats", stem, i));
+        s.write(&path).map_err(stringify)?;
+        segments.push((s, path.to_string_lossy().into_owned()));
+    }
+    Ok(segments)
+}
+
+//the manifest lines are pd messages, `data <key> <file> <source>`, `current <key>` and
+//`marker <name> <seconds>`, so it can be read back with a text object
+fn write_bundle(
+    dir: &Path,
+    entries: &[(String, Arc<AtsData>)],
+    current: Option<String>,
+    markers: &[(String, f64)],
+) -> Result<String, String> {
+    use std::fmt::Write;
+    std::fs::create_dir_all(dir).map_err(stringify)?;
+    let mut manifest = String::new();
+    for (key, f) in entries.iter() {
+        let file = format!("{}.ats", key);
+        f.write(dir.join(&file)).map_err(stringify)?;
+        writeln!(manifest, "data {} {} {};", key, file, f.source.replace(char::is_whitespace, "_")).unwrap();
+    }
+    if let Some(key) = current {
+        writeln!(manifest, "current {};", key).unwrap();
+    }
+    for (name, secs) in markers.iter() {
+        writeln!(manifest, "marker {} {};", name, secs).unwrap();
+    }
+    let path = dir.join("manifest.txt");
+    std::fs::write(&path, manifest).map_err(stringify)?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+//the contents of a bundle, keys are the ones written to the manifest, not cache keys
+struct Bundle {
+    data: Vec<(String, AtsData)>,
+    current: Option<String>,
+    markers: Vec<(String, f64)>,
+    path: String,
+}
+
+//read the manifest written by write_bundle and the ats files it names, relative to the
+//manifest's directory
+fn read_bundle(manifest: &Path) -> Result<Bundle, String> {
+    let text = std::fs::read_to_string(manifest)
+        .map_err(|e| format!("failed to read {}: {}", manifest.display(), e))?;
+    let dir = manifest.parent().unwrap_or_else(|| Path::new(""));
+    let mut bundle = Bundle {
+        data: Vec::new(),
+        current: None,
+        markers: Vec::new(),
+        path: manifest.to_string_lossy().into_owned(),
+    };
+    for line in text.split(';') {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => (),
+            ["data", key, file, source] => {
+                let path = dir.join(file);
+                let mut f = AtsData::try_read(&path)
+                    .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+                f.source = (*source).into();
+                bundle.data.push(((*key).into(), f));
+            }
+            ["current", key] => bundle.current = Some((*key).into()),
+            ["marker", name, secs] => {
+                let secs = secs
+                    .parse::<f64>()
+                    .map_err(|_| format!("{}: bad marker time {}", bundle.path, secs))?;
+                bundle.markers.push(((*name).into(), secs));
+            }
+            _ => return Err(format!("{}: unknown manifest line {}", bundle.path, line.trim())),
+        }
+    }
+    Ok(bundle)
+}
+
+//the stages of anal_file, reported as `anal_progress <0..1>` when each one starts. main_anal
+//doesn't report its own progress so tracking, the bulk of the work, is one step
+#[derive(Clone, Copy)]
+enum AnalStage {
+    //the source is decoded or mixed down for the analyzer
+    Decode,
+    //main_anal is tracking the partials and writing the residual
+    Track,
+    //the residual is copied out
+    Residual,
+    Done,
+}
+
+impl AnalStage {
+    //the fraction of the analysis done when this stage starts
+    fn progress(self) -> f64 {
+        match self {
+            AnalStage::Decode => 0f64,
+            AnalStage::Track => 0.1f64,
+            AnalStage::Residual => 0.9f64,
+            AnalStage::Done => 1f64,
+        }
+    }
+}
+
+//copy the shared residual soundfile to res_out, if given, and to a temp file that save_residual
+//can copy from later
+fn keep_residual(respath: &Path, res_out: Option<String>, send: &Sender<JobDone>) -> Result<(), String> {
+    if !respath.exists() {
+        return Ok(());
+    }
+    if let Some(out) = res_out {
+        std::fs::copy(respath, &out).map_err(|e| format!("failed to copy the residual to {}: {}", out, e))?;
+    }
+    let kept = tempfile::Builder::new()
+        .suffix(".wav")
+        .tempfile()
+        .map_err(stringify)?
+        .into_temp_path();
+    std::fs::copy(respath, &kept).map_err(stringify)?;
+    let _ = send.send(JobDone::Residual(kept));
+    Ok(())
+}
+
 fn to_cstring(p: PathBuf) -> Result<CString, String> {
     let s = p.to_str();
     if let Some(s) = s {
@@ -428,6 +1867,53 @@ fn to_cstring(p: PathBuf) -> Result<CString, String> {
     }
 }
 
+//fetch a url into memory, erroring if it is bigger than max bytes
+fn download<F: Fn(u64, Option<u64>)>(
+    url: &str,
+    max: u64,
+    cancel: &CancelToken,
+    progress: F,
+) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+    let resp = ureq::get(url)
+        .timeout_connect(URL_TIMEOUT_MS)
+        .timeout_read(URL_TIMEOUT_MS)
+        .call();
+    if let Some(err) = resp.synthetic_error() {
+        return Err(format!("failed to fetch {}: {}", url, err));
+    }
+    if !resp.ok() {
+        return Err(format!("failed to fetch {}: {} {}", url, resp.status(), resp.status_text()));
+    }
+    let total = resp.header("Content-Length").and_then(|l| l.parse::<u64>().ok());
+    if let Some(total) = total {
+        if total > max {
+            return Err(format!("{} is {} bytes, more than the {} byte limit", url, total, max));
+        }
+    }
+    let mut reader = resp.into_reader().take(max + 1);
+    let mut bytes = Vec::with_capacity(total.unwrap_or(0) as usize);
+    let mut chunk = [0u8; 65536];
+    let mut reported = 0;
+    loop {
+        cancel.check()?;
+        let n = reader.read(&mut chunk).map_err(|e| format!("failed to read {}: {}", url, e))?;
+        if n == 0 {
+            break;
+        }
+        bytes.extend_from_slice(&chunk[..n]);
+        if bytes.len() as u64 > max {
+            return Err(format!("{} is more than the {} byte limit", url, max));
+        }
+        if bytes.len() as u64 - reported >= URL_PROGRESS_BYTES {
+            reported = bytes.len() as u64;
+            progress(reported, total);
+        }
+    }
+    progress(bytes.len() as u64, total);
+    Ok(bytes)
+}
+
 fn stringify<E: std::fmt::Display>(x: E) -> String {
     format!("error code: {}", x)
 }
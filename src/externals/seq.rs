@@ -0,0 +1,217 @@
+use crate::data::AtsData;
+use crate::synth::lerp;
+use pd_ext::atom::Atom;
+use pd_ext::builder::ControlExternalBuilder;
+use pd_ext::clock::Clock;
+use pd_ext::external::ControlExternal;
+use pd_ext::outlet::{OutletSend, OutletType};
+use pd_ext::post::PdPost;
+use pd_ext::symbol::Symbol;
+use pd_ext_macros::external;
+use std::convert::TryInto;
+use std::sync::Arc;
+
+const INTERVAL_MS: f64 = 50f64;
+
+lazy_static::lazy_static! {
+    static ref DATA_KEY: Symbol = "ats_data".try_into().unwrap();
+    static ref TARGET: Symbol = "ats_target".try_into().unwrap();
+    static ref STATE: Symbol = "state".try_into().unwrap();
+    static ref DONE: Symbol = "done".try_into().unwrap();
+}
+
+//a step of the sequence, the data key to move to, per partial amplitude weights and how long, in
+//milliseconds, to move from the previous state to this one, more than 0
+struct State {
+    key: Symbol,
+    weights: Vec<f64>,
+    dur: f64,
+}
+
+//the weight of a partial, partials past the end of the list are unweighted
+fn weight(weights: &[f64], i: usize) -> f64 {
+    weights.get(i).cloned().unwrap_or(1f64)
+}
+
+external! {
+    #[name="ats/seq"]
+    pub struct AtsSeqExternal {
+        states: Vec<State>,
+        //the playing state, when it started in logical time and its data
+        current: Option<(usize, f64, Arc<AtsData>)>,
+        //the data and weights the playing state moves from
+        from: Option<(Arc<AtsData>, Vec<f64>)>,
+        //the interpolated data, under one key for the life of this object, and whether the
+        //synths have been sent it yet
+        out: Option<Arc<AtsData>>,
+        out_key: Option<Symbol>,
+        started: bool,
+        looping: bool,
+        interval: f64,
+        synth_outlet: Box<dyn OutletSend>,
+        info_outlet: Box<dyn OutletSend>,
+        clock: Clock,
+        post: Box<dyn PdPost>,
+    }
+
+    impl ControlExternal for AtsSeqExternal {
+        fn new(builder: &mut dyn ControlExternalBuilder<Self>) -> Result<Self, String> {
+            let synth_outlet = builder.new_message_outlet(OutletType::AnyThing);
+            let info_outlet = builder.new_message_outlet(OutletType::AnyThing);
+            let clock = Clock::new(builder.obj(), atsseqexternal_tick_trampoline);
+            Ok(Self {
+                states: Vec::new(),
+                current: None,
+                from: None,
+                out: None,
+                out_key: None,
+                started: false,
+                looping: false,
+                interval: INTERVAL_MS,
+                synth_outlet,
+                info_outlet,
+                clock,
+                post: builder.poster(),
+            })
+        }
+    }
+
+    impl AtsSeqExternal {
+        //start from the first state
+        #[bang]
+        pub fn bang(&mut self) {
+            if self.states.is_empty() {
+                self.post.post_error("no states to play".into());
+                return;
+            }
+            self.from = None;
+            self.started = false;
+            self.enter(0);
+        }
+
+        #[sel]
+        pub fn stop(&mut self) {
+            self.current = None;
+            self.clock.unset();
+        }
+
+        //append a state: <key> <duration ms> [partial weights...]
+        #[sel]
+        pub fn add(&mut self, args: &[Atom]) {
+            let v: Vec<f64> = args.iter().skip(1).filter_map(|a| a.get_float()).map(|v| v as f64).collect();
+            match (args.get(0).and_then(|a| a.get_symbol()), v.get(0)) {
+                (Some(key), Some(dur)) if v.len() + 1 == args.len() && *dur > 0f64 => {
+                    self.states.push(State {
+                        key,
+                        weights: v[1..].to_vec(),
+                        dur: *dur,
+                    });
+                }
+                _ => self.post.post_error("add expects <key> <duration ms> [weights...]".into()),
+            }
+        }
+
+        #[sel]
+        pub fn clear(&mut self) {
+            self.stop();
+            self.states.clear();
+        }
+
+        //start over from the first state after the last one
+        #[sel]
+        pub fn r#loop(&mut self, v: pd_sys::t_float) {
+            self.looping = v != 0f32;
+        }
+
+        //milliseconds between interpolated data
+        #[sel]
+        pub fn interval(&mut self, ms: pd_sys::t_float) {
+            if ms > 0f32 {
+                self.interval = ms as f64;
+            } else {
+                self.post.post_error("interval must be greater than 0".into());
+            }
+        }
+
+        #[sel]
+        pub fn version(&mut self) {
+            crate::externals::about::send_version(self.info_outlet.as_ref());
+        }
+
+        //start moving from the last state's data towards a state's data
+        fn enter(&mut self, index: usize) {
+            let key = self.states[index].key;
+            let data = match crate::cache::get(key) {
+                Some(d) => d,
+                None => {
+                    self.post.post_error(format!("state {}: data key {} not found", index, key));
+                    self.stop();
+                    return;
+                }
+            };
+            //the first state moves from its own data, unweighted
+            if self.from.is_none() {
+                self.from = Some((data.clone(), Vec::new()));
+            }
+            self.current = Some((index, unsafe { pd_sys::clock_getlogicaltime() }, data));
+            self.info_outlet.send_anything(*STATE, &[(index as f64).into(), key.into()]);
+            self.clock.delay(0f64);
+        }
+
+        //interpolate the data and send its key, the first data of a run goes out as ats_data so
+        //the synths crossfade to it, after that as ats_target so they follow it directly
+        fn send(&mut self, data: AtsData) {
+            let data = Arc::new(data);
+            let key = match self.out_key {
+                Some(key) => {
+                    crate::cache::set(key, &data);
+                    key
+                }
+                None => {
+                    let key = crate::cache::insert(data.clone());
+                    self.out_key = Some(key);
+                    key
+                }
+            };
+            self.out = Some(data);
+            let sel = if self.started { *TARGET } else { *DATA_KEY };
+            self.started = true;
+            self.synth_outlet.send_anything(sel, &[key.into()]);
+        }
+
+        #[tramp]
+        pub fn tick(&mut self) {
+            let (index, started, to) = match &self.current {
+                Some((i, s, d)) => (*i, *s, d.clone()),
+                None => return,
+            };
+            let (from, from_weights) = match &self.from {
+                Some(f) => f.clone(),
+                None => return,
+            };
+            let state = &self.states[index];
+            let elapsed = unsafe { pd_sys::clock_gettimesince(started) };
+            let t = (elapsed / state.dur).min(1f64);
+            let count = std::cmp::max(state.weights.len(), from_weights.len());
+            let weights: Vec<f64> = (0..count)
+                .map(|i| lerp(weight(&from_weights, i), weight(&state.weights, i), t))
+                .collect();
+            let mut data = from.morph(&to, t);
+            data.scale_amps(&weights);
+            self.send(data);
+            if t < 1f64 {
+                self.clock.delay(self.interval);
+                return;
+            }
+            self.from = Some((to, self.states[index].weights.clone()));
+            if index + 1 < self.states.len() {
+                self.enter(index + 1);
+            } else if self.looping {
+                self.enter(0);
+            } else {
+                self.current = None;
+                self.info_outlet.send_anything(*DONE, &[]);
+            }
+        }
+    }
+}
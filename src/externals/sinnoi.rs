@@ -1,199 +1,1028 @@
-use crate::data::AtsData;
+use crate::data::{AtsData, Peak, NOISE_BANDS};
+use crate::ducker::Ducker;
+use crate::pool::Pool;
+use crate::synth::{
+    db_to_amp, eq_loudness_gain, flush_denormal, frame_pos, harmonize, lerp, process_lanes, soft_clip, Adsr, AdsrTimes,
+    AmpInterp, ArcAtomic, DcBlock, FlushDenormals, Interp, NoiseType, OobMode, Osc, ParitalSynth,
+    ParitalSynthHandle, PartialParams, Scale, SlewTimes, DB_FLOOR, LOAD_ORDERING, STORE_ORDERING,
+};
 use atomic::Atomic;
+use clap::{App, AppSettings, Arg};
 use itertools::izip;
+use pd_ext::atom::Atom;
 use pd_ext::builder::SignalProcessorExternalBuilder;
+use pd_ext::clock::Clock;
 use pd_ext::external::{SignalProcessor, SignalProcessorExternal};
+use pd_ext::outlet::{OutletSend, OutletType};
 use pd_ext::post::PdPost;
 use pd_ext::symbol::Symbol;
-use rand::prelude::*;
+use std::collections::HashMap;
 use std::convert::TryInto;
-use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
 use std::sync::Arc;
 
 const DSP_RECV_MAX: usize = 32;
-const STORE_ORDERING: std::sync::atomic::Ordering = std::sync::atomic::Ordering::Relaxed;
-const LOAD_ORDERING: std::sync::atomic::Ordering = std::sync::atomic::Ordering::Relaxed;
+//voice gain ramps, in ms from 0 to 1
+const VOICE_RAMP_MS: f64 = 5f64;
+//default bound on the bank size in auto mode
+const AUTO_MAX: usize = 1024;
+const ENV_INTERVAL_MS: f64 = 50f64;
+const XFADE_MS: f64 = 10f64;
+const GATE_RELEASE_MS: f64 = 50f64;
+const LOOP_XFADE_MS: f64 = 50f64;
+//default amplitude a partial is born above, about -80dB
+const BIRTH_AMP: f64 = 1e-4;
+//attack, decay, sustain level and release for notes
+const ADSR_MS: [f64; 4] = [10f64, 100f64, 1f64, 200f64];
+const CALIBRATE_SECONDS: f64 = 3600f64;
+//milliseconds between parameter updates during an interpolated preset recall
+const PRESET_INTERVAL_MS: f64 = 20f64;
+const SINE_TABLE_SIZE: usize = 4096;
+//larger blocks are synthesized on a single thread
+const THREAD_BLOCK_MAX: usize = 4096;
 
-type ArcAtomic<T> = Arc<Atomic<T>>;
-
-fn noise() -> f64 {
-    thread_rng().gen_range(-1f64, 1f64)
-}
+//position events, reported at most once per poll
+const EVENT_NONE: usize = 0;
+const EVENT_DONE: usize = 1;
+const EVENT_LOOP: usize = 2;
+const EVENT_OOB: usize = 3;
 
 lazy_static::lazy_static! {
+    static ref ENV: Symbol = "env".try_into().unwrap();
+    static ref DONE: Symbol = "done".try_into().unwrap();
+    static ref LOOP: Symbol = "loop".try_into().unwrap();
+    static ref OOB: Symbol = "oob".try_into().unwrap();
+    static ref NONE: Symbol = "none".try_into().unwrap();
+    static ref LINEAR: Symbol = "linear".try_into().unwrap();
+    static ref CUBIC: Symbol = "cubic".try_into().unwrap();
+    static ref EXP: Symbol = "exp".try_into().unwrap();
+    static ref COSINE: Symbol = "cosine".try_into().unwrap();
+    static ref INTERP: Symbol = "interp".try_into().unwrap();
+    static ref FREQ_MUL: Symbol = "freq_mul".try_into().unwrap();
+    static ref FREQ_ADD: Symbol = "freq_add".try_into().unwrap();
+    static ref AMP_MUL: Symbol = "amp_mul".try_into().unwrap();
+    static ref NOISE_AMP_MUL: Symbol = "noise_amp_mul".try_into().unwrap();
+    static ref NOISE_BW_SCALE: Symbol = "noise_bw_scale".try_into().unwrap();
+    static ref NOISE_BW_HZ: Symbol = "noise_bw_hz".try_into().unwrap();
+    static ref DETUNE: Symbol = "detune".try_into().unwrap();
+    static ref UNIFORM: Symbol = "uniform".try_into().unwrap();
+    static ref GAUSSIAN: Symbol = "gaussian".try_into().unwrap();
+    static ref VELVET: Symbol = "velvet".try_into().unwrap();
+    static ref RELATIVE: Symbol = "relative".try_into().unwrap();
+    static ref HZ: Symbol = "hz".try_into().unwrap();
     static ref ALL: Symbol = "all".try_into().unwrap();
+    static ref CLAMP: Symbol = "clamp".try_into().unwrap();
+    static ref WRAP: Symbol = "wrap".try_into().unwrap();
+    static ref MIRROR: Symbol = "mirror".try_into().unwrap();
+    static ref SILENCE: Symbol = "silence".try_into().unwrap();
+    static ref NEG_INF: Symbol = "-inf".try_into().unwrap();
 }
 
-struct Slewed {
-    cur: f64,
-    dest: ArcAtomic<f64>,
-    inc: ArcAtomic<f64>,
+//a preset slot, named by a symbol or a number
+fn preset_name(a: &Atom) -> Option<String> {
+    a.get_symbol()
+        .map(|s| s.into())
+        .or_else(|| a.get_int().map(|i| i.to_string()))
 }
 
-impl Slewed {
-    pub fn new(dest: ArcAtomic<f64>, inc: f64) -> Self {
-        Self {
-            cur: dest.load(LOAD_ORDERING),
-            dest,
-            inc: Arc::new(Atomic::new(inc)),
+//a synth's assignment to a partial, with a gain that ramps when the assignment changes
+#[derive(Default, Clone, Copy)]
+struct Voice {
+    partial: Option<usize>,
+    gain: f64,
+    target: f64,
+    //values latched by hold and hold_amp, with the partial they were latched for
+    held_freq: Option<(usize, f64)>,
+    held_amp: Option<(usize, f64, f64)>,
+    //amplitude hysteresis state for the partial it tracks
+    hyst: Option<HystState>,
+    //the smoothed frequency, amplitude and noise energy for the partial it tracks
+    blurred: Option<(usize, (f64, f64, f64))>,
+    //the birth/death fade level of the partial it tracks
+    born: Option<(usize, f64)>,
+}
+
+//thresholds a partial's amplitude must rise above to sound and fall below to go quiet, and how
+//many samples it sounds for at least once on
+#[derive(Clone, Copy)]
+struct AmpHysteresis {
+    on: f64,
+    off: f64,
+    min_on: usize,
+}
+
+#[derive(Clone, Copy)]
+struct HystState {
+    partial: usize,
+    on: bool,
+    //samples since turning on
+    on_for: usize,
+    level: f64,
+}
+
+impl Voice {
+    //the frequency to play, latching freq while hold is on
+    fn hold_freq(&mut self, hold: bool, p: usize, freq: f64) -> f64 {
+        if !hold {
+            self.held_freq = None;
+            return freq;
+        }
+        match self.held_freq {
+            Some((q, f)) if q == p => f,
+            _ => {
+                self.held_freq = Some((p, freq));
+                freq
+            }
+        }
+    }
+
+    //the amplitude and noise energy to play, latching them while hold_amp is on
+    fn hold_amp(&mut self, hold: bool, p: usize, amp: f64, noise: f64) -> (f64, f64) {
+        if !hold {
+            self.held_amp = None;
+            return (amp, noise);
+        }
+        match self.held_amp {
+            Some((q, a, n)) if q == p => (a, n),
+            _ => {
+                self.held_amp = Some((p, amp, noise));
+                (amp, noise)
+            }
         }
     }
-    pub fn val(&self) -> f64 {
-        self.cur
+
+    //lowpass the partial's trajectory with the one pole coefficient, if any
+    fn blur(&mut self, coeff: Option<f64>, p: usize, v: (f64, f64, f64)) -> (f64, f64, f64) {
+        let k = match coeff {
+            Some(k) => k,
+            None => {
+                self.blurred = None;
+                return v;
+            }
+        };
+        let b = match self.blurred.as_mut() {
+            Some((q, b)) if *q == p => b,
+            _ => {
+                self.blurred = Some((p, v));
+                return v;
+            }
+        };
+        b.0 += k * (v.0 - b.0);
+        //the amplitudes decay towards silence, keep them out of the denormals
+        b.1 = flush_denormal(b.1 + k * (v.1 - b.1));
+        b.2 = flush_denormal(b.2 + k * (v.2 - b.2));
+        *b
+    }
+
+    //the gain that fades the partial in when its amplitude rises above threshold and out when it
+    //falls back, over fade times in samples. the upcoming frame's amplitude, and the samples until
+    //it, start the fade out early so that it ends as the partial dies
+    fn birth(
+        &mut self,
+        threshold: f64,
+        fades: (f64, f64),
+        p: usize,
+        amp: f64,
+        upcoming: (f64, f64),
+    ) -> f64 {
+        if fades.0 <= 0f64 && fades.1 <= 0f64 {
+            self.born = None;
+            return 1f64;
+        }
+        let alive = amp > threshold;
+        let level = match self.born.as_mut() {
+            Some((q, level)) if *q == p => level,
+            _ => {
+                let level = if alive { 1f64 } else { 0f64 };
+                self.born = Some((p, level));
+                return level;
+            }
+        };
+        let step = |samples: f64| if samples > 1f64 { 1f64 / samples } else { 1f64 };
+        *level = if alive {
+            (*level + step(fades.0)).min(1f64)
+        } else {
+            (*level - step(fades.1)).max(0f64)
+        };
+        let (next_amp, until) = upcoming;
+        if alive && next_amp <= threshold && fades.1 > 0f64 {
+            *level = level.min(until / fades.1);
+        }
+        *level
     }
-    pub fn update(&mut self) {
-        let dest = self.dest.load(LOAD_ORDERING);
-        let inc = self.inc.load(LOAD_ORDERING);
-        self.cur = if self.cur == dest || (self.cur - dest).abs() <= inc {
-            dest
-        } else if self.cur < dest {
-            self.cur + inc
+
+    //the gain of the partial under amplitude hysteresis, fading between on and off
+    fn hysteresis(&mut self, h: Option<AmpHysteresis>, inc: f64, p: usize, amp: f64) -> f64 {
+        let h = match h {
+            Some(h) => h,
+            None => {
+                self.hyst = None;
+                return 1f64;
+            }
+        };
+        let s = match self.hyst.as_mut() {
+            Some(s) if s.partial == p => s,
+            _ => {
+                let on = amp >= h.on;
+                self.hyst = Some(HystState {
+                    partial: p,
+                    on,
+                    on_for: 0,
+                    level: if on { 1f64 } else { 0f64 },
+                });
+                return if on { 1f64 } else { 0f64 };
+            }
+        };
+        if s.on {
+            s.on_for = s.on_for.saturating_add(1);
+            if amp < h.off && s.on_for >= h.min_on {
+                s.on = false;
+            }
+        } else if amp >= h.on {
+            s.on = true;
+            s.on_for = 0;
+        }
+        s.level = if s.on {
+            (s.level + inc).min(1f64)
         } else {
-            self.cur - inc
+            (s.level - inc).max(0f64)
         };
+        s.level
+    }
+
+    fn ramp(&mut self, inc: f64) {
+        if self.gain < self.target {
+            self.gain = (self.gain + inc).min(self.target);
+        } else if self.gain > self.target {
+            self.gain = (self.gain - inc).max(self.target);
+        }
+    }
+}
+
+//the per synth state, allocated off the audio thread and swapped in whole
+struct SynthBank {
+    synths: Box<[ParitalSynth]>,
+    voices: Box<[Voice]>,
+    //scratch space for loudest partial selection
+    chosen: Box<[(f64, usize)]>,
+    //per synth flag, set when the partial is below amp_gate for the whole block
+    gated: Box<[bool]>,
+    //the sine oscillators as struct of arrays, per synth phases, increments and amplitudes, for
+    //the simd feature's process_lanes
+    lanes: Lanes,
+}
+
+struct Lanes {
+    phases: Box<[f64]>,
+    incs: Box<[f64]>,
+    amps: Box<[f64]>,
+    osc: Osc,
+}
+
+impl Lanes {
+    fn new(count: usize, osc: &Osc) -> Self {
+        Self {
+            phases: vec![0f64; count].into(),
+            incs: vec![0f64; count].into(),
+            amps: vec![0f64; count].into(),
+            osc: osc.clone(),
+        }
+    }
+
+    fn restart(&mut self) {
+        for p in self.phases.iter_mut() {
+            *p = 0f64;
+        }
+    }
+}
+
+impl SynthBank {
+    //build a bank of the given size, carrying over the parameters of any existing handles
+    fn new(
+        prev: &[ParitalSynthHandle],
+        count: usize,
+        times: &SlewTimes,
+        osc: &Osc,
+        sample_rate: f64,
+    ) -> (Box<[ParitalSynthHandle]>, Self) {
+        let mut synths = Vec::new();
+        let mut handles = Vec::new();
+        for i in 0..count {
+            let (h, mut s) = if let Some(p) = prev.get(i) {
+                p.new_like(times, sample_rate)
+            } else {
+                ParitalSynthHandle::new(times, sample_rate)
+            };
+            s.set_osc(osc.clone());
+            handles.push(h);
+            synths.push(s);
+        }
+        (
+            handles.into(),
+            Self {
+                voices: vec![Voice::default(); count].into(),
+                chosen: vec![(0f64, 0usize); count].into(),
+                gated: vec![false; count].into(),
+                lanes: Lanes::new(count, osc),
+                synths: synths.into(),
+            },
+        )
     }
 }
 
-pub struct ParitalSynth {
-    phase_freq_mul: f64,
-    phase: f64,
-    noise_phase: f64,
-    noise_x0: f64,
-    noise_x1: f64,
-
-    //params
-    freq_mul: Slewed,
-    freq_add: Slewed,
-    amp_mul: Slewed,
-    noise_amp_mul: Slewed,
-    noise_bw_scale: Slewed,
+enum Update {
+    Data(Option<Arc<AtsData>>),
+    //data that replaces the current data without a crossfade, see ats_target
+    Target(Arc<AtsData>),
+    Bank(SynthBank),
+    Scale(Scale),
+    Ab(Option<Arc<AtsData>>, Option<Arc<AtsData>>),
+    Toggle,
+    //offset, incr and limit together
+    Select(usize, usize, usize),
+    Seed(u64),
+    //note on with a transposition in semitones, or note off
+    Note(Option<f64>),
 }
 
-struct ParitalSynthHandle {
-    freq_mul: ArcAtomic<f64>,
-    freq_add: ArcAtomic<f64>,
-    amp_mul: ArcAtomic<f64>,
-    noise_amp_mul: ArcAtomic<f64>,
-    noise_bw_scale: ArcAtomic<f64>,
+pub struct AtsSinNoiProcessor {
+    current: Option<Arc<AtsData>>,
+    //the other dataset in a/b mode, swapped with current on toggle
+    alt: Option<Arc<AtsData>>,
+    ab_pending: bool,
+    //noise seed, kept so that rebuilt banks are seeded too
+    seed: Option<u64>,
+    ab_fade_in: bool,
+    //data waiting for the fade out of current, and the level of the crossfade
+    next: Option<Option<Arc<AtsData>>>,
+    level: f64,
+    xfade: ArcAtomic<f64>,
+    //the rate of the signal, pd's rate times the oversampling of an enclosing block~
+    sample_rate: f64,
+    oversample: ArcAtomic<f64>,
+    updates: Receiver<Update>,
+    //replaced banks go back to the message thread to be freed
+    trash: SyncSender<SynthBank>,
+    incr: ArcAtomic<usize>,
+    offset: ArcAtomic<usize>,
+    limit: ArcAtomic<usize>,
+    freq_min: ArcAtomic<f64>,
+    freq_max: ArcAtomic<f64>,
+    amp_gate: ArcAtomic<f64>,
+    //time constant, in frames, for smoothing partial trajectories, 0 is off
+    blur: ArcAtomic<f64>,
+    //the amplitude below which a partial counts as unborn or dead, for fade_in and fade_out
+    birth_amp: ArcAtomic<f64>,
+    //amplitude hysteresis thresholds, off while amp_on is 0, and minimum on time in ms
+    amp_on: ArcAtomic<f64>,
+    amp_off: ArcAtomic<f64>,
+    amp_min_on: ArcAtomic<f64>,
+    top: ArcAtomic<usize>,
+    interp: ArcAtomic<Interp>,
+    amp_interp: ArcAtomic<AmpInterp>,
+    oob_mode: ArcAtomic<OobMode>,
+    hold_end: ArcAtomic<bool>,
+    //latch the frequencies or amplitudes of the playing partials
+    hold_freq: ArcAtomic<bool>,
+    hold_amp: ArcAtomic<bool>,
+    //loop region in milliseconds and its crossfade
+    //play the data backwards for the same position input
+    reverse: ArcAtomic<bool>,
+    loop_on: ArcAtomic<bool>,
+    loop_start: ArcAtomic<f64>,
+    loop_end: ArcAtomic<f64>,
+    loop_xfade: ArcAtomic<f64>,
+    //store the oscillator phases once per block so the message thread can read them
+    report_phase: ArcAtomic<bool>,
+    bank: SynthBank,
+    //optional signal inlet index that scales the summed output
+    vca: Option<usize>,
+    //optional signal inlet indexes that scale the frequency and amplitude of the whole bank
+    sig_freq_mul: Option<usize>,
+    sig_amp_mul: Option<usize>,
+    //optional sidechain signal inlet index and its filterbank
+    ducker: Option<(usize, Ducker)>,
+    duck_amount: ArcAtomic<f64>,
+    duck_attack: ArcAtomic<f64>,
+    duck_release: ArcAtomic<f64>,
+    noise_width: ArcAtomic<f64>,
+    //noise energy scale for each critical band
+    band_gain: Box<[ArcAtomic<f64>]>,
+    //optional gate signal inlet index, rising edges restart the oscillators and falling edges
+    //release over gate_release ms
+    gate: Option<usize>,
+    gate_prev: f32,
+    gate_level: f64,
+    gate_release: ArcAtomic<f64>,
+    //output dc blocker, per channel, and saturation
+    dcblock: ArcAtomic<bool>,
+    dc: [DcBlock; 2],
+    softclip: ArcAtomic<bool>,
+    //note envelope, only applied once a note has been played, and the note's frequency ratio
+    adsr: Option<Adsr>,
+    adsr_times: ArcAtomic<[f64; 4]>,
+    transpose: f64,
+    //workers that synthesize ranges of the bank, with their left and right outputs and envelopes
+    pool: Option<Pool>,
+    thread_out: Box<[pd_sys::t_float]>,
+    thread_env: Box<[(f64, f64)]>,
+    //estimated rms of the sines and noise over the last block
+    env_sines: ArcAtomic<f64>,
+    env_noise: ArcAtomic<f64>,
+    //the envelope goes out every env_interval ms while dsp runs, 0 stops it
+    env_interval: ArcAtomic<f64>,
+    env_elapsed: f64,
+    env_clock: PerformClock,
+    //the last position event and the position, in seconds, it happened at, the clock outputs it
+    pos_event: ArcAtomic<usize>,
+    pos_event_at: ArcAtomic<f64>,
+    event_clock: PerformClock,
+    //position at the end of the previous block, in frames
+    last_pos: Option<f64>,
+    //spectral tilt in dB per octave around a pivot frequency
+    tilt: ArcAtomic<f64>,
+    tilt_pivot: ArcAtomic<f64>,
+    //how much of the equal loudness compensation for moved partials to apply, 0..1
+    eq_loudness: ArcAtomic<f64>,
+    scale: Scale,
+    quantize: ArcAtomic<f64>,
+    harmonic_f0: ArcAtomic<f64>,
+    harmonic_strength: ArcAtomic<f64>,
 }
 
-impl ParitalSynthHandle {
-    pub fn freq_mul(&mut self, v: f64) {
-        self.freq_mul.store(v, STORE_ORDERING);
+//a clock set from the processor, perform runs in pd's scheduler thread, as for bang~, so the
+//clock is never touched from another thread
+struct PerformClock(Clock);
+unsafe impl Send for PerformClock {}
+
+impl AtsSinNoiProcessor {
+    //output the envelope once an interval of blocks has passed
+    fn tick_env(&mut self, frames: usize) {
+        let interval = self.env_interval.load(LOAD_ORDERING);
+        if interval <= 0f64 || self.sample_rate <= 0f64 {
+            self.env_elapsed = 0f64;
+            return;
+        }
+        self.env_elapsed += frames as f64 * 1000f64 / self.sample_rate;
+        if self.env_elapsed >= interval {
+            self.env_elapsed = 0f64;
+            self.env_clock.0.delay(0f64);
+        }
     }
 
-    pub fn freq_add(&mut self, v: f64) {
-        self.freq_add.store(v, STORE_ORDERING);
+    //note when the position leaves the data, or jumps back by more than half of it
+    fn track_pos(&mut self, secs: f64, pmul: f64, last: f64) {
+        let pos = secs * pmul;
+        let event = pos_event(self.last_pos, pos, last);
+        if event != EVENT_NONE {
+            self.pos_event_at.store(secs, STORE_ORDERING);
+            self.pos_event.store(event, STORE_ORDERING);
+            self.event_clock.0.delay(0f64);
+        }
+        self.last_pos = Some(pos);
     }
 
-    pub fn amp_mul(&mut self, v: f64) {
-        self.amp_mul.store(v, STORE_ORDERING);
+    //seed each synth's noise from the seed and its index
+    fn seed_bank(&mut self) {
+        if let Some(seed) = self.seed {
+            for (i, s) in self.bank.synths.iter_mut().enumerate() {
+                s.seed(seed.wrapping_add(i as u64));
+            }
+        }
     }
 
-    pub fn noise_amp_mul(&mut self, v: f64) {
-        self.noise_amp_mul.store(v, STORE_ORDERING);
+    fn reset(&mut self) {
+        self.last_pos = None;
+        self.ab_pending = false;
+        for v in self.bank.voices.iter_mut() {
+            v.partial = None;
+        }
     }
 
-    pub fn noise_bw_scale(&mut self, v: f64) {
-        self.noise_bw_scale.store(v, STORE_ORDERING);
+    //swap the a/b datasets, the voices fade back in
+    fn swap_ab(&mut self) {
+        std::mem::swap(&mut self.current, &mut self.alt);
+        self.reset();
+        self.ab_fade_in = true;
     }
 
-    pub fn new() -> (Self, ParitalSynth) {
-        let freq_mul = Arc::new(Atomic::new(1f64));
-        let freq_add = Arc::new(Atomic::new(0f64));
-        let amp_mul = Arc::new(Atomic::new(1f64));
-        let noise_amp_mul = Arc::new(Atomic::new(1f64));
-        let noise_bw_scale = Arc::new(Atomic::new(0.1f64));
-        (
-            Self {
-                freq_mul: freq_mul.clone(),
-                freq_add: freq_add.clone(),
-                amp_mul: amp_mul.clone(),
-                noise_amp_mul: noise_amp_mul.clone(),
-                noise_bw_scale: noise_bw_scale.clone(),
-            },
-            ParitalSynth::new(freq_mul, freq_add, amp_mul, noise_amp_mul, noise_bw_scale),
-        )
+    //a block with no output, pending dataset changes happen right away
+    fn idle(&mut self, fade_out: bool) {
+        self.report(0f64, 0f64);
+        if fade_out {
+            self.swap_ab();
+        }
+        if let Some(next) = self.next.take() {
+            self.change(next);
+        }
+    }
+
+    fn change(&mut self, data: Option<Arc<AtsData>>) {
+        self.current = data;
+        self.alt = None;
+        self.reset();
+    }
+
+    //pd's rate can change while dsp is off, pick it up on the next block, the processor doesn't
+    //see the signal's own rate so block~ oversampling comes from the oversample message
+    fn check_sample_rate(&mut self) {
+        let sample_rate = pd_ext::pd::sample_rate() as f64 * self.oversample.load(LOAD_ORDERING);
+        if sample_rate != self.sample_rate && sample_rate > 0f64 {
+            self.sample_rate = sample_rate;
+            for s in self.bank.synths.iter_mut() {
+                s.set_sample_rate(sample_rate);
+            }
+            if let Some((_, d)) = &mut self.ducker {
+                d.set_sample_rate(sample_rate);
+            }
+        }
+    }
+
+    fn report(&self, sines: f64, noise: f64) {
+        self.env_sines.store(sines, STORE_ORDERING);
+        self.env_noise.store(noise, STORE_ORDERING);
+    }
+
+    //pick the loudest partials at the given position and hand them to voices, keeping existing
+    //assignments so that oscillators don't jump between partials
+    fn assign_top(&mut self, c: &AtsData, n: usize, start: usize, incr: usize, pos: f64) {
+        let (p0, p1, fract) = frame_pos(pos, c.frames.len());
+        let f0 = &c.frames[p0];
+        let f1 = &c.frames[p1];
+
+        //insertion sort into the n loudest
+        let chosen = &mut self.bank.chosen[0..n];
+        let mut len = 0;
+        for p in (start..c.partials()).step_by(incr) {
+            let a = lerp(f0[p].amp, f1[p].amp, fract);
+            if len == n && a <= chosen[n - 1].0 {
+                continue;
+            }
+            let mut i = if len < n {
+                len += 1;
+                len - 1
+            } else {
+                n - 1
+            };
+            while i > 0 && chosen[i - 1].0 < a {
+                chosen[i] = chosen[i - 1];
+                i -= 1;
+            }
+            chosen[i] = (a, p);
+        }
+        let chosen = &chosen[0..len];
+
+        for v in self.bank.voices.iter_mut() {
+            if let Some(p) = v.partial {
+                v.target = if chosen.iter().any(|c| c.1 == p) {
+                    1f64
+                } else {
+                    0f64
+                };
+            }
+        }
+        for (_, p) in chosen.iter() {
+            if self.bank.voices.iter().any(|v| v.partial == Some(*p)) {
+                continue;
+            }
+            if let Some(v) = self.bank.voices.iter_mut().find(|v| v.partial.is_none()) {
+                v.partial = Some(*p);
+                v.gain = 0f64;
+                v.target = 1f64;
+            }
+        }
     }
 }
 
-impl ParitalSynth {
-    fn new(
-        freq_mul: ArcAtomic<f64>,
-        freq_add: ArcAtomic<f64>,
-        amp_mul: ArcAtomic<f64>,
-        noise_amp_mul: ArcAtomic<f64>,
-        noise_bw_scale: ArcAtomic<f64>,
-    ) -> Self {
-        Self {
-            phase_freq_mul: 1f64 / pd_ext::pd::sample_rate() as f64,
-            phase: 0.into(),
-            noise_phase: 0.into(),
-            noise_x0: noise(),
-            noise_x1: noise(),
+//a pointer into a buffer that threads share, each using a separate range of it
+struct Unshared<T>(*mut T);
+
+unsafe impl<T> Sync for Unshared<T> {}
+
+impl<T> Unshared<T> {
+    unsafe fn slice<'a>(&self, range: &std::ops::Range<usize>) -> &'a mut [T] {
+        std::slice::from_raw_parts_mut(self.0.add(range.start), range.len())
+    }
+}
+
+//a looped range of the data, in frames, positions past the end wrap back to the start. the
+//data crossfades over the last xfade frames towards the frames before the start, so the wrap is
+//seamless
+#[derive(Clone, Copy)]
+struct LoopRegion {
+    start: f64,
+    end: f64,
+    xfade: f64,
+    //played backwards, wrapping at the start and crossfading with frames after the end
+    reverse: bool,
+}
 
-            freq_mul: Slewed::new(freq_mul, 0.001f64),
-            freq_add: Slewed::new(freq_add, 1f64),
-            amp_mul: Slewed::new(amp_mul, 0.001f64),
-            noise_amp_mul: Slewed::new(noise_amp_mul, 0.001f64),
-            noise_bw_scale: Slewed::new(noise_bw_scale, 0.001f64),
+impl LoopRegion {
+    fn wrap(&self, pos: f64) -> f64 {
+        let len = self.end - self.start;
+        if self.reverse {
+            if pos < self.start {
+                self.end - (self.end - pos) % len
+            } else {
+                pos
+            }
+        } else if pos >= self.end {
+            self.start + (pos - self.start) % len
+        } else {
+            pos
         }
     }
 
-    pub fn slew(&mut self) {
-        self.freq_mul.update();
-        self.freq_add.update();
-        self.amp_mul.update();
-        self.noise_amp_mul.update();
-        self.noise_bw_scale.update();
+    //the position to blend towards and how far, near the end, or the start in reverse
+    fn blend(&self, pos: f64) -> Option<(f64, f64)> {
+        let len = self.end - self.start;
+        if self.xfade <= 0f64 {
+            None
+        } else if self.reverse {
+            let from = self.start + self.xfade;
+            if pos < from && pos > self.start {
+                Some((pos + len, (from - pos) / self.xfade))
+            } else {
+                None
+            }
+        } else {
+            let from = self.end - self.xfade;
+            if pos > from && pos < self.end {
+                Some((pos - len, (pos - from) / self.xfade))
+            } else {
+                None
+            }
+        }
     }
+}
 
-    pub fn synth(&mut self, freq: f64, sin_amp: f64, noise_energy: f64) -> f32 {
-        self.slew();
+//the data position, in frames, to play for an input position, mirrored in reverse, then wrapped
+//by the loop and mapped by the oob mode, past the end holds the last frame with hold_end
+fn map_position(
+    p: f64,
+    last: f64,
+    reverse: bool,
+    loop_region: Option<LoopRegion>,
+    hold_end: bool,
+    oob: OobMode,
+) -> Option<f64> {
+    let p = if reverse { last - p } else { p };
+    let p = loop_region.map_or(p, |l| l.wrap(p));
+    if hold_end && reverse && p <= 0f64 {
+        Some(0f64)
+    } else if hold_end && !reverse && p >= last {
+        Some(last)
+    } else if reverse && p == last {
+        //the mirror of position 0
+        Some(last)
+    } else {
+        oob.apply(p, last)
+    }
+}
 
-        //apply transformations
-        //should freq scaling affect noise bandwidth and offset?
-        let freq = freq * self.freq_mul.val() + self.freq_add.val();
-        let sin_amp = self.amp_mul.val() * sin_amp;
-        let noise_energy = noise_energy * self.noise_amp_mul.val();
+//the event, if any, for the position input moving from prev to pos, in either direction
+fn pos_event(prev: Option<f64>, pos: f64, last: f64) -> usize {
+    match prev {
+        Some(p) if p < 0f64 => EVENT_NONE,
+        //running backwards reaches the start, a jump there from far away is a wrap
+        Some(p) if p > 0f64 && pos == 0f64 && p - pos < last * 0.5f64 => EVENT_DONE,
+        _ if pos < 0f64 => EVENT_OOB,
+        Some(p) if p < last && pos >= last => EVENT_DONE,
+        Some(p) if pos < last && (p - pos).abs() > last * 0.5f64 => EVENT_LOOP,
+        _ => EVENT_NONE,
+    }
+}
 
-        //TODO if freq > 500 { 1 } else { 0.25 } * bw...
-        let noise_bw = freq * self.noise_bw_scale.val();
+//the frames around a position
+struct Around<'a> {
+    fm: &'a [Peak],
+    f0: &'a [Peak],
+    f1: &'a [Peak],
+    f2: &'a [Peak],
+    fract: f64,
+}
 
-        self.phase = (self.phase + freq * self.phase_freq_mul).fract();
-        self.noise_phase = self.noise_phase + noise_bw * self.phase_freq_mul;
-        if self.noise_phase >= 1f64 {
-            self.noise_phase = self.noise_phase.fract();
-            self.noise_x0 = self.noise_x1;
-            self.noise_x1 = noise();
+impl<'a> Around<'a> {
+    fn new(c: &'a AtsData, pos: f64) -> Self {
+        let (p0, p1, fract) = frame_pos(pos, c.frames.len());
+        Self {
+            fm: &c.frames[p0.saturating_sub(1)],
+            f0: &c.frames[p0],
+            f1: &c.frames[p1],
+            f2: &c.frames[std::cmp::min(p1 + 1, c.frames.len() - 1)],
+            fract,
         }
+    }
 
-        let sin = (2f64 * std::f64::consts::PI * self.phase).sin();
-        let noise = lerp(self.noise_x0, self.noise_x1, self.noise_phase);
+    fn at(&self, interp: Interp, p: usize, get: fn(&Peak) -> f64) -> f64 {
+        interp.apply(
+            &[
+                get(&self.fm[p]),
+                get(&self.f0[p]),
+                get(&self.f1[p]),
+                get(&self.f2[p]),
+            ],
+            self.fract,
+        )
+    }
 
-        (sin * sin_amp + noise * sin * noise_energy) as f32
+    //amplitudes follow interp unless another curve is chosen
+    fn amp_at(
+        &self,
+        interp: Interp,
+        amp_interp: AmpInterp,
+        p: usize,
+        get: fn(&Peak) -> f64,
+    ) -> f64 {
+        match amp_interp {
+            AmpInterp::Follow => self.at(interp, p, get),
+            m => m.apply(get(&self.f0[p]), get(&self.f1[p]), self.fract),
+        }
     }
 }
 
-pub struct AtsSinNoiProcessor {
-    current: Option<Arc<AtsData>>,
-    data_recv: Receiver<Option<Arc<AtsData>>>,
-    incr: ArcAtomic<usize>,
-    offset: ArcAtomic<usize>,
-    limit: ArcAtomic<usize>,
-    synths: Box<[ParitalSynth]>,
+//the parameters and inputs of a block, shared by the threads that synthesize it
+struct Block<'a> {
+    data: &'a AtsData,
+    with_noise: bool,
+    pmul: f64,
+    last: f64,
+    oob: OobMode,
+    hold_end: bool,
+    reverse: bool,
+    hold_freq: bool,
+    hold_amp: bool,
+    hysteresis: Option<AmpHysteresis>,
+    //one pole coefficient for blur
+    blur: Option<f64>,
+    //the amplitude partials are born above, and samples per millisecond for their fades
+    birth_amp: f64,
+    ms_samples: f64,
+    //per sample step of the voice gain ramps
+    voice_inc: f64,
+    loop_region: Option<LoopRegion>,
+    interp: Interp,
+    amp_interp: AmpInterp,
+    freq_min: f64,
+    freq_max: f64,
+    duck_amount: f64,
+    band_gain: [f64; NOISE_BANDS],
+    noise_width: f64,
+    tilt: f64,
+    tilt_pivot: f64,
+    eq_loudness: f64,
+    scale: &'a Scale,
+    quantize: f64,
+    harmonic_f0: f64,
+    harmonic_strength: f64,
+    transpose: f64,
+    stereo: bool,
+    //the sine for process_lanes
+    osc: &'a Osc,
+    pos: &'a [pd_sys::t_float],
+    freq_mul: Option<&'a [pd_sys::t_float]>,
+    amp_mul: Option<&'a [pd_sys::t_float]>,
+    //the gate input and its last value from the previous block
+    gate: Option<(&'a [pd_sys::t_float], pd_sys::t_float)>,
+}
+
+impl<'a> Block<'a> {
+    //positions outside of the data mapped by the oob mode, past the end holds the last frame with
+    //hold_end
+    fn apply_oob(&self, p: f64) -> Option<f64> {
+        map_position(
+            p,
+            self.last,
+            self.reverse,
+            self.loop_region,
+            self.hold_end,
+            self.oob,
+        )
+    }
+
+    //synthesize a range of the bank into left and right, right is empty for mono, returning the
+    //summed squares of the sine and noise amplitudes
+    fn synth(
+        &self,
+        synths: &mut [ParitalSynth],
+        voices: &mut [Voice],
+        gated: &[bool],
+        lanes: (&mut [f64], &mut [f64], &mut [f64]),
+        mut ducker: Option<(&[pd_sys::t_float], &mut Ducker)>,
+        left: &mut [pd_sys::t_float],
+        right: &mut [pd_sys::t_float],
+    ) -> (f64, f64) {
+        let c = self.data;
+        let (lane_phases, lane_incs, lane_amps) = lanes;
+        let lanes = cfg!(feature = "simd") && !self.stereo;
+        let mut env = (0f64, 0f64);
+        for i in 0..left.len() {
+            if let Some((g, prev)) = self.gate {
+                let prev = if i == 0 { prev } else { g[i - 1] };
+                if g[i] > 0f32 && prev <= 0f32 {
+                    for s in synths.iter_mut() {
+                        s.restart();
+                    }
+                    for p in lane_phases.iter_mut() {
+                        *p = 0f64;
+                    }
+                }
+            }
+            if let Some((input, bank)) = ducker.as_mut() {
+                bank.process(input[i] as f64);
+            }
+            let freq_mul = self.freq_mul.map_or(1f64, |v| v[i] as f64);
+            let amp_mul = self.amp_mul.map_or(1f64, |v| v[i] as f64);
+            let (pos, in_range) = match self.apply_oob((self.pos[i] as f64) * self.pmul) {
+                Some(p) => (p, true),
+                None => (0f64, false),
+            };
+            let here = Around::new(c, pos);
+            let there = self
+                .loop_region
+                .and_then(|l| l.blend(pos))
+                .map(|(p, x)| (Around::new(c, p), x));
+            let mut l = 0 as pd_sys::t_float;
+            let mut r = 0 as pd_sys::t_float;
+            //skipped and sleeping voices hold their phase
+            if lanes {
+                for (i, a) in lane_incs.iter_mut().zip(lane_amps.iter_mut()) {
+                    *i = 0f64;
+                    *a = 0f64;
+                }
+            }
+            for (j, (s, v, g)) in izip!(synths.iter_mut(), voices.iter_mut(), gated.iter()).enumerate() {
+                //keep ramping skipped voices so they don't resume with a stale gain
+                v.ramp(self.voice_inc);
+                let p = match v.partial {
+                    Some(p) if !*g && !s.muted() => p,
+                    _ => continue,
+                };
+                let at = |get: fn(&Peak) -> f64| {
+                    let v = here.at(self.interp, p, get);
+                    there
+                        .as_ref()
+                        .map_or(v, |(t, x)| lerp(v, t.at(self.interp, p, get), *x))
+                };
+                let amp_at = |get: fn(&Peak) -> f64| {
+                    let v = here.amp_at(self.interp, self.amp_interp, p, get);
+                    there.as_ref().map_or(v, |(t, x)| {
+                        lerp(v, t.amp_at(self.interp, self.amp_interp, p, get), *x)
+                    })
+                };
+                let p0 = &here.f0[p];
+                let f = v.hold_freq(self.hold_freq, p, at(|q| q.freq));
+                let (amp, noise) = v.hold_amp(
+                    self.hold_amp,
+                    p,
+                    amp_at(|q| q.amp),
+                    if self.with_noise {
+                        amp_at(|q| q.noise_energy.unwrap())
+                    } else {
+                        0f64
+                    },
+                );
+                let (f, amp, noise) = v.blur(self.blur, p, (f, amp, noise));
+                let analysed = f;
+                let f = harmonize(f, self.harmonic_f0, self.harmonic_strength);
+                let f = self.scale.quantize(f, self.quantize) * freq_mul * self.transpose;
+                //the range applies to what is heard, after every transform
+                let heard = s.transformed_freq(f);
+                let (a, n) = if in_range && heard >= self.freq_min && heard <= self.freq_max {
+                    let fades = s.fades();
+                    let fades = (fades.0 * self.ms_samples, fades.1 * self.ms_samples);
+                    //samples until the next frame at the data's own rate
+                    let until = (1f64 - here.fract) * self.ms_samples * 1000f64 / self.pmul;
+                    let g = v.gain
+                        * v.hysteresis(self.hysteresis, self.voice_inc, p, amp)
+                        * v.birth(self.birth_amp, fades, p, amp, (here.f1[p].amp, until))
+                        * if let Some((_, d)) = ducker.as_ref() {
+                            d.gain(p0.band, self.duck_amount)
+                        } else {
+                            1f64
+                        }
+                        * if self.tilt != 0f64 && analysed > 0f64 {
+                            (analysed / self.tilt_pivot).powf(self.tilt)
+                        } else {
+                            1f64
+                        };
+                    (
+                        g * amp_mul * amp.max(0f64),
+                        g * self.band_gain[p0.band] * noise.max(0f64),
+                    )
+                } else {
+                    (0f64, 0f64)
+                };
+                let (a, n) = if self.eq_loudness > 0f64 {
+                    let g = eq_loudness_gain(analysed, heard, self.eq_loudness);
+                    (a * g, n * g)
+                } else {
+                    (a, n)
+                };
+                //silent voices sleep, their oscillators resume where they stopped
+                if a == 0f64 && n == 0f64 {
+                    continue;
+                }
+                env.0 += a * a;
+                env.1 += n * n;
+                if self.stereo {
+                    let (sl, sr) = s.synth_stereo(f, a, n, self.noise_width);
+                    l = l + sl;
+                    r = r + sr;
+                } else if lanes {
+                    let (inc, amp) = s.synth_lane(f, a, n);
+                    lane_incs[j] = inc;
+                    lane_amps[j] = amp;
+                } else {
+                    l = l + s.synth(f, a, n);
+                }
+            }
+            if lanes {
+                l = l + process_lanes(lane_phases, lane_incs, lane_amps, self.osc) as f32;
+            }
+            left[i] = l;
+            if self.stereo {
+                right[i] = r;
+            }
+        }
+        env
+    }
 }
 
 impl SignalProcessor for AtsSinNoiProcessor {
     fn process(
         &mut self,
-        _frames: usize,
+        frames: usize,
         inputs: &[&mut [pd_sys::t_float]],
         outputs: &mut [&mut [pd_sys::t_float]],
     ) {
+        let _ftz = FlushDenormals::new();
+        self.check_sample_rate();
+        self.tick_env(frames);
         let mut cnt = 0;
-        while let Ok(c) = self.data_recv.try_recv() {
-            self.current = c;
+        while let Ok(u) = self.updates.try_recv() {
+            match u {
+                //fade out before changing, unless the crossfade is disabled
+                Update::Data(c) => {
+                    if self.xfade.load(LOAD_ORDERING) > 0f64 && self.current.is_some() {
+                        self.next = Some(c);
+                    } else {
+                        self.change(c);
+                        self.level = 0f64;
+                    }
+                }
+                //voices keep their partials unless the partial count changes, during a
+                //crossfade the target replaces the data being faded to
+                Update::Target(c) => {
+                    let same = self.current.as_ref().map(|d| d.partials()) == Some(c.partials());
+                    if self.next.is_some() {
+                        self.next = Some(Some(c));
+                    } else if same && self.alt.is_none() {
+                        self.current = Some(c);
+                    } else {
+                        self.change(Some(c));
+                    }
+                }
+                Update::Ab(a, b) => {
+                    self.current = a;
+                    self.alt = b;
+                    self.reset();
+                }
+                Update::Toggle => self.ab_pending = self.alt.is_some(),
+                Update::Note(Some(transpose)) => {
+                    self.transpose = (transpose / 12f64).exp2();
+                    self.adsr.get_or_insert_with(Adsr::default).on();
+                }
+                Update::Note(None) => {
+                    if let Some(a) = self.adsr.as_mut() {
+                        a.off();
+                    }
+                }
+                //the oscillators restart too so that the output repeats exactly
+                Update::Seed(seed) => {
+                    self.seed = Some(seed);
+                    self.seed_bank();
+                    for s in self.bank.synths.iter_mut() {
+                        s.restart();
+                    }
+                    self.bank.lanes.restart();
+                }
+                Update::Select(offset, incr, limit) => {
+                    self.offset.store(offset, STORE_ORDERING);
+                    self.incr.store(incr, STORE_ORDERING);
+                    self.limit.store(limit, STORE_ORDERING);
+                }
+                Update::Bank(b) => {
+                    let old = std::mem::replace(&mut self.bank, b);
+                    let _ = self.trash.try_send(old);
+                    //the bank was built at pd's rate
+                    for s in self.bank.synths.iter_mut() {
+                        s.set_sample_rate(self.sample_rate);
+                    }
+                    self.seed_bank();
+                }
+                Update::Scale(s) => self.scale = s,
+            }
             cnt = cnt + 1;
             if cnt > DSP_RECV_MAX {
                 break;
@@ -201,82 +1030,420 @@ impl SignalProcessor for AtsSinNoiProcessor {
         }
 
         let mut clear = || {
-            for out in outputs[0].iter_mut() {
-                *out = 0f32.into();
+            for out in outputs.iter_mut() {
+                for v in out.iter_mut() {
+                    *v = 0f32.into();
+                }
             }
         };
 
-        if let Some(c) = &self.current {
+        if let Some(c) = self.current.clone().filter(|c| !c.frames.is_empty()) {
             let with_noise = c.has_noise();
             let pmul = c.header.fra / c.header.dur;
 
+            //silent while the position stays outside of the data, skip the parameter loads and
+            //the synth loop entirely
+            let last = c.frames.len().saturating_sub(1) as f64;
+            let end = inputs[0][inputs[0].len() - 1] as f64;
+
+            //an a/b toggle fades out the block that crosses a frame boundary, or the next block if
+            //the position is still, and swaps at its end
+            let fade_out = self.ab_pending
+                && match self.last_pos {
+                    Some(p) => p.floor() != (end * pmul).floor() || p == end * pmul,
+                    None => true,
+                };
+            let fade_in = std::mem::replace(&mut self.ab_fade_in, false);
+            self.track_pos(end, pmul, last);
+            let oob = self.oob_mode.load(LOAD_ORDERING);
+            let hold_end = self.hold_end.load(LOAD_ORDERING);
+            let hold_freq = self.hold_freq.load(LOAD_ORDERING);
+            let hold_amp = self.hold_amp.load(LOAD_ORDERING);
+            let reverse = self.reverse.load(LOAD_ORDERING);
+            let loop_region = if self.loop_on.load(LOAD_ORDERING) {
+                let start = (self.loop_start.load(LOAD_ORDERING) * 0.001f64 * pmul).max(0f64);
+                let end = (self.loop_end.load(LOAD_ORDERING) * 0.001f64 * pmul).min(last);
+                //the crossfade reads frames before the start, or after the end in reverse, so it
+                //can't be longer than that
+                let xfade = (self.loop_xfade.load(LOAD_ORDERING) * 0.001f64 * pmul)
+                    .min(if reverse { last - end } else { start })
+                    .min(end - start);
+                if end > start {
+                    Some(LoopRegion {
+                        start,
+                        end,
+                        xfade,
+                        reverse,
+                    })
+                } else {
+                    None
+                }
+            } else {
+                None
+            };
+            //positions outside of the data mapped by the oob mode, past the end holds the last
+            //frame with hold_end
+            let apply_oob = |p: f64| map_position(p, last, reverse, loop_region, hold_end, oob);
+            let map_pos = |p: f64| apply_oob(p).unwrap_or(p);
+            if inputs[0]
+                .iter()
+                .all(|p| apply_oob(*p as f64 * pmul).is_none())
+            {
+                clear();
+                self.idle(fade_out);
+                return;
+            }
+
             let start = self.offset.load(LOAD_ORDERING);
             let incr = self.incr.load(LOAD_ORDERING);
             let limit = self.limit.load(LOAD_ORDERING);
+            let top = self.top.load(LOAD_ORDERING);
+            let freq_min = self.freq_min.load(LOAD_ORDERING);
+            let freq_max = self.freq_max.load(LOAD_ORDERING);
+            let amp_gate = self.amp_gate.load(LOAD_ORDERING);
+            let blur = self.blur.load(LOAD_ORDERING);
+            let blur = if blur > 0f64 {
+                let samples = blur / pmul * self.sample_rate;
+                Some(1f64 - (-1f64 / samples).exp())
+            } else {
+                None
+            };
+            let amp_on = self.amp_on.load(LOAD_ORDERING);
+            let hysteresis = if amp_on > 0f64 {
+                Some(AmpHysteresis {
+                    on: amp_on,
+                    off: self.amp_off.load(LOAD_ORDERING).min(amp_on),
+                    min_on: (self.amp_min_on.load(LOAD_ORDERING) * self.sample_rate / 1000f64)
+                        as usize,
+                })
+            } else {
+                None
+            };
+            let duck_amount = self.duck_amount.load(LOAD_ORDERING);
+            let mut band_gain = [1f64; NOISE_BANDS];
+            for (g, v) in band_gain.iter_mut().zip(self.band_gain.iter()) {
+                *g = v.load(LOAD_ORDERING);
+            }
+            let noise_width = self.noise_width.load(LOAD_ORDERING);
+            let interp = self.interp.load(LOAD_ORDERING);
+            let amp_interp = self.amp_interp.load(LOAD_ORDERING);
+            //tilt as an exponent of f / pivot
+            let tilt = self.tilt.load(LOAD_ORDERING) / (20f64 * 2f64.log10());
+            let tilt_pivot = self.tilt_pivot.load(LOAD_ORDERING);
+            let eq_loudness = self.eq_loudness.load(LOAD_ORDERING);
+            let quantize = self.quantize.load(LOAD_ORDERING);
+            let harmonic_f0 = self.harmonic_f0.load(LOAD_ORDERING);
+            let harmonic_strength = self.harmonic_strength.load(LOAD_ORDERING);
             let count = c.partials();
             if start >= count {
                 clear();
+                self.idle(fade_out);
                 return;
             };
             let count = count - start;
             let count = count / incr + if (count % incr) > 0 { 1 } else { 0 };
 
             //total partials to synthesize
-            let count = std::cmp::min(count, std::cmp::min(limit, self.synths.len()));
+            let count = std::cmp::min(count, std::cmp::min(limit, self.bank.synths.len()));
 
-            if count == 0 {
-                clear();
+            if top > 0 && self.bank.synths.len() > 0 {
+                let n = std::cmp::min(top, self.bank.synths.len());
+                let pos = map_pos(inputs[0][0] as f64 * pmul);
+                self.assign_top(&c, n, start, incr, pos);
             } else {
-                //end (exclusive) of partial data to synth
-                let end = std::cmp::min(count * incr + start, c.partials());
-                //indexes of partials (step_by later)
-                let range = start..end;
-
-                let synths = &mut self.synths[0..count];
-                let frames = c.frames.len() as isize;
-                for (out, pos) in outputs[0].iter_mut().zip(inputs[0].iter()) {
-                    let pos = (*pos as f64) * pmul;
-                    let mut p0 = pos.floor() as isize;
-                    let mut fract = 0f64;
-                    let mut in_range = false;
-                    if p0 < 0 {
-                        p0 = 0;
-                    } else if p0 + 1 >= frames {
-                        p0 = frames - 2;
-                        fract = 1f64;
+                for (i, v) in self.bank.voices.iter_mut().enumerate() {
+                    v.partial = if i < count {
+                        Some(start + i * incr)
+                    } else {
+                        None
+                    };
+                    v.gain = 1f64;
+                    v.target = 1f64;
+                }
+            }
+
+            if self.bank.voices.iter().all(|v| v.partial.is_none()) {
+                clear();
+                self.idle(fade_out);
+                return;
+            }
+
+            let synths = &mut self.bank.synths;
+            let voices = &mut self.bank.voices;
+            let gated = &mut self.bank.gated;
+            let lanes = &mut self.bank.lanes;
+            let osc = &lanes.osc;
+            let (lane_phases, lane_incs, lane_amps) = (&mut lanes.phases, &mut lanes.incs, &mut lanes.amps);
+            let ducker = &mut self.ducker;
+            if let Some((_, d)) = ducker {
+                d.set_times(
+                    self.duck_attack.load(LOAD_ORDERING),
+                    self.duck_release.load(LOAD_ORDERING),
+                );
+            }
+            let frames = c.frames.len() as isize;
+
+            //find the max amplitude of each partial over the frames this block spans
+            if amp_gate > 0f64 {
+                let (fmin, fmax) = inputs[0].iter().fold((frames, 0isize), |(lo, hi), p| {
+                    let p = map_pos((*p as f64) * pmul).floor() as isize;
+                    (std::cmp::min(lo, p), std::cmp::max(hi, p + 1))
+                });
+                let fmin = std::cmp::max(fmin, 0) as usize;
+                let fmax = std::cmp::min(fmax, frames - 1) as usize;
+                for (v, g) in voices.iter().zip(gated.iter_mut()) {
+                    *g = if let Some(p) = v.partial {
+                        fmin > fmax || c.frames[fmin..=fmax].iter().all(|f| f[p].amp < amp_gate)
                     } else {
-                        fract = pos.fract();
-                        in_range = true;
+                        true
+                    };
+                }
+            } else {
+                for g in gated.iter_mut() {
+                    *g = false;
+                }
+            }
+
+            let report_phase = self.report_phase.load(LOAD_ORDERING);
+            for (s, p) in synths.iter_mut().zip(lane_phases.iter()) {
+                s.load();
+                if report_phase {
+                    //process_lanes keeps the phases with the simd feature
+                    if cfg!(feature = "simd") && outputs.len() == 1 {
+                        s.set_phase(*p);
                     }
-                    let p0 = p0 as usize;
-
-                    let f0 = &c.frames[p0];
-                    let f1 = &c.frames[p0 + 1];
-                    *out = 0 as pd_sys::t_float;
-                    for (s, p0, p1) in izip!(
-                        synths.iter_mut(),
-                        f0[range.clone()].iter().step_by(incr),
-                        f1[range.clone()].iter().step_by(incr)
-                    ) {
-                        let f = lerp(p0.freq, p1.freq, fract);
-                        let (a, n) = if in_range {
+                    s.report_phase();
+                }
+            }
+
+            let gate_prev = self.gate_prev;
+            let block = Block {
+                data: &c,
+                with_noise,
+                pmul,
+                last,
+                oob,
+                hold_end,
+                reverse,
+                hold_freq,
+                hold_amp,
+                hysteresis,
+                blur,
+                birth_amp: self.birth_amp.load(LOAD_ORDERING),
+                ms_samples: self.sample_rate / 1000f64,
+                voice_inc: 1000f64 / (VOICE_RAMP_MS * self.sample_rate),
+                loop_region,
+                interp,
+                amp_interp,
+                freq_min,
+                freq_max,
+                duck_amount,
+                band_gain,
+                noise_width,
+                tilt,
+                tilt_pivot,
+                eq_loudness,
+                scale: &self.scale,
+                quantize,
+                harmonic_f0,
+                harmonic_strength,
+                transpose: self.transpose,
+                stereo: outputs.len() > 1,
+                osc,
+                pos: &inputs[0],
+                freq_mul: self.sig_freq_mul.map(|v| &*inputs[v]),
+                amp_mul: self.sig_amp_mul.map(|v| &*inputs[v]),
+                gate: self.gate.map(|v| (&*inputs[v], gate_prev)),
+            };
+            let n = outputs[0].len();
+            let (left, right) = outputs.split_at_mut(1);
+            let left = &mut *left[0];
+            let right: &mut [pd_sys::t_float] = if block.stereo {
+                &mut *right[0]
+            } else {
+                &mut []
+            };
+
+            //the ducker runs per sample alongside the synths so it keeps the block on one thread
+            let threads = match &self.pool {
+                Some(p) if ducker.is_none() && n <= THREAD_BLOCK_MAX => p.threads(),
+                _ => 1,
+            };
+            let env = if threads > 1 {
+                let len = synths.len();
+                let chunk = |t: usize| len * t / threads..len * (t + 1) / threads;
+                let synths = Unshared(synths.as_mut_ptr());
+                let voices = Unshared(voices.as_mut_ptr());
+                let lane_phases = Unshared(lane_phases.as_mut_ptr());
+                let lane_incs = Unshared(lane_incs.as_mut_ptr());
+                let lane_amps = Unshared(lane_amps.as_mut_ptr());
+                let thread_out = Unshared(self.thread_out.as_mut_ptr());
+                let thread_env = Unshared(self.thread_env.as_mut_ptr());
+                //the calling thread writes directly to the outlets
+                let mut main_out = (&mut *left, &mut *right);
+                let outs = Unshared(
+                    &mut main_out as *mut (&mut [pd_sys::t_float], &mut [pd_sys::t_float]),
+                );
+                let gated = &gated[..];
+                let block = &block;
+                self.pool.as_ref().unwrap().run(&|t| {
+                    //the flags are per thread
+                    let _ftz = FlushDenormals::new();
+                    let range = chunk(t);
+                    //each thread only touches its own range of the bank and its own output
+                    unsafe {
+                        let (l, r) = if t == 0 {
+                            let o = &mut *outs.0;
+                            (&mut *o.0, &mut *o.1)
+                        } else {
+                            let o = std::slice::from_raw_parts_mut(
+                                thread_out.0.add((t - 1) * 2 * THREAD_BLOCK_MAX),
+                                2 * THREAD_BLOCK_MAX,
+                            );
+                            let (l, r) = o.split_at_mut(THREAD_BLOCK_MAX);
                             (
-                                lerp(p0.amp, p1.amp, fract),
-                                if with_noise {
-                                    lerp(p0.noise_energy.unwrap(), p1.noise_energy.unwrap(), fract)
+                                &mut l[..n],
+                                if block.stereo {
+                                    &mut r[..n]
                                 } else {
-                                    0f64
+                                    &mut r[..0]
                                 },
                             )
-                        } else {
-                            (0f64, 0f64)
                         };
-                        *out = *out + s.synth(f, a, n);
+                        let env = block.synth(
+                            synths.slice(&range),
+                            voices.slice(&range),
+                            &gated[range.clone()],
+                            (
+                                lane_phases.slice(&range),
+                                lane_incs.slice(&range),
+                                lane_amps.slice(&range),
+                            ),
+                            None,
+                            l,
+                            r,
+                        );
+                        *thread_env.0.add(t) = env;
                     }
+                });
+                for t in 1..threads {
+                    let o =
+                        &self.thread_out[(t - 1) * 2 * THREAD_BLOCK_MAX..t * 2 * THREAD_BLOCK_MAX];
+                    for (out, v) in left.iter_mut().zip(o[..n].iter()) {
+                        *out = *out + *v;
+                    }
+                    for (out, v) in right.iter_mut().zip(o[THREAD_BLOCK_MAX..].iter()) {
+                        *out = *out + *v;
+                    }
+                }
+                self.thread_env[..threads]
+                    .iter()
+                    .fold((0f64, 0f64), |e, t| (e.0 + t.0, e.1 + t.1))
+            } else {
+                block.synth(
+                    synths,
+                    voices,
+                    gated,
+                    (lane_phases, lane_incs, lane_amps),
+                    ducker.as_mut().map(|(d, bank)| (&*inputs[*d], bank)),
+                    left,
+                    right,
+                )
+            };
+
+            //output gain, per sample
+            let xfade = self.xfade.load(LOAD_ORDERING) * self.sample_rate / 1000f64;
+            let level_inc = if xfade > 0f64 { 1f64 / xfade } else { 1f64 };
+            let fading = self.next.is_some();
+            let mut level = self.level;
+            let release = self.gate_release.load(LOAD_ORDERING) * self.sample_rate / 1000f64;
+            let release_inc = if release > 0f64 { 1f64 / release } else { 1f64 };
+            let dc_coeff = if self.dcblock.load(LOAD_ORDERING) {
+                Some(DcBlock::coeff(self.sample_rate))
+            } else {
+                None
+            };
+            let softclip = self.softclip.load(LOAD_ORDERING);
+            let adsr = self.adsr_times.load(LOAD_ORDERING);
+            let sample_rate = self.sample_rate;
+            let samples = |ms: f64| ms * sample_rate / 1000f64;
+            let adsr = AdsrTimes {
+                attack: samples(adsr[0]),
+                decay: samples(adsr[1]),
+                sustain: adsr[2],
+                release: samples(adsr[3]),
+            };
+            //output stages after the gains
+            let dc = &mut self.dc;
+            let mut finish = |x: pd_sys::t_float, c: usize| {
+                let x = match dc_coeff {
+                    Some(k) => dc[c].process(x as f64, k),
+                    None => x as f64,
+                };
+                if softclip {
+                    soft_clip(x) as pd_sys::t_float
+                } else {
+                    x as pd_sys::t_float
+                }
+            };
+            for i in 0..n {
+                let gain = if let Some(v) = self.vca {
+                    inputs[v][i]
+                } else {
+                    1f32
+                };
+                if let Some(g) = self.gate {
+                    let v = inputs[g][i];
+                    if v > 0f32 && self.gate_prev <= 0f32 {
+                        self.gate_level = 1f64;
+                    } else if v <= 0f32 {
+                        self.gate_level = (self.gate_level - release_inc).max(0f64);
+                    }
+                    self.gate_prev = v;
+                }
+                level = if fading {
+                    (level - level_inc).max(0f64)
+                } else {
+                    (level + level_inc).min(1f64)
+                };
+                let env = self.adsr.as_mut().map_or(1f64, |a| a.process(&adsr));
+                let gain = gain
+                    * (level * self.gate_level * env) as f32
+                    * if fade_out {
+                        1f32 - (i + 1) as f32 / n as f32
+                    } else if fade_in {
+                        (i + 1) as f32 / n as f32
+                    } else {
+                        1f32
+                    };
+                left[i] = finish(left[i] * gain, 0);
+                if block.stereo {
+                    right[i] = finish(right[i] * gain, 1);
+                }
+            }
+
+            //release voices that have faded out
+            for v in voices.iter_mut() {
+                if v.target == 0f64 && v.gain <= 0f64 {
+                    v.partial = None;
+                }
+            }
+
+            //sum of the partial powers, a sinusoid of amplitude a has a mean square of a^2 / 2
+            let len = 2f64 * n as f64;
+            self.report((env.0 / len).sqrt(), (env.1 / len).sqrt());
+            if fade_out {
+                self.swap_ab();
+            }
+            self.level = level;
+            if level <= 0f64 {
+                if let Some(next) = self.next.take() {
+                    self.change(next);
                 }
             }
         } else {
             clear();
+            self.idle(false);
         }
     }
 }
@@ -286,14 +1453,82 @@ fn set_clamp_bottom(a: &mut ArcAtomic<usize>, v: pd_sys::t_float, b: isize) {
     a.store(v, STORE_ORDERING);
 }
 
+//map a -inf symbol to the floor so that dB lists can use it
+fn db_args(args: &[Atom]) -> Vec<Atom> {
+    args.iter()
+        .map(|a| match a.get_symbol() {
+            Some(s) if s == *NEG_INF => DB_FLOOR.into(),
+            _ => *a,
+        })
+        .collect()
+}
+
 pd_ext_macros::external! {
     #[name = "ats/sinnoi~"]
     pub struct AtsSinNoiExternal {
-        data_send: SyncSender<Option<Arc<AtsData>>>,
+        updates: SyncSender<Update>,
+        trash: Receiver<SynthBank>,
+        //the bank size bound when sizing from the data
+        auto: Option<usize>,
         offset: ArcAtomic<usize>,
         incr: ArcAtomic<usize>,
         limit: ArcAtomic<usize>,
+        top: ArcAtomic<usize>,
+        interp: ArcAtomic<Interp>,
+        amp_interp: ArcAtomic<AmpInterp>,
+        oob_mode: ArcAtomic<OobMode>,
+        hold_end: ArcAtomic<bool>,
+        hold_freq: ArcAtomic<bool>,
+        hold_amp: ArcAtomic<bool>,
+        reverse: ArcAtomic<bool>,
+        loop_on: ArcAtomic<bool>,
+        loop_start: ArcAtomic<f64>,
+        loop_end: ArcAtomic<f64>,
+        loop_xfade: ArcAtomic<f64>,
+        report_phase: ArcAtomic<bool>,
+        xfade: ArcAtomic<f64>,
+        freq_min: ArcAtomic<f64>,
+        freq_max: ArcAtomic<f64>,
+        amp_gate: ArcAtomic<f64>,
+        blur: ArcAtomic<f64>,
+        birth_amp: ArcAtomic<f64>,
+        amp_on: ArcAtomic<f64>,
+        amp_off: ArcAtomic<f64>,
+        amp_min_on: ArcAtomic<f64>,
+        duck_amount: ArcAtomic<f64>,
+        duck_attack: ArcAtomic<f64>,
+        duck_release: ArcAtomic<f64>,
+        noise_width: ArcAtomic<f64>,
+        band_gain: Box<[ArcAtomic<f64>]>,
+        gate_release: ArcAtomic<f64>,
+        oversample: ArcAtomic<f64>,
+        dcblock: ArcAtomic<bool>,
+        softclip: ArcAtomic<bool>,
+        adsr_times: ArcAtomic<[f64; 4]>,
+        tilt: ArcAtomic<f64>,
+        tilt_pivot: ArcAtomic<f64>,
+        eq_loudness: ArcAtomic<f64>,
+        quantize: ArcAtomic<f64>,
+        harmonic_f0: ArcAtomic<f64>,
+        harmonic_strength: ArcAtomic<f64>,
         handles: Box<[ParitalSynthHandle]>,
+        times: SlewTimes,
+        osc: Osc,
+        env_sines: ArcAtomic<f64>,
+        env_noise: ArcAtomic<f64>,
+        info_outlet: Box<dyn OutletSend>,
+        //dump_params output, messages that restore the transforms when sent back
+        params_outlet: Box<dyn OutletSend>,
+        pos_event: ArcAtomic<usize>,
+        pos_event_at: ArcAtomic<f64>,
+        env_interval: ArcAtomic<f64>,
+        //stored transforms by slot name, and the recall in progress with when it started and its
+        //length in ms
+        presets: HashMap<String, Vec<PartialParams>>,
+        //named sets of partial indexes, usable in place of an index in parameter messages
+        groups: HashMap<Symbol, Vec<usize>>,
+        preset_fade: Option<(Vec<PartialParams>, Vec<PartialParams>, f64, f64)>,
+        preset_clock: Clock,
         post: Box<dyn PdPost>,
     }
 
@@ -302,22 +1537,171 @@ pd_ext_macros::external! {
         #[sel]
         pub fn ats_data(&mut self, key: pd_ext::symbol::Symbol) {
             let d = crate::cache::get(key);
-            let _ = self.data_send.try_send(d);
+            self.fit(&d);
+            let _ = self.updates.try_send(Update::Data(d));
             //TODO warn if empty?
         }
 
+        //switch to the data under key right away, without the crossfade of ats_data, for data
+        //that changes gradually such as the output of ats/seq
         #[sel]
-        pub fn clear(&mut self) {
-            let _ = self.data_send.send(None);
+        pub fn ats_target(&mut self, key: pd_ext::symbol::Symbol) {
+            match crate::cache::get(key) {
+                Some(d) => {
+                    self.fit(&Some(d.clone()));
+                    self.send_update(Update::Target(d), "ats_target");
+                }
+                None => self.post.post_error(format!("ats_target: data key {} not found", key)),
+            }
         }
 
+        //replace the data with a single steady partial, for checking tuning, levels and routing
+        //without a file. positions from 0 to an hour play it
         #[sel]
-        pub fn offset(&mut self, v: pd_sys::t_float) {
-            set_clamp_bottom(&mut self.offset, v, 0);
+        pub fn calibrate(&mut self, args: &[pd_ext::atom::Atom]) {
+            match (args.get(0).and_then(|a| a.get_float()), args.get(1).and_then(|a| a.get_float())) {
+                (Some(freq), Some(amp)) if args.len() == 2 && freq > 0f32 => {
+                    let sr = pd_ext::pd::sample_rate() as f64;
+                    let frames = vec![vec![(freq as f64, (amp as f64).max(0f64))]; 2];
+                    let d = Some(Arc::new(AtsData::synthetic(&frames, CALIBRATE_SECONDS, sr, "calibrate".into())));
+                    self.fit(&d);
+                    let _ = self.updates.try_send(Update::Data(d));
+                }
+                _ => self.post.post_error("calibrate expects <freq> <amp>".into()),
+            }
         }
 
+        //load two datasets to compare, toggle switches between them
         #[sel]
-        pub fn incr(&mut self, v: pd_sys::t_float) {
+        pub fn ab(&mut self, args: &[pd_ext::atom::Atom]) {
+            match (args.get(0).and_then(|a| a.get_symbol()), args.get(1).and_then(|a| a.get_symbol())) {
+                (Some(a), Some(b)) if args.len() == 2 => {
+                    let a = crate::cache::get(a);
+                    let b = crate::cache::get(b);
+                    self.fit(&a);
+                    self.fit(&b);
+                    let _ = self.updates.try_send(Update::Ab(a, b));
+                }
+                _ => self.post.post_error("ab expects <key_a> <key_b>".into()),
+            }
+        }
+
+        //grow the bank to fit received data, as with -auto, with an optional bound on the size
+        #[sel]
+        pub fn autosize(&mut self, args: &[pd_ext::atom::Atom]) {
+            let max = match (args.get(0).and_then(|a| a.get_float()), args.get(1).map(|a| a.get_int())) {
+                (Some(on), None) if args.len() == 1 => Some((on != 0f32, AUTO_MAX)),
+                (Some(on), Some(Some(max))) if args.len() == 2 && max > 0 => Some((on != 0f32, max as usize)),
+                _ => None,
+            };
+            match max {
+                Some((true, max)) => self.auto = Some(max),
+                Some((false, _)) => self.auto = None,
+                None => self.post.post_error("autosize expects <0/1> [max]".into()),
+            }
+        }
+
+        //grow or shrink the synth bank, up to AUTO_MAX synths
+        #[sel]
+        pub fn voices(&mut self, v: pd_sys::t_float) {
+            let count = std::cmp::max(0, v.floor() as isize) as usize;
+            if count > AUTO_MAX {
+                self.post.post_error(format!("voices limited to {}", AUTO_MAX));
+            }
+            let count = std::cmp::min(count, AUTO_MAX);
+            if count != self.handles.len() {
+                self.resize(count);
+            }
+        }
+
+        //reseed the noise of every partial, for repeatable renders
+        #[sel]
+        pub fn seed(&mut self, v: pd_sys::t_float) {
+            self.send_update(Update::Seed(v as i64 as u64), "seed");
+        }
+
+        #[sel]
+        pub fn toggle(&mut self) {
+            let _ = self.updates.try_send(Update::Toggle);
+        }
+
+        #[sel]
+        pub fn clear(&mut self) {
+            self.send_update(Update::Data(None), "clear");
+        }
+
+        //fade time, in milliseconds, when the data changes, 0 switches immediately
+        #[sel]
+        pub fn xfade(&mut self, ms: pd_sys::t_float) {
+            self.xfade.store((ms as f64).max(0f64), STORE_ORDERING);
+        }
+
+        //release time of the gate inlet, in milliseconds
+        #[sel]
+        pub fn release(&mut self, ms: pd_sys::t_float) {
+            self.gate_release.store((ms as f64).max(0f64), STORE_ORDERING);
+        }
+
+        //the up/down sampling of an enclosing block~, so that times and frequencies follow the
+        //rate of the signal
+        #[sel]
+        pub fn oversample(&mut self, factor: pd_sys::t_float) {
+            if factor > 0f32 {
+                self.oversample.store(factor as f64, STORE_ORDERING);
+            } else {
+                self.post.post_error("oversample expects a positive factor".into());
+            }
+        }
+
+        //start the note envelope, transposing the bank by semitones
+        #[sel]
+        pub fn note_on(&mut self, args: &[pd_ext::atom::Atom]) {
+            let transpose = match args {
+                [] => 0f64,
+                [t] if t.get_float().is_some() => t.get_float().unwrap() as f64,
+                _ => {
+                    self.post.post_error("note_on expects an optional transposition".into());
+                    return;
+                }
+            };
+            self.send_update(Update::Note(Some(transpose)), "note_on");
+        }
+
+        #[sel]
+        pub fn note_off(&mut self) {
+            self.send_update(Update::Note(None), "note_off");
+        }
+
+        //the note envelope, attack, decay and release in ms and the sustain level 0..1
+        #[sel]
+        pub fn adsr(&mut self, args: &[pd_ext::atom::Atom]) {
+            let v: Vec<f64> = args.iter().filter_map(|a| a.get_float()).map(|v| (v as f64).max(0f64)).collect();
+            if args.len() == 4 && v.len() == 4 {
+                self.adsr_times.store([v[0], v[1], v[2].min(1f64), v[3]], STORE_ORDERING);
+            } else {
+                self.post.post_error("adsr expects <attack_ms> <decay_ms> <sustain> <release_ms>".into());
+            }
+        }
+
+        //filter dc out of the output
+        #[sel]
+        pub fn dcblock(&mut self, v: pd_sys::t_float) {
+            self.dcblock.store(v != 0f32, STORE_ORDERING);
+        }
+
+        //saturate the output smoothly instead of letting it pass +-1
+        #[sel]
+        pub fn softclip(&mut self, v: pd_sys::t_float) {
+            self.softclip.store(v != 0f32, STORE_ORDERING);
+        }
+
+        #[sel]
+        pub fn offset(&mut self, v: pd_sys::t_float) {
+            set_clamp_bottom(&mut self.offset, v, 0);
+        }
+
+        #[sel]
+        pub fn incr(&mut self, v: pd_sys::t_float) {
             set_clamp_bottom(&mut self.incr, v, 1);
         }
 
@@ -326,6 +1710,281 @@ pd_ext_macros::external! {
             set_clamp_bottom(&mut self.limit, v, 0);
         }
 
+        //set offset, incr and limit at once from an inclusive partial range, or all partials
+        #[sel]
+        pub fn select(&mut self, args: &[pd_ext::atom::Atom]) {
+            let ints: Vec<i32> = args.iter().filter_map(|a| a.get_int()).collect();
+            let select = if args.len() == 1 && args[0].get_symbol() == Some(*ALL) {
+                Some((0, 1, std::usize::MAX))
+            } else if ints.len() == 3 && args.len() == 3 && ints[0] >= 0 && ints[1] >= ints[0] && ints[2] > 0 {
+                let (start, end, step) = (ints[0] as usize, ints[1] as usize, ints[2] as usize);
+                Some((start, step, (end - start) / step + 1))
+            } else {
+                None
+            };
+            if let Some((offset, incr, limit)) = select {
+                //the processor applies them together, between blocks
+                let _ = self.updates.try_send(Update::Select(offset, incr, limit));
+            } else {
+                self.post.post_error("select expects <start> <end> <step> or all".into());
+            }
+        }
+
+        #[sel]
+        pub fn top(&mut self, v: pd_sys::t_float) {
+            set_clamp_bottom(&mut self.top, v, 0);
+        }
+
+        #[sel]
+        pub fn freq_min(&mut self, v: pd_sys::t_float) {
+            self.freq_min.store(v as f64, STORE_ORDERING);
+        }
+
+        #[sel]
+        pub fn amp_gate(&mut self, v: pd_sys::t_float) {
+            self.amp_gate.store(v as f64, STORE_ORDERING);
+        }
+
+        //fade partials in, over ms, as their amplitude rises above birth_amp
+        #[sel]
+        pub fn fade_in(&mut self, args: &[pd_ext::atom::Atom]) {
+            self.apply_if(args, |s, v| s.fade_in(v));
+        }
+
+        //fade partials out, over ms, as their amplitude falls below birth_amp
+        #[sel]
+        pub fn fade_out(&mut self, args: &[pd_ext::atom::Atom]) {
+            self.apply_if(args, |s, v| s.fade_out(v));
+        }
+
+        #[sel]
+        pub fn birth_amp(&mut self, v: pd_sys::t_float) {
+            self.birth_amp.store((v as f64).max(0f64), STORE_ORDERING);
+        }
+
+        //smooth the partial trajectories over about this many frames, 0 is off
+        #[sel]
+        pub fn blur(&mut self, frames: pd_sys::t_float) {
+            self.blur.store((frames as f64).max(0f64), STORE_ORDERING);
+        }
+
+        //partials start sounding above the on amplitude and fade out once they fall below the
+        //off amplitude, after sounding for at least min_on ms, an on amplitude of 0 disables
+        #[sel]
+        pub fn amp_hysteresis(&mut self, args: &[pd_ext::atom::Atom]) {
+            let v: Vec<f64> = args.iter().filter_map(|a| a.get_float()).map(|v| v as f64).collect();
+            match v.as_slice() {
+                [on] if *on == 0f64 => self.amp_on.store(0f64, STORE_ORDERING),
+                [on, off] | [on, off, _] if v.len() == args.len() && *on >= *off && *off >= 0f64 => {
+                    self.amp_off.store(*off, STORE_ORDERING);
+                    self.amp_min_on.store(v.get(2).cloned().unwrap_or(0f64).max(0f64), STORE_ORDERING);
+                    self.amp_on.store(*on, STORE_ORDERING);
+                }
+                _ => self.post.post_error("amp_hysteresis expects <on> <off> [min on ms], with off no more than on, or 0".into()),
+            }
+        }
+
+        //scale the noise of the partials in a critical band
+        #[sel]
+        pub fn band_gain(&mut self, args: &[pd_ext::atom::Atom]) {
+            match crate::externals::index_value_args(args, NOISE_BANDS) {
+                Ok((Some(i), v)) => {
+                    if i < NOISE_BANDS {
+                        self.band_gain[i].store(v, STORE_ORDERING);
+                    }
+                }
+                Ok((None, v)) => {
+                    for g in self.band_gain.iter() {
+                        g.store(v, STORE_ORDERING);
+                    }
+                }
+                Err(msg) => self.post.post_error(msg),
+            }
+        }
+
+        #[sel]
+        pub fn duck(&mut self, args: &[pd_ext::atom::Atom]) {
+            let v: Vec<f64> = args.iter().filter_map(|a| a.get_float()).map(|v| v as f64).collect();
+            if v.len() != args.len() || v.len() == 0 || v.len() > 3 {
+                self.post.post_error("duck expects <amount> [attack ms] [release ms]".into());
+                return;
+            }
+            self.duck_amount.store(v[0], STORE_ORDERING);
+            if v.len() > 1 {
+                self.duck_attack.store(v[1], STORE_ORDERING);
+            }
+            if v.len() > 2 {
+                self.duck_release.store(v[2], STORE_ORDERING);
+            }
+        }
+
+        #[sel]
+        pub fn noise_width(&mut self, v: pd_sys::t_float) {
+            self.noise_width.store((v as f64).max(0f64).min(1f64), STORE_ORDERING);
+        }
+
+        //brighten or darken the resynthesis, a gain in dB per octave away from the pivot
+        #[sel]
+        pub fn tilt(&mut self, args: &[pd_ext::atom::Atom]) {
+            match (args.get(0).and_then(|a| a.get_float()), args.get(1).and_then(|a| a.get_float())) {
+                (Some(db), Some(pivot)) if args.len() == 2 && pivot > 0f32 => {
+                    self.tilt_pivot.store(pivot as f64, STORE_ORDERING);
+                    self.tilt.store(db as f64, STORE_ORDERING);
+                }
+                _ => self.post.post_error("tilt expects <dB_per_octave> <pivot_hz>".into()),
+            }
+        }
+
+        //keep transposed partials about as loud as at their analysed frequencies, 0 disables
+        #[sel]
+        pub fn eq_loudness(&mut self, v: pd_sys::t_float) {
+            self.eq_loudness.store((v as f64).max(0f64).min(1f64), STORE_ORDERING);
+        }
+
+        //snap partial frequencies toward a scale, 0 strength disables
+        #[sel]
+        pub fn quantize(&mut self, v: pd_sys::t_float) {
+            self.quantize.store((v as f64).max(0f64).min(1f64), STORE_ORDERING);
+        }
+
+        //the quantize scale as midi notes, only the pitch class matters
+        #[sel]
+        pub fn scale(&mut self, args: &[pd_ext::atom::Atom]) {
+            let v: Vec<f64> = args.iter().filter_map(|a| a.get_float()).map(|v| v as f64).collect();
+            if v.len() != args.len() || v.len() == 0 {
+                self.post.post_error("scale expects a list of midi notes".into());
+                return;
+            }
+            let _ = self.updates.try_send(Update::Scale(Scale::from_midi(&v)));
+        }
+
+        //the quantize scale as frequency ratios above a root
+        #[sel]
+        pub fn scale_ratios(&mut self, args: &[pd_ext::atom::Atom]) {
+            let v: Vec<f64> = args.iter().filter_map(|a| a.get_float()).map(|v| v as f64).collect();
+            if v.len() != args.len() || v.len() < 2 || v.iter().any(|v| *v <= 0f64) {
+                self.post.post_error("scale_ratios expects <root_hz> <ratio...>".into());
+                return;
+            }
+            let _ = self.updates.try_send(Update::Scale(Scale::new(v[0], &v[1..])));
+        }
+
+        //retune partials toward multiples of a fundamental, 0 strength disables
+        #[sel]
+        pub fn harmonize(&mut self, args: &[pd_ext::atom::Atom]) {
+            match (args.get(0).and_then(|a| a.get_float()), args.get(1).and_then(|a| a.get_float())) {
+                (Some(f0), Some(strength)) if args.len() == 2 && f0 > 0f32 => {
+                    self.harmonic_f0.store(f0 as f64, STORE_ORDERING);
+                    self.harmonic_strength.store((strength as f64).max(0f64).min(1f64), STORE_ORDERING);
+                }
+                _ => self.post.post_error("harmonize expects <f0_hz> <strength>".into()),
+            }
+        }
+
+        #[sel]
+        pub fn interp(&mut self, mode: Symbol) {
+            let mode = if mode == *NONE {
+                Interp::None
+            } else if mode == *LINEAR {
+                Interp::Linear
+            } else if mode == *CUBIC {
+                Interp::Cubic
+            } else {
+                self.post.post_error("interp expects none, linear or cubic".into());
+                return;
+            };
+            self.interp.store(mode, STORE_ORDERING);
+        }
+
+        //the amplitude curve between frames, interp, the default, follows the interp mode
+        #[sel]
+        pub fn amp_interp(&mut self, mode: Symbol) {
+            let mode = if mode == *INTERP {
+                AmpInterp::Follow
+            } else if mode == *LINEAR {
+                AmpInterp::Linear
+            } else if mode == *EXP {
+                AmpInterp::Exp
+            } else if mode == *COSINE {
+                AmpInterp::Cosine
+            } else {
+                self.post.post_error("amp_interp expects interp, linear, exp or cosine".into());
+                return;
+            };
+            self.amp_interp.store(mode, STORE_ORDERING);
+        }
+
+        //how positions outside of the data are treated, silence by default
+        #[sel]
+        pub fn oob_mode(&mut self, mode: Symbol) {
+            let mode = if mode == *CLAMP {
+                OobMode::Clamp
+            } else if mode == *WRAP {
+                OobMode::Wrap
+            } else if mode == *MIRROR {
+                OobMode::Mirror
+            } else if mode == *SILENCE {
+                OobMode::Silence
+            } else {
+                self.post.post_error("oob_mode expects clamp, wrap, mirror or silence".into());
+                return;
+            };
+            self.oob_mode.store(mode, STORE_ORDERING);
+        }
+
+        //sustain the last frame once the position passes the end, whatever the oob_mode
+        #[sel]
+        pub fn hold_end(&mut self, v: pd_sys::t_float) {
+            self.hold_end.store(v != 0f32, STORE_ORDERING);
+        }
+
+        //freeze the frequencies of the playing partials while the amplitudes follow the data
+        #[sel]
+        pub fn hold(&mut self, v: pd_sys::t_float) {
+            self.hold_freq.store(v != 0f32, STORE_ORDERING);
+        }
+
+        //freeze the amplitudes of the playing partials while the frequencies follow the data
+        #[sel]
+        pub fn hold_amp(&mut self, v: pd_sys::t_float) {
+            self.hold_amp.store(v != 0f32, STORE_ORDERING);
+        }
+
+        //play the data backwards, position 0 is the end of the data, loop points stay where they
+        //are in the data
+        #[sel]
+        pub fn reverse(&mut self, v: pd_sys::t_float) {
+            self.reverse.store(v != 0f32, STORE_ORDERING);
+        }
+
+        //`loop <0/1>`, loop the data between loop_start and loop_end, in milliseconds, while the
+        //position input runs past the end
+        #[sel]
+        pub fn r#loop(&mut self, v: pd_sys::t_float) {
+            self.loop_on.store(v != 0f32, STORE_ORDERING);
+        }
+
+        #[sel]
+        pub fn loop_start(&mut self, ms: pd_sys::t_float) {
+            self.loop_start.store((ms as f64).max(0f64), STORE_ORDERING);
+        }
+
+        #[sel]
+        pub fn loop_end(&mut self, ms: pd_sys::t_float) {
+            self.loop_end.store((ms as f64).max(0f64), STORE_ORDERING);
+        }
+
+        //crossfade, in milliseconds, from the end of the loop towards the data before its start
+        #[sel]
+        pub fn loop_xfade(&mut self, ms: pd_sys::t_float) {
+            self.loop_xfade.store((ms as f64).max(0f64), STORE_ORDERING);
+        }
+
+        #[sel]
+        pub fn freq_max(&mut self, v: pd_sys::t_float) {
+            self.freq_max.store(v as f64, STORE_ORDERING);
+        }
+
         #[sel]
         pub fn freq_mul(&mut self, args: &[pd_ext::atom::Atom]) {
             self.apply_if(args, |s, v| s.freq_mul(v));
@@ -346,127 +2005,987 @@ pd_ext_macros::external! {
             self.apply_if(args, |s, v| s.noise_amp_mul(v));
         }
 
+        #[sel]
+        pub fn amp_db(&mut self, args: &[pd_ext::atom::Atom]) {
+            let args = db_args(args);
+            self.apply_if(&args, |s, v| s.amp_mul(db_to_amp(v)));
+        }
+
+        #[sel]
+        pub fn noise_amp_db(&mut self, args: &[pd_ext::atom::Atom]) {
+            let args = db_args(args);
+            self.apply_if(&args, |s, v| s.noise_amp_mul(db_to_amp(v)));
+        }
+
         #[sel]
         pub fn noise_bw_scale(&mut self, args: &[pd_ext::atom::Atom]) {
             self.apply_if(args, |s, v| s.noise_bw_scale(v));
         }
 
-        fn apply_if<F: Fn(&mut ParitalSynthHandle, f64)>(&mut self, args: &[pd_ext::atom::Atom], f: F) {
-            match self.extract_args(args) {
-                Ok((i, v)) =>
-                    if let Some(i) = i {
-                        if i < self.handles.len() {
-                            f(&mut self.handles[i], v)
-                        }
-                    } else {
-                        for s in self.handles.iter_mut() {
-                            f(s, v);
-                        }
-                    },
-                Err(msg) => self.post.post_error(msg)
+        #[sel]
+        pub fn noise_bw_hz(&mut self, args: &[pd_ext::atom::Atom]) {
+            self.apply_if(args, |s, v| s.noise_bw_hz(v));
+        }
+
+        //noise bandwidth from noise_bw_scale, relative to the partial frequency, or noise_bw_hz
+        #[sel]
+        pub fn noise_bw_mode(&mut self, mode: Symbol) {
+            let abs = if mode == *RELATIVE {
+                false
+            } else if mode == *HZ {
+                true
+            } else {
+                self.post.post_error("noise_bw_mode expects relative or hz".into());
+                return;
+            };
+            for h in self.handles.iter_mut() {
+                h.noise_bw_abs(abs);
+            }
+        }
+
+        //the distribution of the noise modulator, for all partials
+        #[sel]
+        pub fn noise_type(&mut self, t: Symbol) {
+            let t = if t == *UNIFORM {
+                NoiseType::Uniform
+            } else if t == *GAUSSIAN {
+                NoiseType::Gaussian
+            } else if t == *VELVET {
+                NoiseType::Velvet
+            } else {
+                self.post.post_error("noise_type expects uniform, gaussian or velvet".into());
+                return;
+            };
+            for h in self.handles.iter_mut() {
+                h.noise_type(t);
+            }
+        }
+
+        //lowpass cutoff for the noise modulator in hz, 0 is off
+        #[sel]
+        pub fn noise_lp(&mut self, args: &[pd_ext::atom::Atom]) {
+            self.apply_if(args, |s, v| s.noise_lp(v));
+        }
+
+        #[sel]
+        pub fn mute(&mut self, args: &[pd_ext::atom::Atom]) {
+            self.apply_if(args, |s, v| s.mute(v != 0f64));
+        }
+
+        #[sel]
+        pub fn sine_on(&mut self, args: &[pd_ext::atom::Atom]) {
+            self.apply_if(args, |s, v| s.sine_on(v != 0f64));
+        }
+
+        #[sel]
+        pub fn noise_on(&mut self, args: &[pd_ext::atom::Atom]) {
+            self.apply_if(args, |s, v| s.noise_on(v != 0f64));
+        }
+
+        #[sel]
+        pub fn solo(&mut self, args: &[pd_ext::atom::Atom]) {
+            //an empty list un-solos, unmuting everything
+            let mut solo = vec![args.len() == 0; self.handles.len()];
+            for a in args.iter() {
+                match a.get_int() {
+                    Some(i) if i >= 0 && (i as usize) < solo.len() => solo[i as usize] = true,
+                    _ => {
+                        self.post.post_error("solo expects a list of partial indexes".into());
+                        return;
+                    }
+                }
+            }
+            for (h, s) in self.handles.iter_mut().zip(solo.iter()) {
+                h.mute(!s);
+            }
+        }
+
+        //transposition messages set freq_mul, taking either <value> for all partials or
+        //<index|all> <value>
+        #[sel]
+        pub fn transpose(&mut self, args: &[pd_ext::atom::Atom]) {
+            self.apply_if_or_all(args, |s, v| s.freq_mul((v / 12f64).exp2()));
+        }
+
+        #[sel]
+        pub fn transpose_cents(&mut self, args: &[pd_ext::atom::Atom]) {
+            self.apply_if_or_all(args, |s, v| s.freq_mul((v / 1200f64).exp2()));
+        }
+
+        //play the data as if base_note were its pitch
+        #[sel]
+        pub fn midi_ratio(&mut self, args: &[pd_ext::atom::Atom]) {
+            match (args.get(0).and_then(|a| a.get_float()), args.get(1).and_then(|a| a.get_float())) {
+                (Some(note), Some(base)) if args.len() == 2 => {
+                    let ratio = ((note - base) as f64 / 12f64).exp2();
+                    for h in self.handles.iter_mut() {
+                        h.freq_mul(ratio);
+                    }
+                }
+                _ => self.post.post_error("midi_ratio expects <note> <base_note>".into()),
+            }
+        }
+
+        #[sel]
+        pub fn detune(&mut self, args: &[pd_ext::atom::Atom]) {
+            self.apply_if(args, |s, v| s.detune(v));
+        }
+
+        //output the transforms of every partial, or one, as messages to send back to restore them
+        #[sel]
+        pub fn dump_params(&mut self, args: &[pd_ext::atom::Atom]) {
+            let range = match args {
+                [] => 0..self.handles.len(),
+                [a] => match a.get_int() {
+                    Some(i) if i >= 0 && (i as usize) < self.handles.len() => i as usize..i as usize + 1,
+                    _ => {
+                        self.post.post_error("dump_params index out of range".into());
+                        return;
+                    }
+                },
+                _ => {
+                    self.post.post_error("dump_params expects an optional partial index".into());
+                    return;
+                }
+            };
+            for i in range {
+                let p = self.handles[i].params();
+                for (sel, v) in [
+                    (*FREQ_MUL, p.freq_mul),
+                    (*FREQ_ADD, p.freq_add),
+                    (*AMP_MUL, p.amp_mul),
+                    (*NOISE_AMP_MUL, p.noise_amp_mul),
+                    (*NOISE_BW_SCALE, p.noise_bw_scale),
+                    (*NOISE_BW_HZ, p.noise_bw_hz),
+                    (*DETUNE, p.detune),
+                ].iter() {
+                    self.params_outlet.send_anything(*sel, &[(i as f64).into(), (*v).into()]);
+                }
             }
         }
 
-        fn extract_args(&self, list: &[pd_ext::atom::Atom]) -> Result<(Option<usize>, f64), String> {
-            if list.len() != 2 {
-                return Err("expected 2 arguments".into());
+        #[sel]
+        pub fn jitter(&mut self, args: &[pd_ext::atom::Atom]) {
+            match self.targets(args, 2) {
+                Ok((indexes, v)) => {
+                    for i in indexes {
+                        self.handles[i].jitter(v[0], v[1]);
+                    }
+                }
+                Err(msg) => self.post.post_error(msg),
             }
-            let mut index = None;
-            if let Some(i) = list[0].get_int() {
-                let i = i as usize;
-                if i > self.handles.len() {
-                    return Err(format!("partial index {} out of range", i));
+        }
+
+        //name a set of partials, an empty list removes the group
+        #[sel]
+        pub fn group(&mut self, args: &[pd_ext::atom::Atom]) {
+            let name = match args.get(0).and_then(|a| a.get_symbol()) {
+                Some(name) if name != *ALL => name,
+                _ => {
+                    self.post.post_error("group expects <name> <indexes...>".into());
+                    return;
                 }
-                index = Some(i);
+            };
+            let mut indexes = Vec::new();
+            for a in args[1..].iter() {
+                match a.get_int() {
+                    Some(i) if i >= 0 => indexes.push(i as usize),
+                    _ => {
+                        self.post.post_error("group expects <name> <indexes...>".into());
+                        return;
+                    }
+                }
+            }
+            if indexes.is_empty() {
+                self.groups.remove(&name);
             } else {
-                let s = list[0].get_symbol();
-                if s.is_none() || s.unwrap() != *ALL {
-                    return Err("expect first arg to be an index or 'all'".into());
+                self.groups.insert(name, indexes);
+            }
+        }
+
+        //set the ramp time, in milliseconds, for a parameter or all of them, 0 restores the default
+        //fixed rate slewing
+        #[sel]
+        pub fn smooth_time(&mut self, args: &[pd_ext::atom::Atom]) {
+            let (param, ms) = match (args.get(0).and_then(|a| a.get_symbol()), args.get(1).and_then(|a| a.get_float())) {
+                (Some(p), Some(ms)) if args.len() == 2 => (p, ms as f64),
+                _ => {
+                    self.post.post_error("smooth_time expects <param|all> <ms>".into());
+                    return;
+                }
+            };
+            let param: String = param.into();
+            let t = &self.times;
+            let times = match param.as_str() {
+                "freq_mul" => vec![&t.freq_mul],
+                "freq_add" => vec![&t.freq_add],
+                "amp_mul" => vec![&t.amp_mul],
+                "noise_amp_mul" => vec![&t.noise_amp_mul],
+                "noise_bw_scale" => vec![&t.noise_bw_scale],
+                "noise_bw_hz" => vec![&t.noise_bw_hz],
+                "all" => vec![&t.freq_mul, &t.freq_add, &t.amp_mul, &t.noise_amp_mul, &t.noise_bw_scale, &t.noise_bw_hz],
+                _ => {
+                    self.post.post_error(format!("smooth_time unknown param {}", param));
+                    return;
                 }
+            };
+            for a in times {
+                a.store(ms.max(0f64), STORE_ORDERING);
             }
-            let val = list[1].get_float();
-            if val.is_none() {
-                return Err("expect second arg to be a float".into());
+        }
+
+        //grow the bank to cover the data in auto mode
+        fn fit(&mut self, d: &Option<Arc<AtsData>>) {
+            if let (Some(max), Some(d)) = (self.auto, d) {
+                let count = std::cmp::min(d.partials(), max);
+                if count > self.handles.len() {
+                    self.resize(count);
+                }
             }
-            let val = val.unwrap() as f64;
-            Ok((index, val))
         }
 
-    }
+        //build a new bank here and hand it to the audio thread, the handles only change once it
+        //has been queued
+        fn resize(&mut self, count: usize) {
+            while self.trash.try_recv().is_ok() {}
+            let (handles, bank) = SynthBank::new(&self.handles, count, &self.times, &self.osc, pd_ext::pd::sample_rate() as f64);
+            match self.updates.try_send(Update::Bank(bank)) {
+                Ok(()) => self.handles = handles,
+                Err(TrySendError::Full(_)) => self.post.post_error("failed to resize, update queue full".into()),
+                Err(TrySendError::Disconnected(_)) => (),
+            }
+        }
 
-    impl SignalProcessorExternal for AtsSinNoiExternal {
-        fn new(builder: &mut dyn SignalProcessorExternalBuilder<Self>) -> Result<(Self, Box<dyn SignalProcessor>), String> {
-            builder.new_signal_outlet();
-            let (data_send, data_recv) = sync_channel(32);
-            let args = builder.creation_args();
-
-            let mut partials = None;
-            let mut offset = 0;
-            let mut incr = 1;
-
-            //get partial count
-            if args.len() > 0 {
-                if let Some(v) = args[0].get_int() {
-                    partials = if v > 0 { Some(v) } else { None };
-                }
-                if args.len() >= 2 {
-                    if let Some(v) = args[1].get_int() {
-                        if v < 0 {
-                            return Err("offset must be a positive integer".into());
-                        }
-                        offset = v;
+        //hand an update to the audio thread without blocking the gui, dropping it if the queue is full
+        fn send_update(&mut self, update: Update, what: &str) {
+            if let Err(TrySendError::Full(_)) = self.updates.try_send(update) {
+                self.post.post_error(format!("{} dropped, update queue full", what));
+            }
+        }
+
+        //enable phase reporting for phases_to_array
+        #[sel]
+        pub fn phase_report(&mut self, v: pd_sys::t_float) {
+            self.report_phase.store(v != 0f32, STORE_ORDERING);
+        }
+
+        //the oscillator phases, in radians, one per synth in the bank, into an array
+        #[sel]
+        pub fn phases_to_array(&mut self, name: Symbol) {
+            if !self.report_phase.load(LOAD_ORDERING) {
+                self.post.post_error("phase_report is off, phases are stale".into());
+            }
+            let phases: Vec<f64> = self.handles.iter().map(|h| h.phase()).collect();
+            if let Err(e) = crate::externals::write_array(name, &phases) {
+                self.post.post_error(e);
+            }
+        }
+
+        #[sel]
+        pub fn version(&mut self) {
+            crate::externals::about::send_version(self.info_outlet.as_ref());
+        }
+
+        //how often, in milliseconds, to output the envelope while dsp runs, 0 stops it
+        #[sel]
+        pub fn env_interval(&mut self, ms: pd_sys::t_float) {
+            self.env_interval.store((ms as f64).max(0f64), STORE_ORDERING);
+        }
+
+        #[tramp]
+        pub fn env_poll(&mut self) {
+            self.info_outlet.send_anything(
+                *ENV,
+                &[
+                    self.env_sines.load(LOAD_ORDERING).into(),
+                    self.env_noise.load(LOAD_ORDERING).into(),
+                ],
+            );
+        }
+
+        //set by the processor when a position event happens
+        #[tramp]
+        pub fn event_poll(&mut self) {
+            let event = self.pos_event.swap(EVENT_NONE, LOAD_ORDERING);
+            let at = self.pos_event_at.load(LOAD_ORDERING);
+            match event {
+                EVENT_DONE => self.info_outlet.send_anything(*DONE, &[]),
+                EVENT_LOOP => self.info_outlet.send_anything(*LOOP, &[]),
+                EVENT_OOB => self.info_outlet.send_anything(*OOB, &[at.into()]),
+                _ => (),
+            }
+        }
+
+        //snapshot the transforms of every partial into a slot
+        #[sel]
+        pub fn preset_store(&mut self, args: &[pd_ext::atom::Atom]) {
+            match args {
+                [a] => match preset_name(a) {
+                    Some(name) => {
+                        let params = self.handles.iter().map(|h| h.params()).collect();
+                        self.presets.insert(name, params);
                     }
-                    if args.len() >= 3 {
-                        if let Some(v) = args[2].get_int() {
-                            if v < 1 {
-                                return Err("increment must be an integer greater than 0".into());
-                            }
-                            incr = v;
-                        }
+                    None => self.post.post_error("preset_store expects a slot".into()),
+                },
+                _ => self.post.post_error("preset_store expects a slot".into()),
+            }
+        }
+
+        //move the transforms to a stored slot over interp_ms, immediately without it
+        #[sel]
+        pub fn preset_recall(&mut self, args: &[pd_ext::atom::Atom]) {
+            let (name, ms) = match args {
+                [a] => (preset_name(a), Some(0f32)),
+                [a, ms] => (preset_name(a), ms.get_float()),
+                _ => (None, None),
+            };
+            let (name, ms) = match (name, ms) {
+                (Some(name), Some(ms)) if ms >= 0f32 => (name, ms as f64),
+                _ => {
+                    self.post.post_error("preset_recall expects <slot> [interp_ms]".into());
+                    return;
+                }
+            };
+            let to = match self.presets.get(&name) {
+                Some(p) => p.clone(),
+                None => {
+                    self.post.post_error(format!("no preset {}", name));
+                    return;
+                }
+            };
+            let from = self.handles.iter().map(|h| h.params()).collect();
+            self.preset_fade = Some((from, to, unsafe { pd_sys::clock_getlogicaltime() }, ms));
+            self.preset_tick();
+        }
+
+        #[tramp]
+        pub fn preset_tick(&mut self) {
+            let t = match &self.preset_fade {
+                Some((_, _, started, ms)) if *ms > 0f64 => {
+                    (unsafe { pd_sys::clock_gettimesince(*started) } / ms).min(1f64)
+                }
+                Some(_) => 1f64,
+                None => return,
+            };
+            if let Some((from, to, _, _)) = &self.preset_fade {
+                //partials added since the store keep their transforms
+                for (h, f, p) in izip!(self.handles.iter_mut(), from.iter(), to.iter()) {
+                    h.set_params(&f.lerp(p, t));
+                }
+            }
+            if t < 1f64 {
+                self.preset_clock.delay(PRESET_INTERVAL_MS);
+            } else {
+                self.preset_fade = None;
+            }
+        }
+
+        fn apply_if_or_all<F: Fn(&mut ParitalSynthHandle, f64)>(&mut self, args: &[pd_ext::atom::Atom], f: F) {
+            if args.len() == 1 {
+                if let Some(v) = args[0].get_float() {
+                    for s in self.handles.iter_mut() {
+                        f(s, v as f64);
                     }
+                } else {
+                    self.post.post_error("expected a float".into());
+                }
+            } else {
+                self.apply_if(args, f);
+            }
+        }
+
+        fn apply_if<F: Fn(&mut ParitalSynthHandle, f64)>(&mut self, args: &[pd_ext::atom::Atom], f: F) {
+            match self.targets(args, 1) {
+                Ok((indexes, v)) =>
+                    for i in indexes {
+                        f(&mut self.handles[i], v[0]);
+                    },
+                Err(msg) => self.post.post_error(msg)
+            }
+        }
+
+        //parse an `<index|all|group> <float>...` argument list with count floats into the
+        //partials in the bank that it addresses
+        fn targets(&self, args: &[pd_ext::atom::Atom], count: usize) -> Result<(Vec<usize>, Vec<f64>), String> {
+            let len = self.handles.len();
+            if let Some(group) = args.get(0).and_then(|a| a.get_symbol()).and_then(|s| self.groups.get(&s)) {
+                let vals: Vec<f64> = args[1..].iter().filter_map(|a| a.get_float()).map(|v| v as f64).collect();
+                if args.len() != count + 1 || vals.len() != count {
+                    return Err(format!("expected a group and {} floats", count));
                 }
+                return Ok((group.iter().cloned().filter(|i| *i < len).collect(), vals));
             }
+            let (i, vals) = crate::externals::index_values_args(args, len, count)?;
+            let indexes = match i {
+                Some(i) => (i..std::cmp::min(i + 1, len)).collect(),
+                None => (0..len).collect(),
+            };
+            Ok((indexes, vals))
+        }
 
-            let offset = Arc::new(Atomic::new(offset as usize));
-            let incr = Arc::new(Atomic::new(incr as usize));
-            let limit = Arc::new(Atomic::new(std::usize::MAX));
+    }
 
-            if let Some(partials) = partials {
-                let mut synths = Vec::new();
-                let mut handles = Vec::new();
-                for _ in 0..partials {
-                    let (h, s) = ParitalSynthHandle::new();
-                    handles.push(h);
-                    synths.push(s);
+    impl SignalProcessorExternal for AtsSinNoiExternal {
+        fn new(builder: &mut dyn SignalProcessorExternalBuilder<Self>) -> Result<(Self, Box<dyn SignalProcessor>), String> {
+            let (updates_send, updates_recv) = sync_channel(32);
+            let (trash_send, trash_recv) = sync_channel(4);
+            let args = builder
+                .creation_args()
+                .iter()
+                .map(|a| (*a).try_into())
+                .collect::<Result<Vec<String>, _>>()
+                .map_err(|_| "failed to convert args to a string array".to_string())?;
+            let CreationArgs {
+                partials,
+                offset,
+                incr,
+                limit,
+                outs,
+                auto,
+                vca,
+                duck,
+                sig_freq_mul,
+                sig_amp_mul,
+                gate,
+                data,
+                table,
+                threads,
+            } = extract_args(args)?;
+            let osc = if table > 0 { Osc::table(table) } else { Osc::Exact };
+
+            for _ in 0..outs {
+                builder.new_signal_outlet();
+            }
+            let info_outlet = builder.new_message_outlet(OutletType::AnyThing);
+            let params_outlet = builder.new_message_outlet(OutletType::AnyThing);
+            let env_clock = Clock::new(builder.obj(), atssinnoiexternal_env_poll_trampoline);
+            let env_interval = Arc::new(Atomic::new(ENV_INTERVAL_MS));
+            let event_clock = Clock::new(builder.obj(), atssinnoiexternal_event_poll_trampoline);
+            let preset_clock = Clock::new(builder.obj(), atssinnoiexternal_preset_tick_trampoline);
+
+            //extra signal inlets follow the position inlet, vca first then the duck sidechain, then
+            //freq_mul, amp_mul and the gate
+            let mut inlets = 1;
+            let vca = if vca {
+                builder.new_signal_inlet();
+                inlets += 1;
+                Some(inlets - 1)
+            } else {
+                None
+            };
+            let ducker = if duck {
+                builder.new_signal_inlet();
+                inlets += 1;
+                Some((inlets - 1, Ducker::new(pd_ext::pd::sample_rate() as f64)))
+            } else {
+                None
+            };
+            let mut sig_inlet = |enabled: bool| {
+                if enabled {
+                    builder.new_signal_inlet();
+                    inlets += 1;
+                    Some(inlets - 1)
+                } else {
+                    None
                 }
+            };
+            let sig_freq_mul = sig_inlet(sig_freq_mul);
+            let sig_amp_mul = sig_inlet(sig_amp_mul);
+            let gate = sig_inlet(gate);
+
+            let offset = Arc::new(Atomic::new(offset));
+            let incr = Arc::new(Atomic::new(incr));
+            let limit = Arc::new(Atomic::new(limit.unwrap_or(std::usize::MAX)));
+            let top = Arc::new(Atomic::new(0usize));
+            let interp = Arc::new(Atomic::new(Interp::Linear));
+            let amp_interp = Arc::new(Atomic::new(AmpInterp::Follow));
+            let oob_mode = Arc::new(Atomic::new(OobMode::Silence));
+            let hold_end = Arc::new(Atomic::new(false));
+            let hold_freq = Arc::new(Atomic::new(false));
+            let hold_amp = Arc::new(Atomic::new(false));
+            let reverse = Arc::new(Atomic::new(false));
+            let loop_on = Arc::new(Atomic::new(false));
+            let loop_start = Arc::new(Atomic::new(0f64));
+            let loop_end = Arc::new(Atomic::new(0f64));
+            let loop_xfade = Arc::new(Atomic::new(LOOP_XFADE_MS));
+            let report_phase = Arc::new(Atomic::new(false));
+            let xfade = Arc::new(Atomic::new(XFADE_MS));
+            let freq_min = Arc::new(Atomic::new(0f64));
+            let freq_max = Arc::new(Atomic::new(std::f64::MAX));
+            let amp_gate = Arc::new(Atomic::new(0f64));
+            let blur = Arc::new(Atomic::new(0f64));
+            let birth_amp = Arc::new(Atomic::new(BIRTH_AMP));
+            let amp_on = Arc::new(Atomic::new(0f64));
+            let amp_off = Arc::new(Atomic::new(0f64));
+            let amp_min_on = Arc::new(Atomic::new(0f64));
+            let duck_amount = Arc::new(Atomic::new(0f64));
+            let band_gain: Box<[ArcAtomic<f64>]> = (0..NOISE_BANDS).map(|_| Arc::new(Atomic::new(1f64))).collect();
+            let duck_attack = Arc::new(Atomic::new(10f64));
+            let duck_release = Arc::new(Atomic::new(200f64));
+            let noise_width = Arc::new(Atomic::new(0f64));
+            let gate_release = Arc::new(Atomic::new(GATE_RELEASE_MS));
+            let oversample = Arc::new(Atomic::new(1f64));
+            let dcblock = Arc::new(Atomic::new(false));
+            let softclip = Arc::new(Atomic::new(false));
+            let adsr_times = Arc::new(Atomic::new(ADSR_MS));
+            let env_sines = Arc::new(Atomic::new(0f64));
+            let env_noise = Arc::new(Atomic::new(0f64));
+            let pos_event = Arc::new(Atomic::new(EVENT_NONE));
+            let pos_event_at = Arc::new(Atomic::new(0f64));
+            let tilt = Arc::new(Atomic::new(0f64));
+            let tilt_pivot = Arc::new(Atomic::new(1000f64));
+            let eq_loudness = Arc::new(Atomic::new(0f64));
+            let quantize = Arc::new(Atomic::new(0f64));
+            let harmonic_f0 = Arc::new(Atomic::new(100f64));
+            let harmonic_strength = Arc::new(Atomic::new(0f64));
+
+            //initial data from the cache
+            let data = if let Some(key) = data {
+                let d = key
+                    .as_str()
+                    .try_into()
+                    .ok()
+                    .and_then(crate::cache::get);
+                if d.is_none() {
+                    builder.poster().post_error(format!("no data found for key {}", key));
+                }
+                d
+            } else {
+                None
+            };
+
+            //in auto mode the bank starts empty, or fit to the initial data, and the partial
+            //count, if given, bounds its size
+            let (partials, auto) = if auto {
+                let max = partials.unwrap_or(AUTO_MAX);
+                (
+                    Some(data.as_ref().map_or(0, |d| std::cmp::min(d.partials(), max))),
+                    Some(max),
+                )
+            } else {
+                (partials, None)
+            };
+
+            if let Some(partials) = partials {
+                let times = SlewTimes::default();
+                let (handles, bank) = SynthBank::new(&[], partials, &times, &osc, pd_ext::pd::sample_rate() as f64);
 
                 Ok(
                     (
                         Self {
-                            data_send,
-                            handles: handles.into(),
+                            updates: updates_send,
+                            trash: trash_recv,
+                            auto,
+                            handles,
+                            times,
+                            osc,
                             offset: offset.clone(),
                             incr: incr.clone(),
                             limit: limit.clone(),
+                            top: top.clone(),
+                            interp: interp.clone(),
+                            amp_interp: amp_interp.clone(),
+                            oob_mode: oob_mode.clone(),
+                            hold_end: hold_end.clone(),
+                            hold_freq: hold_freq.clone(),
+                            hold_amp: hold_amp.clone(),
+                            reverse: reverse.clone(),
+                            loop_on: loop_on.clone(),
+                            loop_start: loop_start.clone(),
+                            loop_end: loop_end.clone(),
+                            loop_xfade: loop_xfade.clone(),
+                            report_phase: report_phase.clone(),
+                            xfade: xfade.clone(),
+                            freq_min: freq_min.clone(),
+                            freq_max: freq_max.clone(),
+                            amp_gate: amp_gate.clone(),
+                            blur: blur.clone(),
+                            birth_amp: birth_amp.clone(),
+                            amp_on: amp_on.clone(),
+                            amp_off: amp_off.clone(),
+                            amp_min_on: amp_min_on.clone(),
+                            duck_amount: duck_amount.clone(),
+                            band_gain: band_gain.clone(),
+                            duck_attack: duck_attack.clone(),
+                            duck_release: duck_release.clone(),
+                            noise_width: noise_width.clone(),
+                            gate_release: gate_release.clone(),
+                            oversample: oversample.clone(),
+                            dcblock: dcblock.clone(),
+                            softclip: softclip.clone(),
+                            adsr_times: adsr_times.clone(),
+                            tilt: tilt.clone(),
+                            tilt_pivot: tilt_pivot.clone(),
+                            eq_loudness: eq_loudness.clone(),
+                            quantize: quantize.clone(),
+                            harmonic_f0: harmonic_f0.clone(),
+                            harmonic_strength: harmonic_strength.clone(),
+                            env_sines: env_sines.clone(),
+                            env_noise: env_noise.clone(),
+                            info_outlet,
+                            params_outlet,
+                            pos_event: pos_event.clone(),
+                            pos_event_at: pos_event_at.clone(),
+                            env_interval: env_interval.clone(),
+                            presets: HashMap::new(),
+                            groups: HashMap::new(),
+                            preset_fade: None,
+                            preset_clock,
                             post: builder.poster()
                         },
                         Box::new(AtsSinNoiProcessor {
-                            current: None,
-                            data_recv,
+                            current: data,
+                            alt: None,
+                            ab_pending: false,
+                            seed: None,
+                            ab_fade_in: false,
+                            next: None,
+                            level: 1f64,
+                            xfade,
+                            sample_rate: pd_ext::pd::sample_rate() as f64,
+                            updates: updates_recv,
+                            trash: trash_send,
                             offset,
                             incr,
                             limit,
-                            synths: synths.into(),
+                            top,
+                            interp,
+                            amp_interp,
+                            oob_mode,
+                            hold_end,
+                            hold_freq,
+                            hold_amp,
+                            reverse,
+                            loop_on,
+                            loop_start,
+                            loop_end,
+                            loop_xfade,
+                            report_phase,
+                            freq_min,
+                            freq_max,
+                            amp_gate,
+                            blur,
+                            birth_amp,
+                            amp_on,
+                            amp_off,
+                            amp_min_on,
+                            bank,
+                            vca,
+                            sig_freq_mul,
+                            sig_amp_mul,
+                            ducker,
+                            duck_amount,
+                            band_gain,
+                            duck_attack,
+                            duck_release,
+                            noise_width,
+                            gate,
+                            gate_prev: 0f32,
+                            //closed until the first rising edge
+                            gate_level: if gate.is_some() { 0f64 } else { 1f64 },
+                            gate_release,
+                            oversample,
+                            dcblock,
+                            dc: Default::default(),
+                            softclip,
+                            adsr: None,
+                            adsr_times,
+                            transpose: 1f64,
+                            pool: if threads > 1 { Some(Pool::new(threads)) } else { None },
+                            thread_out: vec![0f32; (threads - 1) * 2 * THREAD_BLOCK_MAX].into(),
+                            thread_env: vec![(0f64, 0f64); threads].into(),
+                            env_sines,
+                            env_noise,
+                            env_interval,
+                            env_elapsed: 0f64,
+                            env_clock: PerformClock(env_clock),
+                            pos_event,
+                            pos_event_at,
+                            event_clock: PerformClock(event_clock),
+                            last_pos: None,
+                            tilt,
+                            tilt_pivot,
+                            eq_loudness,
+                            scale: Scale::new(1f64, &[]),
+                            quantize,
+                            harmonic_f0,
+                            harmonic_strength,
                         })
                     )
                 )
             } else {
-                Err("first argument must be a non zero partial count".into())
+                Err("first argument must be a non zero partial count, or use -auto".into())
+            }
+        }
+    }
+}
+
+struct CreationArgs {
+    partials: Option<usize>,
+    offset: usize,
+    incr: usize,
+    limit: Option<usize>,
+    outs: usize,
+    auto: bool,
+    vca: bool,
+    duck: bool,
+    sig_freq_mul: bool,
+    sig_amp_mul: bool,
+    gate: bool,
+    data: Option<String>,
+    table: usize,
+    threads: usize,
+}
+
+fn create_app() -> App<'static, 'static> {
+    App::new("ats/sinnoi~")
+        .setting(AppSettings::AllowNegativeNumbers)
+        .setting(AppSettings::NoBinaryName)
+        .setting(AppSettings::ColorNever)
+        .setting(AppSettings::DisableHelpSubcommand)
+        .setting(AppSettings::DisableHelpFlags)
+        .setting(AppSettings::DisableVersion)
+        .setting(AppSettings::DeriveDisplayOrder)
+        //the original positional arguments, still accepted for existing patches
+        .arg(Arg::with_name("partials_pos").index(1).hidden(true))
+        .arg(Arg::with_name("offset_pos").index(2).hidden(true))
+        .arg(Arg::with_name("incr_pos").index(3).hidden(true))
+        .arg(Arg::with_name("limit_pos").index(4).hidden(true))
+        .arg(
+            Arg::with_name("partials")
+                .long("partials")
+                .takes_value(true)
+                .help("number of partials to synthesize, the maximum with -auto"),
+        )
+        .arg(
+            Arg::with_name("offset")
+                .long("offset")
+                .takes_value(true)
+                .help("index of the first partial, defaults to 0"),
+        )
+        .arg(
+            Arg::with_name("incr")
+                .long("incr")
+                .takes_value(true)
+                .help("partial index increment, defaults to 1"),
+        )
+        .arg(
+            Arg::with_name("limit")
+                .long("limit")
+                .takes_value(true)
+                .help("maximum number of partials to synthesize, defaults to all"),
+        )
+        .arg(
+            Arg::with_name("table")
+                .long("table")
+                .takes_value(true)
+                .help("sine table size, 0 for exact sines, defaults to 4096"),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .long("threads")
+                .takes_value(true)
+                .help("threads to split the bank across, defaults to 1"),
+        )
+        .arg(
+            Arg::with_name("data")
+                .long("data")
+                .takes_value(true)
+                .help("cache key of data to start with"),
+        )
+        .arg(
+            Arg::with_name("outs")
+                .long("outs")
+                .takes_value(true)
+                .possible_values(&["1", "2"])
+                .help("1 for mono or 2 for stereo output, defaults to 1"),
+        )
+        .arg(
+            Arg::with_name("stereo")
+                .long("stereo")
+                .help("same as -outs 2"),
+        )
+        .arg(
+            Arg::with_name("auto")
+                .long("auto")
+                .help("size the synth bank from the received data"),
+        )
+        .arg(
+            Arg::with_name("vca")
+                .long("vca")
+                .help("add a signal inlet that scales the output"),
+        )
+        .arg(
+            Arg::with_name("duck")
+                .long("duck")
+                .help("add a sidechain signal inlet to duck against"),
+        )
+        .arg(
+            Arg::with_name("sig")
+                .long("sig")
+                .takes_value(true)
+                .multiple(true)
+                .min_values(1)
+                .possible_values(&["freq_mul", "amp_mul"])
+                .help("add signal inlets for the given parameters"),
+        )
+        .arg(
+            Arg::with_name("gate")
+                .long("gate")
+                .help("add a gate signal inlet, rising edges restart and falling edges release"),
+        )
+}
+
+fn extract_args(args: Vec<String>) -> Result<CreationArgs, String> {
+    //pd style single dash long flags
+    let args: Vec<String> = args
+        .into_iter()
+        .map(|a| {
+            if a.len() > 2
+                && a.starts_with('-')
+                && a.chars().nth(1).map_or(false, char::is_alphabetic)
+            {
+                format!("-{}", a)
+            } else {
+                a
+            }
+        })
+        .collect();
+    let mut app = create_app();
+    match app.clone().get_matches_from_safe(args) {
+        Ok(m) => {
+            let parse = |long: &str, pos: &str| -> Result<Option<i64>, String> {
+                m.value_of(long)
+                    .or_else(|| m.value_of(pos))
+                    .map(|v| {
+                        v.parse::<f64>()
+                            .map(|v| v as i64)
+                            .map_err(|e| e.to_string())
+                    })
+                    .transpose()
+            };
+            let partials = parse("partials", "partials_pos")?;
+            let offset = parse("offset", "offset_pos")?.unwrap_or(0);
+            if offset < 0 {
+                return Err("offset must be a positive integer".into());
+            }
+            let incr = parse("incr", "incr_pos")?.unwrap_or(1);
+            if incr < 1 {
+                return Err("increment must be an integer greater than 0".into());
+            }
+            let limit = parse("limit", "limit_pos")?;
+            if limit.map_or(false, |l| l < 0) {
+                return Err("limit must be a positive integer".into());
+            }
+            let table = parse("table", "table")?.unwrap_or(SINE_TABLE_SIZE as i64);
+            if table < 0 {
+                return Err("table size must be a positive integer".into());
+            }
+            let threads = parse("threads", "threads")?.unwrap_or(1);
+            if threads < 1 {
+                return Err("threads must be an integer greater than 0".into());
+            }
+            let sig: Vec<&str> = m.values_of("sig").map(|v| v.collect()).unwrap_or_default();
+            Ok(CreationArgs {
+                partials: partials.filter(|p| *p > 0).map(|p| p as usize),
+                offset: offset as usize,
+                incr: incr as usize,
+                limit: limit.map(|l| l as usize),
+                outs: if m.is_present("stereo") || m.value_of("outs") == Some("2") {
+                    2
+                } else {
+                    1
+                },
+                auto: m.is_present("auto"),
+                vca: m.is_present("vca"),
+                duck: m.is_present("duck"),
+                sig_freq_mul: sig.contains(&"freq_mul"),
+                sig_amp_mul: sig.contains(&"amp_mul"),
+                gate: m.is_present("gate"),
+                data: m.value_of("data").map(String::from),
+                table: table as usize,
+                threads: threads as usize,
+            })
+        }
+        Err(m) => {
+            let mut help = Vec::new();
+            let _ = app.write_long_help(&mut help);
+            let help = String::from_utf8(help);
+            if let Ok(help) = help {
+                Err(format!("{} {}", m.message, help))
+            } else {
+                Err(m.message)
             }
         }
     }
 }
 
-fn lerp(x0: f64, x1: f64, frac: f64) -> f64 {
-    x0 + (x1 - x0) * frac
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn position_events_either_way() {
+        let last = 10f64;
+        assert_eq!(pos_event(Some(9f64), 10f64, last), EVENT_DONE);
+        assert_eq!(pos_event(Some(1f64), 0f64, last), EVENT_DONE);
+        assert_eq!(pos_event(Some(0.5f64), -0.5f64, last), EVENT_OOB);
+        assert_eq!(pos_event(Some(10f64), 0f64, last), EVENT_LOOP);
+        assert_eq!(pos_event(Some(5f64), -1f64, last), EVENT_OOB);
+        assert_eq!(pos_event(Some(9.5f64), 0.5f64, last), EVENT_LOOP);
+        assert_eq!(pos_event(Some(0.5f64), 9.5f64, last), EVENT_LOOP);
+        //turning around in the middle is just movement
+        assert_eq!(pos_event(Some(5f64), 4.9f64, last), EVENT_NONE);
+        assert_eq!(pos_event(Some(4.9f64), 5f64, last), EVENT_NONE);
+        assert_eq!(pos_event(Some(0f64), -1f64, last), EVENT_OOB);
+        assert_eq!(pos_event(Some(-1f64), -2f64, last), EVENT_NONE);
+    }
+
+    #[test]
+    fn frame_pos_within_the_frames() {
+        assert_eq!(frame_pos(2.5f64, 10), (2, 3, 0.5f64));
+        assert_eq!(frame_pos(-1f64, 10), (0, 1, 0f64));
+        assert_eq!(frame_pos(20f64, 10), (8, 9, 1f64));
+        //too few frames to interpolate between
+        assert_eq!(frame_pos(3f64, 1), (0, 0, 0f64));
+        assert_eq!(frame_pos(3f64, 0), (0, 0, 0f64));
+    }
+
+    #[test]
+    fn reverse_mirrors_positions() {
+        let last = 10f64;
+        let map = |p, reverse, l| map_position(p, last, reverse, l, false, OobMode::Silence);
+        assert_eq!(map(0f64, true, None), Some(last));
+        assert_eq!(map(2.5f64, true, None), Some(7.5f64));
+        assert_eq!(map(2.5f64, false, None), Some(2.5f64));
+        assert_eq!(map(11f64, true, None), None);
+
+        //in reverse the loop wraps at its start and blends with frames after its end
+        let l = LoopRegion {
+            start: 2f64,
+            end: 6f64,
+            xfade: 1f64,
+            reverse: true,
+        };
+        assert_eq!(map(9f64, true, Some(l)), Some(5f64));
+        assert_eq!(l.blend(2.5f64), Some((6.5f64, 0.5f64)));
+        assert_eq!(l.blend(4f64), None);
+        let l = LoopRegion {
+            reverse: false,
+            ..l
+        };
+        assert_eq!(map(7f64, false, Some(l)), Some(3f64));
+        assert_eq!(l.blend(5.5f64), Some((1.5f64, 0.5f64)));
+    }
+
+    #[test]
+    fn birth_fade_out_ends_at_death() {
+        let mut v = Voice::default();
+        let fades = (0f64, 100f64);
+        assert_eq!(v.birth(0.1f64, fades, 0, 1f64, (1f64, 400f64)), 1f64);
+        //the next frame is dead, the fade starts early enough to end there
+        assert_eq!(v.birth(0.1f64, fades, 0, 1f64, (0f64, 50f64)), 0.5f64);
+        assert_eq!(v.birth(0.1f64, fades, 0, 1f64, (0f64, 0f64)), 0f64);
+        assert_eq!(v.birth(0.1f64, fades, 0, 0f64, (0f64, 400f64)), 0f64);
+    }
 }
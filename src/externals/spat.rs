@@ -0,0 +1,542 @@
+use crate::data::AtsData;
+use crate::synth::{
+    frame_pos, lerp, ArcAtomic, FlushDenormals, ParitalSynth, ParitalSynthHandle, SlewTimes,
+    LOAD_ORDERING, STORE_ORDERING,
+};
+use atomic::Atomic;
+use pd_ext::builder::SignalProcessorExternalBuilder;
+use pd_ext::external::{SignalProcessor, SignalProcessorExternal};
+use pd_ext::post::PdPost;
+use pd_ext::symbol::Symbol;
+use std::convert::TryInto;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Arc;
+
+const DSP_RECV_MAX: usize = 32;
+//meters and meters per second, for the spherical head ITD approximation
+const HEAD_RADIUS: f64 = 0.0875;
+const SPEED_OF_SOUND: f64 = 343.0;
+//frequency range mapped across the azimuth range in freq_pan mode
+const FREQ_PAN_LOW: f64 = 20.0;
+const FREQ_PAN_HIGH: f64 = 20000.0;
+
+lazy_static::lazy_static! {
+    static ref BINAURAL: Symbol = "binaural".try_into().unwrap();
+    static ref FOA: Symbol = "foa".try_into().unwrap();
+}
+
+pub enum SpatMode {
+    Binaural,
+    //speaker azimuths in radians, sorted
+    Vbap(Box<[f64]>),
+    //first order ambisonics, FuMa W X Y Z
+    Foa,
+}
+
+impl SpatMode {
+    pub fn outputs(&self) -> usize {
+        match self {
+            SpatMode::Binaural => 2,
+            SpatMode::Vbap(s) => s.len(),
+            SpatMode::Foa => 4,
+        }
+    }
+}
+
+//n speaker azimuths in degrees, evenly spaced and symmetric about the front
+fn even_speakers(n: usize) -> Vec<f64> {
+    (0..n).map(|i| -180f64 + 360f64 * (i as f64 + 0.5f64) / n as f64).collect()
+}
+
+//pairwise 2D VBAP, azimuth in radians, clockwise from the front
+fn vbap_gains(speakers: &[f64], azimuth: f64, gains: &mut [f64]) {
+    for g in gains.iter_mut() {
+        *g = 0f64;
+    }
+    let two_pi = 2f64 * std::f64::consts::PI;
+    let n = speakers.len();
+    for i in 0..n {
+        let a1 = speakers[i];
+        let mut a2 = speakers[(i + 1) % n];
+        if a2 <= a1 {
+            a2 += two_pi;
+        }
+        let mut az = azimuth;
+        while az < a1 {
+            az += two_pi;
+        }
+        while az >= a1 + two_pi {
+            az -= two_pi;
+        }
+        if az <= a2 {
+            let (l1x, l1y) = (a1.cos(), a1.sin());
+            let (l2x, l2y) = (a2.cos(), a2.sin());
+            let (px, py) = (az.cos(), az.sin());
+            let det = l1x * l2y - l1y * l2x;
+            let (g1, g2) = if det.abs() < 1e-9 {
+                (1f64, 0f64)
+            } else {
+                (
+                    ((px * l2y - py * l2x) / det).max(0f64),
+                    ((py * l1x - px * l1y) / det).max(0f64),
+                )
+            };
+            let norm = (g1 * g1 + g2 * g2).sqrt();
+            if norm > 0f64 {
+                gains[i] = g1 / norm;
+                gains[(i + 1) % n] += g2 / norm;
+            }
+            return;
+        }
+    }
+}
+
+pub struct AtsSpatProcessor {
+    current: Option<Arc<AtsData>>,
+    data_recv: Receiver<Option<Arc<AtsData>>>,
+    mode: SpatMode,
+    synths: Box<[ParitalSynth]>,
+    azimuth: Box<[ArcAtomic<f64>]>,
+    elevation: Box<[ArcAtomic<f64>]>,
+    freq_pan: ArcAtomic<bool>,
+    freq_pan_range: (ArcAtomic<f64>, ArcAtomic<f64>),
+    //output channel for the residual, negative to spatialize it with the sines
+    noise_out: ArcAtomic<isize>,
+    //per synth output gains, synths * outputs
+    gains: Box<[f64]>,
+    //per synth interaural delays, binaural only
+    delays: Box<[f64]>,
+    //the rate of the signal, pd's rate times the oversampling of an enclosing block~
+    sample_rate: f64,
+    oversample: ArcAtomic<f64>,
+}
+
+impl AtsSpatProcessor {
+    //compute the per partial gains and delays for this block
+    fn place(&mut self, c: &AtsData, count: usize, pos: f64) {
+        let (p0, p1, fract) = frame_pos(pos, c.frames.len());
+        let freq_pan = self.freq_pan.load(LOAD_ORDERING);
+        let lo = self.freq_pan_range.0.load(LOAD_ORDERING);
+        let hi = self.freq_pan_range.1.load(LOAD_ORDERING);
+        let outs = self.mode.outputs();
+        for i in 0..count {
+            let f = lerp(c.frames[p0][i].freq, c.frames[p1][i].freq, fract);
+            let az = if freq_pan {
+                let n = ((f.max(FREQ_PAN_LOW) / FREQ_PAN_LOW).log2()
+                    / (FREQ_PAN_HIGH / FREQ_PAN_LOW).log2())
+                .min(1f64);
+                lerp(lo, hi, n)
+            } else {
+                self.azimuth[i].load(LOAD_ORDERING)
+            }
+            .to_radians();
+            let el = self.elevation[i].load(LOAD_ORDERING).to_radians();
+            let gains = &mut self.gains[i * outs..(i + 1) * outs];
+            match &self.mode {
+                SpatMode::Binaural => {
+                    let lateral = az.sin() * el.cos();
+                    self.delays[i] = HEAD_RADIUS / SPEED_OF_SOUND * (lateral.asin() + lateral);
+                    //crude head shadow, growing with frequency
+                    let shadow = 1f64 - 0.7f64 * lateral.abs() * (f / 2000f64).min(1f64);
+                    if lateral >= 0f64 {
+                        gains[0] = shadow;
+                        gains[1] = 1f64;
+                    } else {
+                        gains[0] = 1f64;
+                        gains[1] = shadow;
+                    }
+                }
+                SpatMode::Vbap(speakers) => vbap_gains(speakers, az, gains),
+                SpatMode::Foa => {
+                    //ambisonic azimuth is counter clockwise
+                    gains[0] = std::f64::consts::FRAC_1_SQRT_2;
+                    gains[1] = az.cos() * el.cos();
+                    gains[2] = -az.sin() * el.cos();
+                    gains[3] = el.sin();
+                }
+            }
+        }
+    }
+}
+
+impl SignalProcessor for AtsSpatProcessor {
+    fn process(
+        &mut self,
+        _frames: usize,
+        inputs: &[&mut [pd_sys::t_float]],
+        outputs: &mut [&mut [pd_sys::t_float]],
+    ) {
+        let _ftz = FlushDenormals::new();
+        let sample_rate = pd_ext::pd::sample_rate() as f64 * self.oversample.load(LOAD_ORDERING);
+        if sample_rate != self.sample_rate && sample_rate > 0f64 {
+            self.sample_rate = sample_rate;
+            for s in self.synths.iter_mut() {
+                s.set_sample_rate(sample_rate);
+            }
+        }
+
+        let mut cnt = 0;
+        while let Ok(c) = self.data_recv.try_recv() {
+            self.current = c;
+            cnt = cnt + 1;
+            if cnt > DSP_RECV_MAX {
+                break;
+            }
+        }
+
+        let c = match self.current.clone() {
+            Some(c) => c,
+            None => {
+                for out in outputs.iter_mut() {
+                    for v in out.iter_mut() {
+                        *v = 0f32.into();
+                    }
+                }
+                return;
+            }
+        };
+
+        let with_noise = c.has_noise();
+        let pmul = c.header.fra / c.header.dur;
+        let count = std::cmp::min(c.partials(), self.synths.len());
+        let frames = c.frames.len();
+        let outs = self.mode.outputs();
+        self.place(&c, count, inputs[0][0] as f64 * pmul);
+
+        for s in self.synths[0..count].iter_mut() {
+            s.load();
+        }
+
+        let binaural = match self.mode {
+            SpatMode::Binaural => true,
+            _ => false,
+        };
+        let noise_out = match self.noise_out.load(LOAD_ORDERING) {
+            c if c >= 0 && (c as usize) < outs => Some(c as usize),
+            _ => None,
+        };
+        for i in 0..outputs[0].len() {
+            let pos = (inputs[0][i] as f64) * pmul;
+            let in_range = pos >= 0f64 && pos < frames.saturating_sub(1) as f64;
+            let (p0, p1, fract) = frame_pos(pos, frames);
+            let f0 = &c.frames[p0];
+            let f1 = &c.frames[p1];
+            for out in outputs.iter_mut() {
+                out[i] = 0 as pd_sys::t_float;
+            }
+            for (p, s) in self.synths[0..count].iter_mut().enumerate() {
+                if s.muted() {
+                    continue;
+                }
+                let f = lerp(f0[p].freq, f1[p].freq, fract);
+                let (a, n) = if in_range {
+                    (
+                        lerp(f0[p].amp, f1[p].amp, fract),
+                        if with_noise {
+                            lerp(
+                                f0[p].noise_energy.unwrap(),
+                                f1[p].noise_energy.unwrap(),
+                                fract,
+                            )
+                        } else {
+                            0f64
+                        },
+                    )
+                } else {
+                    (0f64, 0f64)
+                };
+                let gains = &self.gains[p * outs..(p + 1) * outs];
+                //with a noise channel the sines are synthesized without noise and the residual
+                //is taken separately
+                let sn = if noise_out.is_some() { 0f64 } else { n };
+                if binaural {
+                    let (l, r) = s.synth_binaural(f, a, sn, self.delays[p], (gains[0], gains[1]));
+                    outputs[0][i] += l;
+                    outputs[1][i] += r;
+                } else {
+                    let v = s.synth(f, a, sn) as f64;
+                    for (out, g) in outputs.iter_mut().zip(gains.iter()) {
+                        out[i] += (v * g) as pd_sys::t_float;
+                    }
+                }
+                if let Some(c) = noise_out {
+                    outputs[c][i] += s.residual(n);
+                }
+            }
+        }
+    }
+}
+
+pd_ext_macros::external! {
+    #[name = "ats/spat~"]
+    pub struct AtsSpatExternal {
+        data_send: SyncSender<Option<Arc<AtsData>>>,
+        handles: Box<[ParitalSynthHandle]>,
+        azimuth: Box<[ArcAtomic<f64>]>,
+        elevation: Box<[ArcAtomic<f64>]>,
+        freq_pan: ArcAtomic<bool>,
+        freq_pan_range: (ArcAtomic<f64>, ArcAtomic<f64>),
+        noise_out: ArcAtomic<isize>,
+        oversample: ArcAtomic<f64>,
+        post: Box<dyn PdPost>,
+    }
+
+    impl AtsSpatExternal {
+        #[sel]
+        pub fn ats_data(&mut self, key: pd_ext::symbol::Symbol) {
+            let d = crate::cache::get(key);
+            let _ = self.data_send.try_send(d);
+        }
+
+        //the same as ats_data, there is no crossfade to skip, so ats/seq can drive this too
+        #[sel]
+        pub fn ats_target(&mut self, key: pd_ext::symbol::Symbol) {
+            self.ats_data(key);
+        }
+
+        #[sel]
+        pub fn clear(&mut self) {
+            let _ = self.data_send.send(None);
+        }
+
+        //the up/down sampling of an enclosing block~, the processor only sees pd's rate
+        #[sel]
+        pub fn oversample(&mut self, factor: pd_sys::t_float) {
+            if factor > 0f32 {
+                self.oversample.store(factor as f64, STORE_ORDERING);
+            } else {
+                self.post.post_error("oversample expects a positive factor".into());
+            }
+        }
+
+        #[sel]
+        pub fn azimuth(&mut self, args: &[pd_ext::atom::Atom]) {
+            Self::set_index_value(&*self.post, &self.azimuth, args);
+        }
+
+        #[sel]
+        pub fn elevation(&mut self, args: &[pd_ext::atom::Atom]) {
+            Self::set_index_value(&*self.post, &self.elevation, args);
+        }
+
+        //derive azimuth from frequency, low to high frequencies spread from lo to hi degrees
+        #[sel]
+        pub fn freq_pan(&mut self, args: &[pd_ext::atom::Atom]) {
+            let v: Vec<f64> = args.iter().filter_map(|a| a.get_float()).map(|v| v as f64).collect();
+            if v.len() != args.len() || (v.len() != 1 && v.len() != 3) {
+                self.post.post_error("freq_pan expects <0/1> [lo degrees] [hi degrees]".into());
+                return;
+            }
+            self.freq_pan.store(v[0] != 0f64, STORE_ORDERING);
+            if v.len() == 3 {
+                self.freq_pan_range.0.store(v[1], STORE_ORDERING);
+                self.freq_pan_range.1.store(v[2], STORE_ORDERING);
+            }
+        }
+
+        //send the residual of every partial to one output channel, counting from 0, instead of
+        //spatializing it, a negative channel goes back to spatializing
+        #[sel]
+        pub fn noise_out(&mut self, channel: pd_sys::t_float) {
+            self.noise_out.store(channel.floor() as isize, STORE_ORDERING);
+        }
+
+        #[sel]
+        pub fn freq_mul(&mut self, args: &[pd_ext::atom::Atom]) {
+            self.apply_if(args, |s, v| s.freq_mul(v));
+        }
+
+        #[sel]
+        pub fn amp_mul(&mut self, args: &[pd_ext::atom::Atom]) {
+            self.apply_if(args, |s, v| s.amp_mul(v));
+        }
+
+        #[sel]
+        pub fn noise_amp_mul(&mut self, args: &[pd_ext::atom::Atom]) {
+            self.apply_if(args, |s, v| s.noise_amp_mul(v));
+        }
+
+        fn set_index_value(post: &dyn PdPost, values: &[ArcAtomic<f64>], args: &[pd_ext::atom::Atom]) {
+            match crate::externals::index_value_args(args, values.len()) {
+                Ok((Some(i), v)) => {
+                    if i < values.len() {
+                        values[i].store(v, STORE_ORDERING);
+                    }
+                }
+                Ok((None, v)) => {
+                    for a in values.iter() {
+                        a.store(v, STORE_ORDERING);
+                    }
+                }
+                Err(msg) => post.post_error(msg),
+            }
+        }
+
+        fn apply_if<F: Fn(&mut ParitalSynthHandle, f64)>(&mut self, args: &[pd_ext::atom::Atom], f: F) {
+            match crate::externals::index_value_args(args, self.handles.len()) {
+                Ok((i, v)) =>
+                    if let Some(i) = i {
+                        if i < self.handles.len() {
+                            f(&mut self.handles[i], v)
+                        }
+                    } else {
+                        for s in self.handles.iter_mut() {
+                            f(s, v);
+                        }
+                    },
+                Err(msg) => self.post.post_error(msg)
+            }
+        }
+    }
+
+    impl SignalProcessorExternal for AtsSpatExternal {
+        fn new(builder: &mut dyn SignalProcessorExternalBuilder<Self>) -> Result<(Self, Box<dyn SignalProcessor>), String> {
+            let (data_send, data_recv) = sync_channel(32);
+            let args = builder.creation_args();
+
+            //<partials> [binaural | foa | <speaker count> | <speaker azimuth degrees>...]
+            let partials = match args.get(0).and_then(|a| a.get_int()) {
+                Some(v) if v > 0 => v as usize,
+                _ => return Err("first argument must be a non zero partial count".into()),
+            };
+            let mode = if args.len() < 2 || args[1].get_symbol() == Some(*BINAURAL) {
+                SpatMode::Binaural
+            } else if args[1].get_symbol() == Some(*FOA) {
+                SpatMode::Foa
+            } else {
+                let v: Vec<f64> = args[1..].iter().filter_map(|a| a.get_float()).map(|v| v as f64).collect();
+                if v.len() != args.len() - 1 {
+                    return Err("expected 'binaural', 'foa', a speaker count or a list of speaker azimuths".into());
+                }
+                let mut speakers: Vec<f64> = if v.len() == 1 {
+                    let n = v[0] as usize;
+                    if n < 2 {
+                        return Err("vbap needs at least 2 speakers".into());
+                    }
+                    even_speakers(n)
+                } else {
+                    v
+                };
+                let mut speakers: Vec<f64> = speakers.drain(..).map(|s| s.to_radians()).collect();
+                speakers.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                SpatMode::Vbap(speakers.into())
+            };
+
+            for _ in 0..mode.outputs() {
+                builder.new_signal_outlet();
+            }
+
+            let times = SlewTimes::default();
+            let sample_rate = pd_ext::pd::sample_rate() as f64;
+            let mut synths = Vec::new();
+            let mut handles = Vec::new();
+            let mut azimuth = Vec::new();
+            let mut elevation = Vec::new();
+            for _ in 0..partials {
+                let (h, s) = ParitalSynthHandle::new(&times, sample_rate);
+                handles.push(h);
+                synths.push(s);
+                azimuth.push(Arc::new(Atomic::new(0f64)));
+                elevation.push(Arc::new(Atomic::new(0f64)));
+            }
+            let freq_pan = Arc::new(Atomic::new(false));
+            let freq_pan_range = (Arc::new(Atomic::new(-90f64)), Arc::new(Atomic::new(90f64)));
+            let outs = mode.outputs();
+            let noise_out = Arc::new(Atomic::new(-1isize));
+            let oversample = Arc::new(Atomic::new(1f64));
+
+            Ok(
+                (
+                    Self {
+                        data_send,
+                        handles: handles.into(),
+                        azimuth: azimuth.clone().into(),
+                        elevation: elevation.clone().into(),
+                        freq_pan: freq_pan.clone(),
+                        freq_pan_range: (freq_pan_range.0.clone(), freq_pan_range.1.clone()),
+                        noise_out: noise_out.clone(),
+                        oversample: oversample.clone(),
+                        post: builder.poster()
+                    },
+                    Box::new(AtsSpatProcessor {
+                        current: None,
+                        data_recv,
+                        mode,
+                        synths: synths.into(),
+                        azimuth: azimuth.into(),
+                        elevation: elevation.into(),
+                        freq_pan,
+                        freq_pan_range,
+                        noise_out,
+                        gains: vec![0f64; partials * outs].into(),
+                        delays: vec![0f64; partials].into(),
+                        sample_rate,
+                        oversample,
+                    })
+                )
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //the gains for azimuth in degrees over the speakers, in degrees and sorted
+    fn gains(speakers: &[f64], azimuth: f64) -> Vec<f64> {
+        let speakers: Vec<f64> = speakers.iter().map(|s| s.to_radians()).collect();
+        let mut g = vec![0f64; speakers.len()];
+        vbap_gains(&speakers, azimuth.to_radians(), &mut g);
+        g
+    }
+
+    fn assert_gains(g: &[f64], expected: &[f64]) {
+        for (g, e) in g.iter().zip(expected) {
+            assert!((g - e).abs() < 1e-9, "{:?} != {:?}", g, e);
+        }
+    }
+
+    #[test]
+    fn vbap_on_a_speaker() {
+        let speakers = even_speakers(4);
+        assert_eq!(speakers, vec![-135f64, -45f64, 45f64, 135f64]);
+        for (i, s) in speakers.iter().enumerate() {
+            let mut expected = vec![0f64; 4];
+            expected[i] = 1f64;
+            assert_gains(&gains(&speakers, *s), &expected);
+        }
+    }
+
+    #[test]
+    fn vbap_between_a_pair() {
+        let g = gains(&even_speakers(4), 0f64);
+        let h = std::f64::consts::FRAC_1_SQRT_2;
+        assert_gains(&g, &[0f64, h, h, 0f64]);
+        //a pair that isn't symmetric about the front
+        let g = gains(&[-30f64, 10f64, 110f64], 60f64);
+        assert_gains(&g, &[0f64, h, h]);
+    }
+
+    #[test]
+    fn vbap_across_the_seam() {
+        let h = std::f64::consts::FRAC_1_SQRT_2;
+        //behind, between the last and first speakers
+        assert_gains(&gains(&even_speakers(4), 180f64), &[h, 0f64, 0f64, h]);
+        assert_gains(&gains(&even_speakers(4), -180f64), &[h, 0f64, 0f64, h]);
+    }
+
+    #[test]
+    fn vbap_outside_the_sorted_range() {
+        let speakers = even_speakers(4);
+        //below the first speaker and past a full turn are wrapped into the speaker range
+        let g = gains(&speakers, -170f64);
+        assert_gains(&g, &gains(&speakers, 190f64));
+        assert_gains(&g, &gains(&speakers, -170f64 + 720f64));
+        assert_gains(&g, &gains(&speakers, -170f64 - 720f64));
+        assert_eq!((g[1], g[2]), (0f64, 0f64));
+        //closer to the first speaker than the last
+        assert!(g[0] > g[3] && g[3] > 0f64);
+        assert!((g[0] * g[0] + g[3] * g[3] - 1f64).abs() < 1e-9);
+    }
+}
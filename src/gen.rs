@@ -0,0 +1,104 @@
+use crate::data::{band_of, frame_samps, rms_energy, AtsData, Peak, NOISE_BANDS, NOISE_BAND_EDGES};
+
+//frames per second of generated data
+pub const GEN_FRAME_RATE: f64 = 100f64;
+//rms amplitude of each band at the start of a noise burst
+const BURST_RMS: f64 = 0.05f64;
+
+fn frame_count(dur: f64) -> usize {
+    ((dur * GEN_FRAME_RATE).ceil() as usize).max(2)
+}
+
+fn peak(freq: f64, amp: f64, noise_energy: Option<f64>) -> Peak {
+    Peak {
+        amp,
+        freq,
+        noise_energy,
+        phase: None,
+        band: band_of(freq),
+    }
+}
+
+//n steady harmonics of f0, each decay times the amplitude of the one below, summing to 1
+pub fn harmonic(f0: f64, n: usize, decay: f64, dur: f64, sr: f64) -> AtsData {
+    let amps: Vec<f64> = (0..n).map(|k| decay.powi(k as i32)).collect();
+    let total: f64 = amps.iter().sum();
+    let frame: Box<[Peak]> = amps
+        .iter()
+        .enumerate()
+        .map(|(k, a)| {
+            let a = if total > 0f64 { a / total } else { 0f64 };
+            peak(f0 * (k + 1) as f64, a, None)
+        })
+        .collect();
+    let frames = vec![frame; frame_count(dur)];
+    AtsData::from_peaks(frames, None, dur, sr, format!("harmonic_{}_{}", f0, n))
+}
+
+//residual only, every critical band starting together and decaying exponentially with the given
+//time constant in seconds. the partials sit silently at the band centers to carry the noise
+pub fn noiseburst(decay: f64, dur: f64, sr: f64) -> AtsData {
+    let count = frame_count(dur);
+    let ws = 2f64 * frame_samps(count, dur, sr);
+    let mut frames = Vec::with_capacity(count);
+    let mut noise = Vec::with_capacity(count);
+    for i in 0..count {
+        let t = i as f64 * dur / count as f64;
+        let rms = if decay > 0f64 {
+            BURST_RMS * (-t / decay).exp()
+        } else {
+            0f64
+        };
+        frames.push(
+            (0..NOISE_BANDS)
+                .map(|b| {
+                    let center = (NOISE_BAND_EDGES[b] + NOISE_BAND_EDGES[b + 1]) * 0.5f64;
+                    peak(center, 0f64, Some(rms))
+                })
+                .collect::<Box<[Peak]>>(),
+        );
+        noise.push([rms_energy(rms, ws); NOISE_BANDS]);
+    }
+    AtsData::from_peaks(
+        frames,
+        Some(noise.into_boxed_slice()),
+        dur,
+        sr,
+        format!("noiseburst_{}", decay),
+    )
+}
+
+//a single partial gliding exponentially from one frequency to another
+pub fn sweep(from: f64, to: f64, dur: f64, sr: f64) -> AtsData {
+    let count = frame_count(dur);
+    let frames = (0..count)
+        .map(|i| {
+            let t = i as f64 / (count - 1) as f64;
+            let freq = from * (to / from).powf(t);
+            vec![peak(freq, 0.5f64, None)].into_boxed_slice()
+        })
+        .collect();
+    AtsData::from_peaks(frames, None, dur, sr, format!("sweep_{}_{}", from, to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generators_fill_headers() {
+        let h = harmonic(100f64, 4, 0.5f64, 1f64, 44100f64);
+        assert_eq!(h.partials(), 4);
+        assert_eq!(h.frames.len(), GEN_FRAME_RATE as usize);
+        assert_eq!(h.frames[0][3].freq, 400f64);
+        assert!((h.amp_sum(0) - 1f64).abs() < 1e-9);
+
+        let b = noiseburst(0.1f64, 0.5f64, 44100f64);
+        assert!(b.has_noise());
+        assert!(b.band_rms(0, 3) > b.band_rms(b.frames.len() - 1, 3));
+
+        let s = sweep(100f64, 1000f64, 1f64, 44100f64);
+        assert_eq!(s.frames[0][0].freq, 100f64);
+        assert!((s.frames[s.frames.len() - 1][0].freq - 1000f64).abs() < 1e-6);
+    }
+}
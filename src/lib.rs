@@ -1,27 +1,71 @@
 mod cache;
 mod data;
+mod decode;
+mod ducker;
 mod externals;
+mod gen;
+mod pitch;
+mod pool;
+mod render;
+mod sdif;
+mod synth;
+mod text;
 
 use std::convert::TryFrom;
 
+//synthesis without a pd runtime
+#[cfg(feature = "offline")]
+pub mod offline {
+    pub use crate::data::{AtsData, AtsDataType, Peak};
+    pub use crate::render::{render_with, write_wav, Transforms};
+}
+
 extern "C" {
+    fn atsaboutexternal_setup();
+    fn atsbandsexternal_tilde_setup();
     fn atsdataexternal_setup();
+    fn atsseqexternal_setup();
     fn atssinnoiexternal_tilde_setup();
+    fn atsspatexternal_tilde_setup();
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn ats_setup() {
+    atsaboutexternal_setup();
+    atsbandsexternal_tilde_setup();
     atsdataexternal_setup();
+    atsseqexternal_setup();
     atssinnoiexternal_tilde_setup();
+    atsspatexternal_tilde_setup();
 
+    let help = pd_ext::symbol::Symbol::try_from("ats-about").expect("failed to create help sym");
+    pd_sys::class_sethelpsymbol(
+        crate::externals::about::ATSABOUTEXTERNAL_CLASS.unwrap(),
+        help.inner(),
+    );
+    let help = pd_ext::symbol::Symbol::try_from("ats-bands~").expect("failed to create help sym");
+    pd_sys::class_sethelpsymbol(
+        crate::externals::bands::ATSBANDSEXTERNAL_CLASS.unwrap(),
+        help.inner(),
+    );
     let help = pd_ext::symbol::Symbol::try_from("ats-data").expect("failed to create help sym");
     pd_sys::class_sethelpsymbol(
         crate::externals::data::ATSDATAEXTERNAL_CLASS.unwrap(),
         help.inner(),
     );
+    let help = pd_ext::symbol::Symbol::try_from("ats-seq").expect("failed to create help sym");
+    pd_sys::class_sethelpsymbol(
+        crate::externals::seq::ATSSEQEXTERNAL_CLASS.unwrap(),
+        help.inner(),
+    );
     let help = pd_ext::symbol::Symbol::try_from("ats-sinnoi~").expect("failed to create help sym");
     pd_sys::class_sethelpsymbol(
         crate::externals::sinnoi::ATSSINNOIEXTERNAL_CLASS.unwrap(),
         help.inner(),
     );
+    let help = pd_ext::symbol::Symbol::try_from("ats-spat~").expect("failed to create help sym");
+    pd_sys::class_sethelpsymbol(
+        crate::externals::spat::ATSSPATEXTERNAL_CLASS.unwrap(),
+        help.inner(),
+    );
 }
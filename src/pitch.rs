@@ -0,0 +1,215 @@
+use crate::data::{AtsData, Peak};
+
+//two-way mismatch weights, from Maher and Beauchamp
+const TWM_P: f64 = 0.5f64;
+const TWM_Q: f64 = 1.4f64;
+const TWM_R: f64 = 0.5f64;
+const TWM_RHO: f64 = 0.33f64;
+//harmonics predicted from each candidate
+const TWM_HARMONICS: usize = 10;
+//loudest peaks that are compared against each candidate and used to derive candidates
+const TWM_PEAKS: usize = 10;
+//relative distance to a harmonic that still counts as harmonic for the confidence
+const HARMONIC_TOLERANCE: f64 = 0.03f64;
+
+//the f0 of a frame and how much of its amplitude it explains, 0..1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct F0 {
+    pub freq: f64,
+    pub confidence: f64,
+}
+
+impl F0 {
+    const NONE: F0 = F0 {
+        freq: 0f64,
+        confidence: 0f64,
+    };
+}
+
+//(freq, amp) of the loudest sounding peaks
+fn loudest(peaks: &[Peak]) -> Vec<(f64, f64)> {
+    let mut v: Vec<(f64, f64)> = peaks
+        .iter()
+        .filter(|p| p.amp > 0f64 && p.freq > 0f64)
+        .map(|p| (p.freq, p.amp))
+        .collect();
+    v.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    v.truncate(TWM_PEAKS);
+    v
+}
+
+fn mismatch(df: f64, freq: f64, amp: f64) -> f64 {
+    let w = df * freq.powf(-TWM_P);
+    w + amp * (TWM_Q * w - TWM_R)
+}
+
+//two-way mismatch error of a candidate f0, lower is better
+fn twm_error(f0: f64, peaks: &[(f64, f64)], amp_max: f64) -> f64 {
+    let freq_max = peaks.iter().map(|p| p.0).fold(0f64, f64::max);
+    let harmonics = std::cmp::max(
+        1,
+        std::cmp::min(TWM_HARMONICS, (freq_max / f0).ceil() as usize),
+    );
+    let nearest = |f: f64| {
+        peaks
+            .iter()
+            .min_by(|a, b| {
+                (a.0 - f)
+                    .abs()
+                    .partial_cmp(&(b.0 - f).abs())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .cloned()
+            .unwrap()
+    };
+    //predicted to measured
+    let pm: f64 = (1..=harmonics)
+        .map(|h| {
+            let f = f0 * h as f64;
+            let (pf, pa) = nearest(f);
+            mismatch((pf - f).abs(), f, pa / amp_max)
+        })
+        .sum();
+    //measured to predicted
+    let mp: f64 = peaks
+        .iter()
+        .map(|(f, a)| {
+            let h = (f / f0).round().max(1f64);
+            mismatch((f - h * f0).abs(), *f, a / amp_max)
+        })
+        .sum();
+    pm / harmonics as f64 + TWM_RHO * mp / peaks.len() as f64
+}
+
+//estimate the f0 of one frame of peaks, within min..max hz
+pub fn estimate(peaks: &[Peak], min: f64, max: f64) -> F0 {
+    let peaks = loudest(peaks);
+    if peaks.is_empty() {
+        return F0::NONE;
+    }
+    let amp_max = peaks[0].1;
+    //candidates are the subharmonics of the loudest peaks
+    let best = peaks
+        .iter()
+        .flat_map(|(f, _)| (1..=TWM_HARMONICS).map(move |h| f / h as f64))
+        .filter(|c| *c >= min && *c <= max)
+        .map(|c| (c, twm_error(c, &peaks, amp_max)))
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+    match best {
+        Some((freq, _)) => {
+            let total: f64 = peaks.iter().map(|p| p.1).sum();
+            let harmonic: f64 = peaks
+                .iter()
+                .filter(|(f, _)| {
+                    let h = (f / freq).round().max(1f64);
+                    (f - h * freq).abs() / (h * freq) <= HARMONIC_TOLERANCE
+                })
+                .map(|p| p.1)
+                .sum();
+            F0 {
+                freq,
+                confidence: harmonic / total,
+            }
+        }
+        None => F0::NONE,
+    }
+}
+
+//the harmonic number nearest a frequency
+fn nearest_harmonic(freq: f64, f0: f64) -> f64 {
+    (freq / f0).round().max(1f64)
+}
+
+//deviation of a frequency from its nearest harmonic of f0, in cents
+pub fn harmonic_deviation(freq: f64, f0: f64) -> f64 {
+    if freq <= 0f64 || f0 <= 0f64 {
+        return 0f64;
+    }
+    1200f64 * (freq / (nearest_harmonic(freq, f0) * f0)).log2()
+}
+
+//power weighted distance of the peaks from the harmonics of f0, relative to f0, 0 for a
+//harmonic frame and at most 1
+pub fn inharmonicity(peaks: &[Peak], f0: f64) -> f64 {
+    if f0 <= 0f64 {
+        return 0f64;
+    }
+    let (dev, power) = peaks.iter().filter(|p| p.amp > 0f64 && p.freq > 0f64).fold(
+        (0f64, 0f64),
+        |(dev, power), p| {
+            let h = nearest_harmonic(p.freq, f0);
+            let a2 = p.amp * p.amp;
+            (dev + (p.freq - h * f0).abs() * a2, power + a2)
+        },
+    );
+    if power > 0f64 {
+        2f64 * dev / (f0 * power)
+    } else {
+        0f64
+    }
+}
+
+//the f0 of every frame
+pub fn track(data: &AtsData, min: f64, max: f64) -> Vec<F0> {
+    data.frames.iter().map(|f| estimate(f, min, max)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //one frame of (freq, amp) peaks
+    fn peaks(v: &[(f64, f64)]) -> Vec<Peak> {
+        AtsData::synthetic(&[v.to_vec()], 0.1f64, 44100f64, "peaks".into()).frames[0].to_vec()
+    }
+
+    #[test]
+    fn harmonic_series() {
+        let p = peaks(&[(220f64, 1f64), (440f64, 0.5f64), (660f64, 0.3f64)]);
+        let f0 = estimate(&p, 50f64, 2000f64);
+        assert!((f0.freq - 220f64).abs() < 1f64, "{:?}", f0);
+        assert!(f0.confidence > 0.99f64);
+    }
+
+    #[test]
+    fn weak_fundamental_no_octave_error() {
+        let p = peaks(&[
+            (200f64, 0.1f64),
+            (400f64, 1f64),
+            (600f64, 0.8f64),
+            (800f64, 0.6f64),
+            (1000f64, 0.4f64),
+        ]);
+        let f0 = estimate(&p, 50f64, 2000f64);
+        assert!((f0.freq - 200f64).abs() < 1f64, "{:?}", f0);
+        //an inharmonic peak lowers the confidence
+        let p = peaks(&[(200f64, 1f64), (400f64, 1f64), (530f64, 1f64)]);
+        let f0 = estimate(&p, 50f64, 2000f64);
+        assert!((f0.freq - 200f64).abs() < 1f64, "{:?}", f0);
+        assert!((f0.confidence - 2f64 / 3f64).abs() < 1e-9);
+    }
+
+    #[test]
+    fn inharmonicity_and_deviation() {
+        let p = peaks(&[(100f64, 1f64), (200f64, 1f64), (300f64, 1f64)]);
+        assert_eq!(inharmonicity(&p, 100f64), 0f64);
+        //half way between harmonics is the most inharmonic
+        let p = peaks(&[(150f64, 1f64)]);
+        assert!((inharmonicity(&p, 100f64) - 1f64).abs() < 1e-9);
+        assert!(
+            (harmonic_deviation(200f64 * 2f64.powf(10f64 / 1200f64), 100f64) - 10f64).abs() < 1e-9
+        );
+        assert_eq!(harmonic_deviation(0f64, 100f64), 0f64);
+    }
+
+    #[test]
+    fn silence_and_range() {
+        assert_eq!(
+            estimate(&peaks(&[(220f64, 0f64)]), 50f64, 2000f64),
+            F0::NONE
+        );
+        //nothing can be found below the range
+        let f0 = estimate(&peaks(&[(100f64, 1f64)]), 150f64, 2000f64);
+        assert_eq!(f0, F0::NONE);
+    }
+}
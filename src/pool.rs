@@ -0,0 +1,124 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+//spins before a waiting worker parks
+const SPINS: usize = 10_000;
+
+type Job<'a> = dyn Fn(usize) + Sync + 'a;
+
+struct Shared {
+    epoch: AtomicUsize,
+    done: AtomicUsize,
+    quit: AtomicBool,
+    //the job of the current epoch, only valid while run is waiting on the workers
+    job: UnsafeCell<Option<*const Job<'static>>>,
+}
+
+unsafe impl Send for Shared {}
+unsafe impl Sync for Shared {}
+
+//a fixed set of worker threads that run a job along with the calling thread, run doesn't lock or
+//allocate so it can be used to split a dsp block
+pub struct Pool {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Pool {
+    //threads includes the calling thread
+    pub fn new(threads: usize) -> Self {
+        let shared = Arc::new(Shared {
+            epoch: AtomicUsize::new(0),
+            done: AtomicUsize::new(0),
+            quit: AtomicBool::new(false),
+            job: UnsafeCell::new(None),
+        });
+        let workers = (1..threads)
+            .map(|index| {
+                let shared = shared.clone();
+                thread::spawn(move || work(&shared, index))
+            })
+            .collect();
+        Self { shared, workers }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.workers.len() + 1
+    }
+
+    //call job with each thread index, 0 on the calling thread, returning once all are done
+    pub fn run<'a>(&self, job: &Job<'a>) {
+        unsafe {
+            //the job only has to outlive this call, the workers are done with it before we return
+            let job: &Job<'static> = std::mem::transmute(job);
+            *self.shared.job.get() = Some(job as *const Job<'static>);
+        }
+        self.shared.done.store(0, Ordering::Relaxed);
+        self.shared.epoch.fetch_add(1, Ordering::Release);
+        for w in self.workers.iter() {
+            w.thread().unpark();
+        }
+        job(0);
+        while self.shared.done.load(Ordering::Acquire) < self.workers.len() {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.shared.quit.store(true, Ordering::Release);
+        for w in self.workers.drain(..) {
+            w.thread().unpark();
+            let _ = w.join();
+        }
+    }
+}
+
+fn work(shared: &Shared, index: usize) {
+    let mut seen = 0;
+    loop {
+        let mut spins = 0;
+        loop {
+            if shared.quit.load(Ordering::Acquire) {
+                return;
+            }
+            let epoch = shared.epoch.load(Ordering::Acquire);
+            if epoch != seen {
+                seen = epoch;
+                break;
+            }
+            if spins < SPINS {
+                spins += 1;
+                std::hint::spin_loop();
+            } else {
+                thread::park();
+            }
+        }
+        unsafe {
+            if let Some(job) = *shared.job.get() {
+                (*job)(index);
+            }
+        }
+        shared.done.fetch_add(1, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_every_index() {
+        let pool = Pool::new(4);
+        let hits: Vec<AtomicUsize> = (0..4).map(|_| AtomicUsize::new(0)).collect();
+        for _ in 0..100 {
+            pool.run(&|i| {
+                hits[i].fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        assert!(hits.iter().all(|h| h.load(Ordering::Relaxed) == 100));
+    }
+}
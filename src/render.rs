@@ -0,0 +1,323 @@
+use crate::data::AtsData;
+use crate::synth::{frame_pos, lerp, ParitalSynth, ParitalSynthHandle, SlewTimes};
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+//transforms applied to every partial of an offline render
+#[derive(Clone, Copy)]
+pub struct Transforms {
+    pub freq_mul: f64,
+    pub amp_mul: f64,
+    pub noise_amp_mul: f64,
+}
+
+impl Default for Transforms {
+    fn default() -> Self {
+        Self {
+            freq_mul: 1f64,
+            amp_mul: 1f64,
+            noise_amp_mul: 1f64,
+        }
+    }
+}
+
+//synthesize the whole of data, without transformation, at the given sample rate
+pub fn render(data: &AtsData, sample_rate: f64) -> Vec<f32> {
+    render_with(data, sample_rate, &Transforms::default(), None)
+}
+
+//synthesize the whole of data with transforms, a seed makes the noise, and so the output,
+//repeatable
+pub fn render_with(
+    data: &AtsData,
+    sample_rate: f64,
+    transforms: &Transforms,
+    seed: Option<u64>,
+) -> Vec<f32> {
+    let len = (data.header.dur * sample_rate) as usize;
+    let positions: Vec<f64> = (0..len).map(|i| i as f64 / sample_rate).collect();
+    render_positions(data, sample_rate, &positions, transforms, seed)
+}
+
+//synthesize data at a position, in seconds, per sample, in any order
+pub fn render_positions(
+    data: &AtsData,
+    sample_rate: f64,
+    positions: &[f64],
+    transforms: &Transforms,
+    seed: Option<u64>,
+) -> Vec<f32> {
+    let times = SlewTimes::default();
+    let mut synths: Vec<ParitalSynth> = (0..data.partials())
+        .map(|p| {
+            let mut s = ParitalSynthHandle::new(&times, sample_rate).1;
+            if let Some(seed) = seed {
+                s.seed(seed.wrapping_add(p as u64));
+            }
+            s
+        })
+        .collect();
+    let with_noise = data.has_noise();
+    let frames = data.frames.len();
+    let pmul = data.header.fra / data.header.dur;
+
+    positions
+        .iter()
+        .map(|secs| {
+            let pos = secs * pmul;
+            let (p0, p1, fract) = frame_pos(pos, frames);
+            let f0 = &data.frames[p0];
+            let f1 = &data.frames[p1];
+            let mut out = 0f32;
+            for (p, s) in synths.iter_mut().enumerate() {
+                let n = if with_noise {
+                    lerp(
+                        f0[p].noise_energy.unwrap(),
+                        f1[p].noise_energy.unwrap(),
+                        fract,
+                    )
+                } else {
+                    0f64
+                };
+                out += s.synth(
+                    transforms.freq_mul * lerp(f0[p].freq, f1[p].freq, fract),
+                    transforms.amp_mul * lerp(f0[p].amp, f1[p].amp, fract),
+                    transforms.noise_amp_mul * n,
+                );
+            }
+            out
+        })
+        .collect()
+}
+
+//write mono 32 bit float wav
+pub fn write_wav<P: AsRef<std::path::Path>>(
+    path: P,
+    samples: &[f32],
+    sample_rate: u32,
+) -> std::io::Result<()> {
+    let mut f = BufWriter::new(File::create(path)?);
+    let data_len = (samples.len() * 4) as u32;
+    f.write_all(b"RIFF")?;
+    f.write_u32::<LittleEndian>(36 + data_len)?;
+    f.write_all(b"WAVEfmt ")?;
+    f.write_u32::<LittleEndian>(16)?;
+    f.write_u16::<LittleEndian>(3)?; //IEEE float
+    f.write_u16::<LittleEndian>(1)?;
+    f.write_u32::<LittleEndian>(sample_rate)?;
+    f.write_u32::<LittleEndian>(sample_rate * 4)?;
+    f.write_u16::<LittleEndian>(4)?;
+    f.write_u16::<LittleEndian>(32)?;
+    f.write_all(b"data")?;
+    f.write_u32::<LittleEndian>(data_len)?;
+    for s in samples {
+        f.write_f32::<LittleEndian>(*s)?;
+    }
+    f.flush()
+}
+
+//the largest side of an exported image, width and height are clamped to 1..=PNG_MAX_SIDE
+pub const PNG_MAX_SIDE: u32 = 16384;
+
+//draw the partials as an 8 bit grayscale image, time left to right and frequency up to the max
+//frequency of the data, brightness by amplitude in dB
+pub fn write_png<P: AsRef<std::path::Path>>(
+    path: P,
+    data: &AtsData,
+    width: u32,
+    height: u32,
+) -> Result<(), png::EncodingError> {
+    let width = width.clamp(1, PNG_MAX_SIDE);
+    let height = height.clamp(1, PNG_MAX_SIDE);
+    let size = (width as usize).checked_mul(height as usize).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "png dimensions too large")
+    })?;
+    let mut pixels = vec![0u8; size];
+    let frames = data.frames.len();
+    let freq_max = if data.header.mf > 0f64 {
+        data.header.mf
+    } else {
+        1f64
+    };
+    let amp_max = if data.header.ma > 0f64 {
+        data.header.ma
+    } else {
+        1f64
+    };
+    for (i, frame) in data.frames.iter().enumerate() {
+        let x = (i * width as usize / frames) as u32;
+        for p in frame.iter().filter(|p| p.amp > 0f64) {
+            let y = ((p.freq / freq_max) * (height - 1) as f64).round();
+            if y < 0f64 || y >= height as f64 {
+                continue;
+            }
+            //-60 dB and below is black
+            let db = 20f64 * (p.amp / amp_max).log10();
+            let v = ((db + 60f64) / 60f64).max(0f64).min(1f64) * 255f64;
+            let pixel = &mut pixels[(height - 1 - y as u32) as usize * width as usize + x as usize];
+            *pixel = std::cmp::max(*pixel, v as u8);
+        }
+    }
+
+    let f = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(f, width, height);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{AtsDataType, Peak, NOISE_BANDS};
+
+    //two partials, a steady 440 and a 1000 falling to 500, with noise on the second
+    fn data() -> AtsData {
+        let frames = 20;
+        let header = ats_sys::ATS_HEADER {
+            mag: 123f64,
+            sr: 44100f64,
+            fs: 512f64,
+            ws: 1024f64,
+            par: 2f64,
+            fra: frames as f64,
+            ma: 0.5f64,
+            mf: 1000f64,
+            dur: 0.2f64,
+            typ: 3f64,
+        };
+        let frames: Vec<Box<[Peak]>> = (0..frames)
+            .map(|f| {
+                let f = f as f64 / (frames - 1) as f64;
+                vec![
+                    Peak {
+                        amp: 0.5f64,
+                        freq: 440f64,
+                        noise_energy: Some(0f64),
+                        phase: None,
+                        band: 4,
+                    },
+                    Peak {
+                        amp: 0.25f64 * (1f64 - f),
+                        freq: 1000f64 - 500f64 * f,
+                        noise_energy: Some(0.1f64),
+                        phase: None,
+                        band: 9,
+                    },
+                ]
+                .into_boxed_slice()
+            })
+            .collect();
+        let noise = vec![[0f64; NOISE_BANDS]; 20].into_boxed_slice();
+        AtsData::new(
+            header,
+            frames,
+            Some(noise),
+            AtsDataType::AmpFreqNoise,
+            "test".into(),
+        )
+    }
+
+    //samples quantized so that the sum doesn't depend on the last bits of sin
+    fn checksum(samples: &[f32]) -> u64 {
+        samples.iter().fold(0xcbf29ce484222325u64, |h, s| {
+            let q = (*s as f64 * 1000f64).round() as i64 as u64;
+            (h ^ q).wrapping_mul(0x100000001b3u64)
+        })
+    }
+
+    //the seeded noise comes from ChaCha8, whose stream is fixed, unlike StdRng's which may change
+    //with a rand upgrade
+    #[test]
+    fn render_golden() {
+        let out = render_with(&data(), 44100f64, &Transforms::default(), Some(1));
+        assert_eq!(out.len(), 8820);
+        assert_eq!(checksum(&out), 9410297374032823986);
+    }
+
+    #[test]
+    fn render_repeatable() {
+        let t = Transforms {
+            freq_mul: 1.5f64,
+            amp_mul: 0.5f64,
+            noise_amp_mul: 2f64,
+        };
+        let a = render_with(&data(), 48000f64, &t, Some(7));
+        let b = render_with(&data(), 48000f64, &t, Some(7));
+        assert_eq!(a, b);
+    }
+
+    //the largest step between samples, bigger than the partials can move on their own at a glitch
+    fn max_step(samples: &[f32]) -> f32 {
+        samples
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0f32, f32::max)
+    }
+
+    #[test]
+    fn render_reverse_and_palindrome() {
+        let d = data();
+        let sr = 44100f64;
+        let t = Transforms {
+            noise_amp_mul: 0f64,
+            ..Transforms::default()
+        };
+        let len = (d.header.dur * sr) as usize;
+        let forward: Vec<f64> = (0..len).map(|i| i as f64 / sr).collect();
+        let reverse: Vec<f64> = forward.iter().rev().cloned().collect();
+        let half = len / 2;
+        let palindrome: Vec<f64> = forward[..half]
+            .iter()
+            .chain(forward[..half].iter().rev())
+            .cloned()
+            .collect();
+
+        //a full scale step per sample of both partials, plus a little for the amplitude ramps
+        let bound = (2f64 * std::f64::consts::PI * (0.5f64 * 440f64 + 0.25f64 * 1000f64) / sr)
+            as f32
+            * 1.1f32;
+        let f = render_positions(&d, sr, &forward, &t, Some(1));
+        let r = render_positions(&d, sr, &reverse, &t, Some(1));
+        let p = render_positions(&d, sr, &palindrome, &t, Some(1));
+        assert!(max_step(&f) < bound);
+        assert!(max_step(&r) < bound);
+        assert!(max_step(&p) < bound);
+
+        //the envelope runs backwards
+        let rms = |s: &[f32]| (s.iter().map(|v| v * v).sum::<f32>() / s.len() as f32).sqrt();
+        let w = len / 10;
+        assert!((rms(&f[..w]) - rms(&r[len - w..])).abs() < 0.01f32);
+        assert!((rms(&f[len - w..]) - rms(&r[..w])).abs() < 0.01f32);
+
+        //with noise, back and forth is repeatable and stays bounded
+        let n = render_positions(&d, sr, &palindrome, &Transforms::default(), Some(3));
+        assert_eq!(
+            n,
+            render_positions(&d, sr, &palindrome, &Transforms::default(), Some(3))
+        );
+        assert!(n.iter().all(|s| s.is_finite() && s.abs() < 1f32));
+    }
+
+    #[test]
+    fn render_silent() {
+        let t = Transforms {
+            amp_mul: 0f64,
+            noise_amp_mul: 0f64,
+            ..Transforms::default()
+        };
+        let out = render_with(&data(), 44100f64, &t, Some(1));
+        assert!(out.iter().all(|s| *s == 0f32));
+    }
+
+    #[test]
+    fn png_clamps_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clamped.png");
+        write_png(&path, &data(), 0, u32::MAX).unwrap();
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let (info, _) = decoder.read_info().unwrap();
+        assert_eq!((info.width, info.height), (1, PNG_MAX_SIDE));
+    }
+}
@@ -0,0 +1,332 @@
+use crate::data::{tracks_duration, AtsData, TrackPoint};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+//sdif doesn't carry a sample rate with tracks
+const SDIF_SR: f64 = 44100f64;
+
+const FLOAT32: i32 = 0x0004;
+const FLOAT64: i32 = 0x0008;
+
+//frame and matrix types with rows of index, frequency, amplitude and phase
+const TRACK_TYPES: [&[u8; 4]; 2] = [b"1TRC", b"1HRM"];
+//loris bandwidth-enhanced partials, rows of index, frequency, amplitude, phase, bandwidth and
+//time offset. loris writes 1TRC when bandwidth enhancement is off
+const LORIS_TYPES: [&[u8; 4]; 2] = [b"RBEP", b"1TRC"];
+
+fn padding(bytes: usize) -> usize {
+    (8 - bytes % 8) % 8
+}
+
+fn skip<R: Read>(r: &mut R, bytes: usize) -> std::io::Result<()> {
+    std::io::copy(&mut r.take(bytes as u64), &mut std::io::sink())?;
+    Ok(())
+}
+
+//the rows of a matrix, which must be float32 or float64 and fit in the bytes left in its frame.
+//nothing is allocated from the counts until they are checked against the frame
+fn read_matrix<R: Read>(
+    r: &mut R,
+    kind: i32,
+    rows: usize,
+    cols: usize,
+    frame_bytes: usize,
+) -> Result<Vec<Vec<f64>>, String> {
+    let size = match kind {
+        FLOAT32 => 4,
+        FLOAT64 => 8,
+        _ => return Err(format!("unsupported sdif matrix data type {:#x}", kind)),
+    };
+    //rows without columns still take space
+    if rows
+        .checked_mul(std::cmp::max(cols, 1))
+        .and_then(|n| n.checked_mul(size))
+        .map_or(true, |n| n > frame_bytes)
+    {
+        return Err(format!(
+            "a {} by {} matrix doesn't fit in its frame",
+            rows, cols
+        ));
+    }
+    let mut out = Vec::with_capacity(rows);
+    for _ in 0..rows {
+        let mut row = Vec::with_capacity(cols);
+        for _ in 0..cols {
+            let v = if size == 4 {
+                r.read_f32::<BigEndian>().map(|v| v as f64)
+            } else {
+                r.read_f64::<BigEndian>()
+            };
+            row.push(v.map_err(|e| e.to_string())?);
+        }
+        out.push(row);
+    }
+    skip(r, padding(rows * cols * size)).map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+//the frames of the first stream of the given types, matrix rows of [index, freq, amp, ...]
+pub(crate) fn read_frames<P: AsRef<Path>>(
+    path: P,
+    types: &[&[u8; 4]],
+) -> Result<Vec<(f64, Vec<Vec<f64>>)>, String> {
+    let path = path.as_ref();
+    let err = |e: std::io::Error| format!("failed to read {}: {}", path.display(), e);
+    let mut r = BufReader::new(File::open(path).map_err(err)?);
+    let mut sig = [0u8; 4];
+    r.read_exact(&mut sig).map_err(err)?;
+    if &sig != b"SDIF" {
+        return Err(format!("{} is not an sdif file", path.display()));
+    }
+    let size = r.read_i32::<BigEndian>().map_err(err)?;
+    skip(&mut r, size.max(0) as usize).map_err(err)?;
+
+    let mut stream = None;
+    let mut frames = Vec::new();
+    loop {
+        match r.read_exact(&mut sig) {
+            Ok(()) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(err(e)),
+        }
+        let size = r.read_i32::<BigEndian>().map_err(err)?;
+        let time = r.read_f64::<BigEndian>().map_err(err)?;
+        let id = r.read_i32::<BigEndian>().map_err(err)?;
+        let matrices = r.read_i32::<BigEndian>().map_err(err)?;
+        let wanted = types.iter().any(|t| **t == sig) && stream.map_or(true, |s| s == id);
+        //the size counts the time, stream id and matrix count
+        let frame_bytes = size.saturating_sub(16).max(0) as usize;
+        if !wanted {
+            skip(&mut r, frame_bytes).map_err(err)?;
+            continue;
+        }
+        stream = Some(id);
+        let mut rows = Vec::new();
+        for _ in 0..matrices {
+            let mut msig = [0u8; 4];
+            r.read_exact(&mut msig).map_err(err)?;
+            let kind = r.read_i32::<BigEndian>().map_err(err)?;
+            let count = r.read_i32::<BigEndian>().map_err(err)?.max(0) as usize;
+            let cols = r.read_i32::<BigEndian>().map_err(err)?.max(0) as usize;
+            let m = read_matrix(&mut r, kind, count, cols, frame_bytes)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            if msig == sig {
+                rows.extend(m.into_iter().filter(|row| row.len() >= 3));
+            }
+        }
+        frames.push((time, rows));
+    }
+    if frames.is_empty() {
+        return Err(format!("no track frames in {}", path.display()));
+    }
+    Ok(frames)
+}
+
+//1TRC or 1HRM tracks, frames are assumed to be evenly spaced
+pub fn read<P: AsRef<Path>>(path: P) -> Result<AtsData, String> {
+    let frames = read_frames(&path, &TRACK_TYPES)?;
+    let times: Vec<f64> = frames.iter().map(|f| f.0).collect();
+    let points: Vec<Vec<TrackPoint>> = frames
+        .into_iter()
+        .map(|(_, rows)| {
+            rows.into_iter()
+                .map(|row| TrackPoint {
+                    id: row[0] as u64,
+                    freq: row[1],
+                    amp: row[2],
+                    phase: row.get(3).cloned(),
+                    noise: None,
+                })
+                .collect()
+        })
+        .collect();
+    AtsData::from_tracks(
+        &points,
+        tracks_duration(&times),
+        SDIF_SR,
+        path.as_ref().to_string_lossy().into_owned(),
+    )
+}
+
+//split a loris amplitude into sine amplitude and rms noise by the bandwidth, the fraction of the
+//point's energy that is noise
+fn loris_point(row: &[f64]) -> TrackPoint {
+    let (amp, noise) = match row.get(4) {
+        Some(bw) => {
+            let bw = bw.clamp(0f64, 1f64);
+            (row[2] * (1f64 - bw).sqrt(), Some(row[2] * bw.sqrt()))
+        }
+        None => (row[2], None),
+    };
+    TrackPoint {
+        id: row[0] as u64,
+        freq: row[1],
+        amp,
+        phase: row.get(3).cloned(),
+        noise,
+    }
+}
+
+//loris partials, bandwidth becomes noise so the partials drive noise modulated sines, the time
+//offsets of the points are ignored
+pub fn read_loris<P: AsRef<Path>>(path: P) -> Result<AtsData, String> {
+    let frames = read_frames(&path, &LORIS_TYPES)?;
+    let times: Vec<f64> = frames.iter().map(|f| f.0).collect();
+    let points: Vec<Vec<TrackPoint>> = frames
+        .into_iter()
+        .map(|(_, rows)| rows.iter().map(|row| loris_point(row)).collect())
+        .collect();
+    AtsData::from_tracks(
+        &points,
+        tracks_duration(&times),
+        SDIF_SR,
+        path.as_ref().to_string_lossy().into_owned(),
+    )
+}
+
+//1TRC frames of the sounding partials, indexed from 1. sdif tracks have no noise
+pub fn write<P: AsRef<Path>>(data: &AtsData, path: P) -> std::io::Result<()> {
+    let mut f = BufWriter::new(File::create(path)?);
+    f.write_all(b"SDIF")?;
+    f.write_i32::<BigEndian>(8)?;
+    f.write_u32::<BigEndian>(3)?; //sdif version
+    f.write_u32::<BigEndian>(1)?; //standard types version
+    for (i, frame) in data.frames.iter().enumerate() {
+        let sounding: Vec<(usize, &crate::data::Peak)> = frame
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.amp > 0f64)
+            .collect();
+        let data_size = sounding.len() * 4 * 8;
+        f.write_all(b"1TRC")?;
+        f.write_i32::<BigEndian>((16 + 16 + data_size) as i32)?;
+        f.write_f64::<BigEndian>(data.frame_time(i))?;
+        f.write_i32::<BigEndian>(0)?;
+        f.write_i32::<BigEndian>(1)?;
+        f.write_all(b"1TRC")?;
+        f.write_i32::<BigEndian>(FLOAT64)?;
+        f.write_i32::<BigEndian>(sounding.len() as i32)?;
+        f.write_i32::<BigEndian>(4)?;
+        for (j, p) in sounding {
+            f.write_f64::<BigEndian>((j + 1) as f64)?;
+            f.write_f64::<BigEndian>(p.freq)?;
+            f.write_f64::<BigEndian>(p.amp)?;
+            f.write_f64::<BigEndian>(p.phase.unwrap_or(0f64))?;
+        }
+    }
+    f.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let frames = vec![
+            vec![(440f64, 0.5f64), (880f64, 0.25f64)],
+            vec![(441f64, 0.4f64), (0f64, 0f64)],
+            vec![(442f64, 0.3f64), (0f64, 0f64)],
+        ];
+        let d = AtsData::synthetic(&frames, 0.3f64, 44100f64, "sdif".into());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("d.sdif");
+        write(&d, &path).unwrap();
+        let r = read(&path).unwrap();
+        assert_eq!(r.frames.len(), 3);
+        assert_eq!(r.partials(), 2);
+        assert_eq!(r.frames[2][0].freq, 442f64);
+        assert_eq!(r.frames[1][1].amp, 0f64);
+        //the silent point keeps the frequency of the partial before it
+        assert_eq!(r.frames[1][1].freq, 880f64);
+        assert!((r.header.dur - 0.3f64).abs() < 1e-9);
+    }
+
+    //a single stream of RBEP frames, each with one matrix
+    fn write_rbep(path: &Path, frames: &[(f64, Vec<[f64; 6]>)]) {
+        let mut f = File::create(path).unwrap();
+        f.write_all(b"SDIF").unwrap();
+        f.write_i32::<BigEndian>(8).unwrap();
+        f.write_u32::<BigEndian>(3).unwrap();
+        f.write_u32::<BigEndian>(1).unwrap();
+        for (time, rows) in frames {
+            f.write_all(b"RBEP").unwrap();
+            f.write_i32::<BigEndian>((32 + rows.len() * 6 * 8) as i32)
+                .unwrap();
+            f.write_f64::<BigEndian>(*time).unwrap();
+            f.write_i32::<BigEndian>(2).unwrap();
+            f.write_i32::<BigEndian>(1).unwrap();
+            f.write_all(b"RBEP").unwrap();
+            f.write_i32::<BigEndian>(FLOAT64).unwrap();
+            f.write_i32::<BigEndian>(rows.len() as i32).unwrap();
+            f.write_i32::<BigEndian>(6).unwrap();
+            for v in rows.iter().flatten() {
+                f.write_f64::<BigEndian>(*v).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn loris_bandwidth_becomes_noise() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("l.sdif");
+        write_rbep(
+            &path,
+            &[
+                (0f64, vec![[1f64, 440f64, 0.5f64, 0f64, 0.36f64, 0f64]]),
+                (
+                    0.01f64,
+                    vec![
+                        [1f64, 441f64, 0.5f64, 0.1f64, 0f64, 0f64],
+                        [2f64, 900f64, 0.2f64, 0f64, 1f64, 0.001f64],
+                    ],
+                ),
+            ],
+        );
+        let d = read_loris(&path).unwrap();
+        assert_eq!(d.frames.len(), 2);
+        assert_eq!(d.partials(), 2);
+        let p = &d.frames[0][0];
+        assert!((p.amp - 0.4f64).abs() < 1e-9);
+        assert!((p.noise_energy.unwrap() - 0.3f64).abs() < 1e-9);
+        //pure noise
+        let p = &d.frames[1][1];
+        assert_eq!(p.amp, 0f64);
+        assert!((p.noise_energy.unwrap() - 0.2f64).abs() < 1e-9);
+        assert_eq!(d.frames[1][0].phase, Some(0.1f64));
+        //plain sdif tracks aren't loris partials
+        assert!(read(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("x.sdif");
+        std::fs::write(&path, b"RIFF0000").unwrap();
+        assert!(read(&path).is_err());
+        //a single frame has nothing to interpolate with
+        write_rbep(
+            &path,
+            &[(0f64, vec![[1f64, 440f64, 0.5f64, 0f64, 0f64, 0f64]])],
+        );
+        assert!(read_loris(&path).is_err());
+        //matrix counts far bigger than the frame fail before allocating
+        let mut f = File::create(&path).unwrap();
+        f.write_all(b"SDIF").unwrap();
+        f.write_i32::<BigEndian>(8).unwrap();
+        f.write_u64::<BigEndian>(0).unwrap();
+        f.write_all(b"1TRC").unwrap();
+        f.write_i32::<BigEndian>(32).unwrap();
+        f.write_f64::<BigEndian>(0f64).unwrap();
+        f.write_i32::<BigEndian>(0).unwrap();
+        f.write_i32::<BigEndian>(1).unwrap();
+        f.write_all(b"1TRC").unwrap();
+        f.write_i32::<BigEndian>(FLOAT64).unwrap();
+        f.write_i32::<BigEndian>(i32::MAX).unwrap();
+        f.write_i32::<BigEndian>(i32::MAX).unwrap();
+        drop(f);
+        assert!(read(&path).is_err());
+    }
+}
@@ -0,0 +1,1380 @@
+use atomic::Atomic;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use std::sync::Arc;
+
+pub const STORE_ORDERING: std::sync::atomic::Ordering = std::sync::atomic::Ordering::Relaxed;
+pub const LOAD_ORDERING: std::sync::atomic::Ordering = std::sync::atomic::Ordering::Relaxed;
+
+pub type ArcAtomic<T> = Arc<Atomic<T>>;
+
+//zero out subnormals, they are very slow on some cpus and inaudible anyway
+#[inline]
+pub fn flush_denormal(x: f64) -> f64 {
+    if (x.to_bits() >> 52) & 0x7ff == 0 {
+        0f64
+    } else {
+        x
+    }
+}
+
+//sets the flush to zero and denormals are zero flags for the current thread while alive,
+//restoring the previous flags on drop. a no-op off x86_64
+pub struct FlushDenormals {
+    #[cfg(target_arch = "x86_64")]
+    csr: u32,
+}
+
+#[cfg(target_arch = "x86_64")]
+#[allow(deprecated)]
+mod csr {
+    use std::arch::x86_64::{_mm_getcsr, _mm_setcsr};
+
+    //FTZ and DAZ
+    const FLAGS: u32 = 0x8040;
+
+    pub fn enable() -> u32 {
+        unsafe {
+            let csr = _mm_getcsr();
+            _mm_setcsr(csr | FLAGS);
+            csr
+        }
+    }
+
+    pub fn restore(csr: u32) {
+        unsafe { _mm_setcsr(csr) }
+    }
+}
+
+impl FlushDenormals {
+    #[cfg(target_arch = "x86_64")]
+    pub fn new() -> Self {
+        Self { csr: csr::enable() }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Drop for FlushDenormals {
+    fn drop(&mut self) {
+        #[cfg(target_arch = "x86_64")]
+        csr::restore(self.csr);
+    }
+}
+
+//corner of the output dc blocker
+const DC_BLOCK_HZ: f64 = 10f64;
+//the fixed slew increments are per sample at this rate, scaled to the running rate
+const SLEW_RATE: f64 = 44100f64;
+
+//one pole, one zero dc blocking filter
+#[derive(Default, Clone, Copy)]
+pub struct DcBlock {
+    x1: f64,
+    y1: f64,
+}
+
+impl DcBlock {
+    //the pole radius for a sample rate
+    pub fn coeff(sample_rate: f64) -> f64 {
+        1f64 - 2f64 * std::f64::consts::PI * DC_BLOCK_HZ / sample_rate
+    }
+
+    pub fn process(&mut self, x: f64, coeff: f64) -> f64 {
+        let y = flush_denormal(x - self.x1 + coeff * self.y1);
+        self.x1 = x;
+        self.y1 = y;
+        y
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum AdsrStage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+//linear attack, decay and release segments, in samples, and the sustain level
+#[derive(Clone, Copy)]
+pub struct AdsrTimes {
+    pub attack: f64,
+    pub decay: f64,
+    pub sustain: f64,
+    pub release: f64,
+}
+
+//a note envelope, retriggering attacks from the current level
+#[derive(Clone, Copy)]
+pub struct Adsr {
+    stage: AdsrStage,
+    level: f64,
+    //the level the release started from
+    released: f64,
+}
+
+impl Default for Adsr {
+    fn default() -> Self {
+        Self {
+            stage: AdsrStage::Off,
+            level: 0f64,
+            released: 0f64,
+        }
+    }
+}
+
+impl Adsr {
+    pub fn on(&mut self) {
+        self.stage = AdsrStage::Attack;
+    }
+
+    pub fn off(&mut self) {
+        if self.stage != AdsrStage::Off {
+            self.stage = AdsrStage::Release;
+            self.released = self.level;
+        }
+    }
+
+    pub fn process(&mut self, t: &AdsrTimes) -> f64 {
+        let inc = |samples: f64| if samples > 1f64 { 1f64 / samples } else { 1f64 };
+        match self.stage {
+            AdsrStage::Attack => {
+                self.level += inc(t.attack);
+                if self.level >= 1f64 {
+                    self.level = 1f64;
+                    self.stage = AdsrStage::Decay;
+                }
+            }
+            AdsrStage::Decay => {
+                self.level -= (1f64 - t.sustain) * inc(t.decay);
+                if self.level <= t.sustain {
+                    self.level = t.sustain;
+                    self.stage = AdsrStage::Sustain;
+                }
+            }
+            AdsrStage::Sustain => self.level = t.sustain,
+            AdsrStage::Release => {
+                self.level -= self.released * inc(t.release);
+                if self.level <= 0f64 {
+                    self.level = 0f64;
+                    self.stage = AdsrStage::Off;
+                }
+            }
+            AdsrStage::Off => (),
+        }
+        self.level
+    }
+}
+
+//smooth saturation towards +-1, nearly linear at low levels
+pub fn soft_clip(x: f64) -> f64 {
+    x.tanh()
+}
+
+fn noise(rng: &mut ChaCha8Rng) -> f64 {
+    rng.gen_range(-1f64, 1f64)
+}
+
+//chance that a velvet noise draw is an impulse rather than silence
+const VELVET_DENSITY: f64 = 0.25f64;
+
+//the distribution of the noise modulator's breakpoints, all with the rms of uniform noise
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NoiseType {
+    Uniform,
+    Gaussian,
+    //sparse impulses of random sign
+    Velvet,
+}
+
+impl Default for NoiseType {
+    fn default() -> Self {
+        NoiseType::Uniform
+    }
+}
+
+impl NoiseType {
+    fn draw(self, rng: &mut ChaCha8Rng) -> f64 {
+        match self {
+            NoiseType::Uniform => noise(rng),
+            NoiseType::Gaussian => {
+                //box-muller, scaled to the 1/sqrt(3) deviation of the uniform draw
+                let u = 1f64 - rng.gen::<f64>();
+                let v = rng.gen::<f64>();
+                (-2f64 * u.ln()).sqrt() * (2f64 * std::f64::consts::PI * v).cos() / 3f64.sqrt()
+            }
+            NoiseType::Velvet => {
+                if rng.gen::<f64>() < VELVET_DENSITY {
+                    let a = (1f64 / (3f64 * VELVET_DENSITY)).sqrt();
+                    if rng.gen::<bool>() {
+                        a
+                    } else {
+                        -a
+                    }
+                } else {
+                    0f64
+                }
+            }
+        }
+    }
+}
+
+pub struct Slewed {
+    cur: f64,
+    dest: ArcAtomic<f64>,
+    inc: f64,
+    //ramp time in ms, when non zero the increment is computed from the distance to a new
+    //destination instead of using inc
+    ramp: ArcAtomic<f64>,
+    target: f64,
+    step: f64,
+    //dest, inc and ramp, in samples, as of the last load
+    dest_val: f64,
+    inc_val: f64,
+    ramp_val: f64,
+}
+
+impl Slewed {
+    pub fn new(dest: ArcAtomic<f64>, inc: f64, ramp: ArcAtomic<f64>, sample_rate: f64) -> Self {
+        let cur = dest.load(LOAD_ORDERING);
+        let mut s = Self {
+            cur,
+            dest,
+            inc,
+            ramp,
+            target: cur,
+            step: 0f64,
+            dest_val: cur,
+            inc_val: inc,
+            ramp_val: 0f64,
+        };
+        s.load(sample_rate);
+        s
+    }
+    pub fn val(&self) -> f64 {
+        self.cur
+    }
+    //read the shared destination and ramp time, once per block
+    pub fn load(&mut self, sample_rate: f64) {
+        self.dest_val = self.dest.load(LOAD_ORDERING);
+        self.inc_val = self.inc * SLEW_RATE / sample_rate;
+        self.ramp_val = self.ramp.load(LOAD_ORDERING) * sample_rate / 1000f64;
+    }
+    pub fn update(&mut self) {
+        let dest = self.dest_val;
+        let ramp = self.ramp_val;
+        let inc = if ramp > 0f64 {
+            if dest != self.target {
+                self.target = dest;
+                self.step = (dest - self.cur).abs() / ramp;
+            }
+            self.step
+        } else {
+            self.inc_val
+        };
+        self.cur = if self.cur == dest || (self.cur - dest).abs() <= inc {
+            dest
+        } else if self.cur < dest {
+            self.cur + inc
+        } else {
+            self.cur - inc
+        };
+    }
+}
+
+//sine oscillator implementation, exact or from a linearly interpolated table
+#[derive(Clone)]
+pub enum Osc {
+    Exact,
+    Table(Arc<[f64]>),
+}
+
+impl Osc {
+    //a table with size points plus a guard point for interpolation
+    pub fn table(size: usize) -> Self {
+        let table: Vec<f64> = (0..=size)
+            .map(|i| (2f64 * std::f64::consts::PI * i as f64 / size as f64).sin())
+            .collect();
+        Osc::Table(table.into())
+    }
+
+    //sine of phase, in cycles
+    fn sin(&self, phase: f64) -> f64 {
+        match self {
+            Osc::Exact => (2f64 * std::f64::consts::PI * phase).sin(),
+            Osc::Table(t) => table_sin(t, phase),
+        }
+    }
+}
+
+fn table_sin(t: &[f64], phase: f64) -> f64 {
+    let pos = phase.rem_euclid(1f64) * (t.len() - 1) as f64;
+    let i = pos as usize;
+    if i + 1 < t.len() {
+        lerp(t[i], t[i + 1], pos.fract())
+    } else {
+        t[i]
+    }
+}
+
+impl Default for Osc {
+    fn default() -> Self {
+        Osc::Exact
+    }
+}
+
+//oscillators advanced and summed together by process_lanes
+pub const LANES: usize = 4;
+
+//odd polynomial sine of a phase in cycles, branch free so that it vectorizes, error around 1e-7
+fn poly_sin(phase: f64) -> f64 {
+    let t = phase - phase.round();
+    //fold into -0.25..0.25
+    let t = if t > 0.25f64 {
+        0.5f64 - t
+    } else if t < -0.25f64 {
+        -0.5f64 - t
+    } else {
+        t
+    };
+    let x = 2f64 * std::f64::consts::PI * t;
+    let x2 = x * x;
+    x * (1f64
+        + x2 * (-1f64 / 6f64
+            + x2 * (1f64 / 120f64
+                + x2 * (-1f64 / 5040f64 + x2 * (1f64 / 362880f64 - x2 / 39916800f64)))))
+}
+
+//a bank's oscillators as struct of arrays, one slot per synth: advance each phase, in cycles,
+//by its increment and sum the sines scaled by amps, LANES slots at a time. the table oscillator
+//is used when there is one, the polynomial sine otherwise
+pub fn process_lanes(phases: &mut [f64], incs: &[f64], amps: &[f64], osc: &Osc) -> f64 {
+    match osc {
+        Osc::Exact => lanes_with(phases, incs, amps, poly_sin),
+        Osc::Table(t) => lanes_with(phases, incs, amps, |p| table_sin(t, p)),
+    }
+}
+
+fn lanes_with<F: Fn(f64) -> f64>(phases: &mut [f64], incs: &[f64], amps: &[f64], sin: F) -> f64 {
+    let mut acc = [0f64; LANES];
+    let step = |acc: &mut f64, p: &mut f64, i: &f64, a: &f64| {
+        *p = (*p + i).fract();
+        *acc += sin(*p) * a;
+    };
+    let mut chunks = phases.chunks_exact_mut(LANES);
+    let mut incs = incs.chunks_exact(LANES);
+    let mut amps = amps.chunks_exact(LANES);
+    for ((p, i), a) in (&mut chunks).zip(&mut incs).zip(&mut amps) {
+        for (((acc, p), i), a) in acc.iter_mut().zip(p.iter_mut()).zip(i).zip(a) {
+            step(acc, p, i, a);
+        }
+    }
+    let mut rem = 0f64;
+    let tail = chunks.into_remainder().iter_mut();
+    for ((p, i), a) in tail.zip(incs.remainder()).zip(amps.remainder()) {
+        step(&mut rem, p, i, a);
+    }
+    acc.iter().sum::<f64>() + rem
+}
+
+//ramp times, in ms, shared by all the synths in a bank
+#[derive(Clone)]
+pub struct SlewTimes {
+    pub freq_mul: ArcAtomic<f64>,
+    pub freq_add: ArcAtomic<f64>,
+    pub amp_mul: ArcAtomic<f64>,
+    pub noise_amp_mul: ArcAtomic<f64>,
+    pub noise_bw_scale: ArcAtomic<f64>,
+    pub noise_bw_hz: ArcAtomic<f64>,
+}
+
+impl Default for SlewTimes {
+    fn default() -> Self {
+        Self {
+            freq_mul: Arc::new(Atomic::new(0f64)),
+            freq_add: Arc::new(Atomic::new(0f64)),
+            amp_mul: Arc::new(Atomic::new(0f64)),
+            noise_amp_mul: Arc::new(Atomic::new(0f64)),
+            noise_bw_scale: Arc::new(Atomic::new(0f64)),
+            noise_bw_hz: Arc::new(Atomic::new(0f64)),
+        }
+    }
+}
+
+pub struct ParitalSynth {
+    osc: Osc,
+    rng: ChaCha8Rng,
+    phase_freq_mul: f64,
+    phase: f64,
+    noise_phase: f64,
+    noise_x0: f64,
+    noise_x1: f64,
+    //decorrelated noise for stereo width
+    noise_y0: f64,
+    noise_y1: f64,
+    //the left and right noise modulators after the optional lowpass
+    noise_l: f64,
+    noise_r: f64,
+
+    //params
+    freq_mul: Slewed,
+    freq_add: Slewed,
+    amp_mul: Slewed,
+    noise_amp_mul: Slewed,
+    noise_bw_scale: Slewed,
+    //absolute noise bandwidth, used instead of the frequency relative scale when noise_bw_abs is set
+    noise_bw_hz: Slewed,
+    noise_bw_abs: ArcAtomic<bool>,
+    noise_type: ArcAtomic<NoiseType>,
+    //one pole lowpass cutoff for the noise modulator in hz, 0 is off
+    noise_lp: ArcAtomic<f64>,
+    mute: ArcAtomic<bool>,
+    //independently enable the deterministic and stochastic parts
+    sine_on: ArcAtomic<bool>,
+    noise_on: ArcAtomic<bool>,
+
+    //static and random detune, in cents
+    detune: ArcAtomic<f64>,
+    jitter_depth: ArcAtomic<f64>,
+    jitter_rate: ArcAtomic<f64>,
+    jitter_phase: f64,
+    jitter_x0: f64,
+    jitter_x1: f64,
+
+    //fade times, in ms, for when the partial is born and dies
+    fade_in: ArcAtomic<f64>,
+    fade_out: ArcAtomic<f64>,
+
+    //the shared values above as of the last load
+    loaded: Loaded,
+    //the oscillator phase, in cycles, as of the last report
+    phase_out: ArcAtomic<f64>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Loaded {
+    noise_bw_abs: bool,
+    noise_type: NoiseType,
+    noise_lp_coeff: Option<f64>,
+    mute: bool,
+    sine_on: bool,
+    noise_on: bool,
+    detune: f64,
+    jitter_depth: f64,
+    jitter_rate: f64,
+    fade_in: f64,
+    fade_out: f64,
+}
+
+//a partial's transformation parameters, for capturing and restoring them
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PartialParams {
+    pub freq_mul: f64,
+    pub freq_add: f64,
+    pub amp_mul: f64,
+    pub noise_amp_mul: f64,
+    pub noise_bw_scale: f64,
+    pub noise_bw_hz: f64,
+    pub detune: f64,
+    pub mute: bool,
+    pub sine_on: bool,
+    pub noise_on: bool,
+    pub jitter_depth: f64,
+    pub jitter_rate: f64,
+    pub fade_in: f64,
+    pub fade_out: f64,
+    pub noise_type: NoiseType,
+    pub noise_lp: f64,
+}
+
+impl PartialParams {
+    //linear interpolation from self to other, the switches change halfway
+    pub fn lerp(&self, other: &Self, frac: f64) -> Self {
+        let switched = if frac < 0.5f64 { self } else { other };
+        Self {
+            freq_mul: lerp(self.freq_mul, other.freq_mul, frac),
+            freq_add: lerp(self.freq_add, other.freq_add, frac),
+            amp_mul: lerp(self.amp_mul, other.amp_mul, frac),
+            noise_amp_mul: lerp(self.noise_amp_mul, other.noise_amp_mul, frac),
+            noise_bw_scale: lerp(self.noise_bw_scale, other.noise_bw_scale, frac),
+            noise_bw_hz: lerp(self.noise_bw_hz, other.noise_bw_hz, frac),
+            detune: lerp(self.detune, other.detune, frac),
+            mute: switched.mute,
+            sine_on: switched.sine_on,
+            noise_on: switched.noise_on,
+            jitter_depth: lerp(self.jitter_depth, other.jitter_depth, frac),
+            jitter_rate: lerp(self.jitter_rate, other.jitter_rate, frac),
+            fade_in: lerp(self.fade_in, other.fade_in, frac),
+            fade_out: lerp(self.fade_out, other.fade_out, frac),
+            noise_type: switched.noise_type,
+            noise_lp: lerp(self.noise_lp, other.noise_lp, frac),
+        }
+    }
+}
+
+//the parameters shared between a handle and its synth
+#[derive(Clone)]
+struct SharedParams {
+    freq_mul: ArcAtomic<f64>,
+    freq_add: ArcAtomic<f64>,
+    amp_mul: ArcAtomic<f64>,
+    noise_amp_mul: ArcAtomic<f64>,
+    noise_bw_scale: ArcAtomic<f64>,
+    noise_bw_hz: ArcAtomic<f64>,
+    noise_bw_abs: ArcAtomic<bool>,
+    noise_type: ArcAtomic<NoiseType>,
+    noise_lp: ArcAtomic<f64>,
+    mute: ArcAtomic<bool>,
+    sine_on: ArcAtomic<bool>,
+    noise_on: ArcAtomic<bool>,
+    detune: ArcAtomic<f64>,
+    jitter_depth: ArcAtomic<f64>,
+    jitter_rate: ArcAtomic<f64>,
+    fade_in: ArcAtomic<f64>,
+    fade_out: ArcAtomic<f64>,
+}
+
+impl SharedParams {
+    //the default values, or the current values of like
+    fn new(like: Option<&SharedParams>) -> Self {
+        fn init<T: Copy>(like: Option<&ArcAtomic<T>>, default: T) -> ArcAtomic<T> {
+            Arc::new(Atomic::new(
+                like.map(|v| v.load(LOAD_ORDERING)).unwrap_or(default),
+            ))
+        }
+        Self {
+            freq_mul: init(like.map(|l| &l.freq_mul), 1f64),
+            freq_add: init(like.map(|l| &l.freq_add), 0f64),
+            amp_mul: init(like.map(|l| &l.amp_mul), 1f64),
+            noise_amp_mul: init(like.map(|l| &l.noise_amp_mul), 1f64),
+            noise_bw_scale: init(like.map(|l| &l.noise_bw_scale), 0.1f64),
+            noise_bw_hz: init(like.map(|l| &l.noise_bw_hz), 100f64),
+            noise_bw_abs: init(like.map(|l| &l.noise_bw_abs), false),
+            noise_type: init(like.map(|l| &l.noise_type), NoiseType::Uniform),
+            noise_lp: init(like.map(|l| &l.noise_lp), 0f64),
+            mute: init(like.map(|l| &l.mute), false),
+            sine_on: init(like.map(|l| &l.sine_on), true),
+            noise_on: init(like.map(|l| &l.noise_on), true),
+            detune: init(like.map(|l| &l.detune), 0f64),
+            jitter_depth: init(like.map(|l| &l.jitter_depth), 0f64),
+            jitter_rate: init(like.map(|l| &l.jitter_rate), 1f64),
+            fade_in: init(like.map(|l| &l.fade_in), 0f64),
+            fade_out: init(like.map(|l| &l.fade_out), 0f64),
+        }
+    }
+}
+
+pub struct ParitalSynthHandle {
+    shared: SharedParams,
+    phase: ArcAtomic<f64>,
+}
+
+impl ParitalSynthHandle {
+    pub fn freq_mul(&mut self, v: f64) {
+        self.shared.freq_mul.store(v, STORE_ORDERING);
+    }
+
+    pub fn freq_add(&mut self, v: f64) {
+        self.shared.freq_add.store(v, STORE_ORDERING);
+    }
+
+    pub fn amp_mul(&mut self, v: f64) {
+        self.shared.amp_mul.store(v, STORE_ORDERING);
+    }
+
+    pub fn noise_amp_mul(&mut self, v: f64) {
+        self.shared.noise_amp_mul.store(v, STORE_ORDERING);
+    }
+
+    pub fn noise_bw_scale(&mut self, v: f64) {
+        self.shared.noise_bw_scale.store(v, STORE_ORDERING);
+    }
+
+    pub fn noise_bw_hz(&mut self, v: f64) {
+        self.shared.noise_bw_hz.store(v.max(0f64), STORE_ORDERING);
+    }
+
+    //use noise_bw_hz rather than noise_bw_scale
+    pub fn noise_bw_abs(&mut self, v: bool) {
+        self.shared.noise_bw_abs.store(v, STORE_ORDERING);
+    }
+
+    pub fn noise_type(&mut self, v: NoiseType) {
+        self.shared.noise_type.store(v, STORE_ORDERING);
+    }
+
+    //lowpass the noise modulator at this many hz, 0 disables the filter
+    pub fn noise_lp(&mut self, hz: f64) {
+        self.shared.noise_lp.store(hz.max(0f64), STORE_ORDERING);
+    }
+
+    pub fn mute(&mut self, v: bool) {
+        self.shared.mute.store(v, STORE_ORDERING);
+    }
+
+    pub fn sine_on(&mut self, v: bool) {
+        self.shared.sine_on.store(v, STORE_ORDERING);
+    }
+
+    pub fn noise_on(&mut self, v: bool) {
+        self.shared.noise_on.store(v, STORE_ORDERING);
+    }
+
+    pub fn detune(&mut self, cents: f64) {
+        self.shared.detune.store(cents, STORE_ORDERING);
+    }
+
+    pub fn jitter(&mut self, depth_cents: f64, rate_hz: f64) {
+        self.shared.jitter_depth.store(depth_cents, STORE_ORDERING);
+        self.shared
+            .jitter_rate
+            .store(rate_hz.max(0f64), STORE_ORDERING);
+    }
+
+    //fade, over ms, in when the partial is born and out when it dies, 0 disables
+    pub fn fade_in(&mut self, ms: f64) {
+        self.shared.fade_in.store(ms.max(0f64), STORE_ORDERING);
+    }
+
+    pub fn fade_out(&mut self, ms: f64) {
+        self.shared.fade_out.store(ms.max(0f64), STORE_ORDERING);
+    }
+
+    pub fn params(&self) -> PartialParams {
+        PartialParams {
+            freq_mul: self.shared.freq_mul.load(LOAD_ORDERING),
+            freq_add: self.shared.freq_add.load(LOAD_ORDERING),
+            amp_mul: self.shared.amp_mul.load(LOAD_ORDERING),
+            noise_amp_mul: self.shared.noise_amp_mul.load(LOAD_ORDERING),
+            noise_bw_scale: self.shared.noise_bw_scale.load(LOAD_ORDERING),
+            noise_bw_hz: self.shared.noise_bw_hz.load(LOAD_ORDERING),
+            detune: self.shared.detune.load(LOAD_ORDERING),
+            mute: self.shared.mute.load(LOAD_ORDERING),
+            sine_on: self.shared.sine_on.load(LOAD_ORDERING),
+            noise_on: self.shared.noise_on.load(LOAD_ORDERING),
+            jitter_depth: self.shared.jitter_depth.load(LOAD_ORDERING),
+            jitter_rate: self.shared.jitter_rate.load(LOAD_ORDERING),
+            fade_in: self.shared.fade_in.load(LOAD_ORDERING),
+            fade_out: self.shared.fade_out.load(LOAD_ORDERING),
+            noise_type: self.shared.noise_type.load(LOAD_ORDERING),
+            noise_lp: self.shared.noise_lp.load(LOAD_ORDERING),
+        }
+    }
+
+    pub fn set_params(&mut self, p: &PartialParams) {
+        self.freq_mul(p.freq_mul);
+        self.freq_add(p.freq_add);
+        self.amp_mul(p.amp_mul);
+        self.noise_amp_mul(p.noise_amp_mul);
+        self.noise_bw_scale(p.noise_bw_scale);
+        self.noise_bw_hz(p.noise_bw_hz);
+        self.detune(p.detune);
+        self.mute(p.mute);
+        self.sine_on(p.sine_on);
+        self.noise_on(p.noise_on);
+        self.jitter(p.jitter_depth, p.jitter_rate);
+        self.fade_in(p.fade_in);
+        self.fade_out(p.fade_out);
+        self.noise_type(p.noise_type);
+        self.noise_lp(p.noise_lp);
+    }
+
+    //the oscillator phase in radians, 0..2pi, as of the last ParitalSynth::report_phase
+    pub fn phase(&self) -> f64 {
+        2f64 * std::f64::consts::PI * self.phase.load(LOAD_ORDERING)
+    }
+
+    pub fn new(times: &SlewTimes, sample_rate: f64) -> (Self, ParitalSynth) {
+        Self::create(None, times, sample_rate)
+    }
+
+    //a new handle and synth starting from this handle's parameter values, used when a bank is
+    //rebuilt so that transforms carry over without slewing
+    pub fn new_like(&self, times: &SlewTimes, sample_rate: f64) -> (Self, ParitalSynth) {
+        Self::create(Some(self), times, sample_rate)
+    }
+
+    fn create(
+        like: Option<&ParitalSynthHandle>,
+        times: &SlewTimes,
+        sample_rate: f64,
+    ) -> (Self, ParitalSynth) {
+        let shared = SharedParams::new(like.map(|h| &h.shared));
+        let synth = ParitalSynth::new(shared.clone(), times.clone(), sample_rate);
+        (
+            Self {
+                phase: synth.phase_out.clone(),
+                shared,
+            },
+            synth,
+        )
+    }
+}
+
+impl ParitalSynth {
+    fn new(shared: SharedParams, times: SlewTimes, sample_rate: f64) -> Self {
+        let mut s = Self {
+            osc: Osc::default(),
+            rng: ChaCha8Rng::from_entropy(),
+            phase_freq_mul: 1f64 / sample_rate,
+            phase: 0.into(),
+            noise_phase: 0.into(),
+            noise_x0: 0f64,
+            noise_x1: 0f64,
+            noise_y0: 0f64,
+            noise_y1: 0f64,
+            noise_l: 0f64,
+            noise_r: 0f64,
+
+            freq_mul: Slewed::new(shared.freq_mul, 0.001f64, times.freq_mul, sample_rate),
+            freq_add: Slewed::new(shared.freq_add, 1f64, times.freq_add, sample_rate),
+            amp_mul: Slewed::new(shared.amp_mul, 0.001f64, times.amp_mul, sample_rate),
+            noise_amp_mul: Slewed::new(
+                shared.noise_amp_mul,
+                0.001f64,
+                times.noise_amp_mul,
+                sample_rate,
+            ),
+            noise_bw_scale: Slewed::new(
+                shared.noise_bw_scale,
+                0.001f64,
+                times.noise_bw_scale,
+                sample_rate,
+            ),
+            noise_bw_hz: Slewed::new(shared.noise_bw_hz, 1f64, times.noise_bw_hz, sample_rate),
+            noise_bw_abs: shared.noise_bw_abs,
+            noise_type: shared.noise_type,
+            noise_lp: shared.noise_lp,
+            mute: shared.mute,
+            sine_on: shared.sine_on,
+            noise_on: shared.noise_on,
+
+            detune: shared.detune,
+            jitter_depth: shared.jitter_depth,
+            jitter_rate: shared.jitter_rate,
+            jitter_phase: 0f64,
+            jitter_x0: 0f64,
+            jitter_x1: 0f64,
+            fade_in: shared.fade_in,
+            fade_out: shared.fade_out,
+            loaded: Loaded::default(),
+            phase_out: Arc::new(Atomic::new(0f64)),
+        };
+        s.load();
+        s.draw_noise();
+        s
+    }
+
+    //read the shared parameters, the synth methods only use the loaded values so this should be
+    //called once per block
+    pub fn load(&mut self) {
+        let sample_rate = 1f64 / self.phase_freq_mul;
+        self.freq_mul.load(sample_rate);
+        self.freq_add.load(sample_rate);
+        self.amp_mul.load(sample_rate);
+        self.noise_amp_mul.load(sample_rate);
+        self.noise_bw_scale.load(sample_rate);
+        self.noise_bw_hz.load(sample_rate);
+        let noise_lp = self.noise_lp.load(LOAD_ORDERING);
+        self.loaded = Loaded {
+            noise_bw_abs: self.noise_bw_abs.load(LOAD_ORDERING),
+            noise_type: self.noise_type.load(LOAD_ORDERING),
+            noise_lp_coeff: if noise_lp > 0f64 {
+                Some(1f64 - (-2f64 * std::f64::consts::PI * noise_lp * self.phase_freq_mul).exp())
+            } else {
+                None
+            },
+            mute: self.mute.load(LOAD_ORDERING),
+            sine_on: self.sine_on.load(LOAD_ORDERING),
+            noise_on: self.noise_on.load(LOAD_ORDERING),
+            detune: self.detune.load(LOAD_ORDERING),
+            jitter_depth: self.jitter_depth.load(LOAD_ORDERING),
+            jitter_rate: self.jitter_rate.load(LOAD_ORDERING),
+            fade_in: self.fade_in.load(LOAD_ORDERING),
+            fade_out: self.fade_out.load(LOAD_ORDERING),
+        };
+    }
+
+    //the frequency the synth plays for freq, less the jitter, as of the last slew
+    pub fn transformed_freq(&self, freq: f64) -> f64 {
+        (freq * self.freq_mul.val() + self.freq_add.val()) * (self.loaded.detune / 1200f64).exp2()
+    }
+
+    //frequency ratio from the static detune and the random jitter lfo
+    fn detune_ratio(&mut self) -> f64 {
+        let detune = self.loaded.detune;
+        let depth = self.loaded.jitter_depth;
+        if depth == 0f64 {
+            if detune == 0f64 {
+                1f64
+            } else {
+                (detune / 1200f64).exp2()
+            }
+        } else {
+            self.jitter_phase += self.loaded.jitter_rate * self.phase_freq_mul;
+            if self.jitter_phase >= 1f64 {
+                self.jitter_phase = self.jitter_phase.fract();
+                self.jitter_x0 = self.jitter_x1;
+                self.jitter_x1 = noise(&mut self.rng);
+            }
+            let jitter = lerp(self.jitter_x0, self.jitter_x1, self.jitter_phase);
+            ((detune + depth * jitter) / 1200f64).exp2()
+        }
+    }
+
+    fn draw_noise(&mut self) {
+        let t = self.loaded.noise_type;
+        self.noise_x0 = t.draw(&mut self.rng);
+        self.noise_x1 = t.draw(&mut self.rng);
+        self.noise_y0 = t.draw(&mut self.rng);
+        self.noise_y1 = t.draw(&mut self.rng);
+        self.jitter_x0 = noise(&mut self.rng);
+        self.jitter_x1 = noise(&mut self.rng);
+    }
+
+    //restart the noise from a fixed seed, for repeatable offline renders
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        self.draw_noise();
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.phase_freq_mul = 1f64 / sample_rate;
+    }
+
+    pub fn set_osc(&mut self, osc: Osc) {
+        self.osc = osc;
+    }
+
+    pub fn osc(&self) -> &Osc {
+        &self.osc
+    }
+
+    //the phase, in cycles, when it is kept by process_lanes
+    pub fn set_phase(&mut self, phase: f64) {
+        self.phase = phase;
+    }
+
+    //start the oscillators over, for repeatable onsets
+    pub fn restart(&mut self) {
+        self.phase = 0f64;
+        self.noise_phase = 0f64;
+    }
+
+    pub fn report_phase(&self) {
+        self.phase_out.store(self.phase, STORE_ORDERING);
+    }
+
+    pub fn muted(&self) -> bool {
+        self.loaded.mute
+    }
+
+    //the birth and death fade times, in ms, as of the last load
+    pub fn fades(&self) -> (f64, f64) {
+        (self.loaded.fade_in, self.loaded.fade_out)
+    }
+
+    pub fn slew(&mut self) {
+        self.freq_mul.update();
+        self.freq_add.update();
+        self.amp_mul.update();
+        self.noise_amp_mul.update();
+        self.noise_bw_scale.update();
+        self.noise_bw_hz.update();
+    }
+
+    //advance the noise and the parameters, returning the transformed frequency and amplitudes,
+    //the sine phase is advanced separately
+    fn step(&mut self, freq: f64, sin_amp: f64, noise_energy: f64) -> (f64, f64, f64) {
+        self.slew();
+
+        //apply transformations
+        //should freq scaling affect noise bandwidth and offset?
+        let freq = (freq * self.freq_mul.val() + self.freq_add.val()) * self.detune_ratio();
+        let sin_amp = if self.loaded.sine_on {
+            self.amp_mul.val() * sin_amp
+        } else {
+            0f64
+        };
+        let noise_energy = if self.loaded.noise_on {
+            noise_energy * self.noise_amp_mul.val()
+        } else {
+            0f64
+        };
+
+        //TODO if freq > 500 { 1 } else { 0.25 } * bw...
+        let noise_bw = if self.loaded.noise_bw_abs {
+            self.noise_bw_hz.val()
+        } else {
+            freq * self.noise_bw_scale.val()
+        };
+
+        self.noise_phase = self.noise_phase + noise_bw * self.phase_freq_mul;
+        if self.noise_phase >= 1f64 {
+            self.noise_phase = self.noise_phase.fract();
+            let t = self.loaded.noise_type;
+            self.noise_x0 = self.noise_x1;
+            self.noise_x1 = t.draw(&mut self.rng);
+            self.noise_y0 = self.noise_y1;
+            self.noise_y1 = t.draw(&mut self.rng);
+        }
+        let l = lerp(self.noise_x0, self.noise_x1, self.noise_phase);
+        let r = lerp(self.noise_y0, self.noise_y1, self.noise_phase);
+        match self.loaded.noise_lp_coeff {
+            Some(a) => {
+                self.noise_l = flush_denormal(self.noise_l + a * (l - self.noise_l));
+                self.noise_r = flush_denormal(self.noise_r + a * (r - self.noise_r));
+            }
+            None => {
+                self.noise_l = l;
+                self.noise_r = r;
+            }
+        }
+
+        (freq, sin_amp, noise_energy)
+    }
+
+    fn advance(&mut self, freq: f64) {
+        self.phase = (self.phase + freq * self.phase_freq_mul).fract();
+    }
+
+    //sine at the current phase plus an offset in cycles
+    fn sin_at(&self, offset: f64) -> f64 {
+        self.osc.sin(self.phase + offset)
+    }
+
+    //the interpolated, optionally filtered, noise modulator
+    fn noise_at(&self) -> f64 {
+        flush_denormal(self.noise_l)
+    }
+
+    pub fn synth(&mut self, freq: f64, sin_amp: f64, noise_energy: f64) -> f32 {
+        let (freq, sin_amp, noise_energy) = self.step(freq, sin_amp, noise_energy);
+        self.advance(freq);
+        let sin = self.sin_at(0f64);
+        let noise = self.noise_at();
+
+        flush_denormal(sin * sin_amp + noise * sin * noise_energy) as f32
+    }
+
+    //the residual at the current oscillator state, for routing the noise apart from the sine after
+    //a synth call made with no noise energy
+    pub fn residual(&self, noise_energy: f64) -> f32 {
+        if !self.loaded.noise_on {
+            return 0f32;
+        }
+        let noise_energy = noise_energy * self.noise_amp_mul.val();
+        flush_denormal(self.noise_at() * self.sin_at(0f64) * noise_energy) as f32
+    }
+
+    //advance the noise, returning the phase increment and the amplitude of the sine, including
+    //the noise modulation, for a slot of process_lanes, which holds the phase
+    pub fn synth_lane(&mut self, freq: f64, sin_amp: f64, noise_energy: f64) -> (f64, f64) {
+        let (freq, sin_amp, noise_energy) = self.step(freq, sin_amp, noise_energy);
+        let noise = self.noise_at();
+        (
+            freq * self.phase_freq_mul,
+            flush_denormal(sin_amp + noise * noise_energy),
+        )
+    }
+
+    //the sine is shared, the right channel blends towards an independent noise modulator by width
+    pub fn synth_stereo(
+        &mut self,
+        freq: f64,
+        sin_amp: f64,
+        noise_energy: f64,
+        width: f64,
+    ) -> (f32, f32) {
+        let (freq, sin_amp, noise_energy) = self.step(freq, sin_amp, noise_energy);
+        self.advance(freq);
+        let sin = self.sin_at(0f64);
+        let left = self.noise_at();
+        let right = flush_denormal(lerp(left, self.noise_r, width));
+
+        (
+            flush_denormal(sin * sin_amp + left * sin * noise_energy) as f32,
+            flush_denormal(sin * sin_amp + right * sin * noise_energy) as f32,
+        )
+    }
+
+    //left and right ears with an interaural delay in seconds (positive means the right ear leads)
+    //and per ear gains
+    pub fn synth_binaural(
+        &mut self,
+        freq: f64,
+        sin_amp: f64,
+        noise_energy: f64,
+        delay: f64,
+        gains: (f64, f64),
+    ) -> (f32, f32) {
+        let (freq, sin_amp, noise_energy) = self.step(freq, sin_amp, noise_energy);
+        self.advance(freq);
+        let offset = freq * delay * 0.5f64;
+        let noise = self.noise_at();
+        let left = self.sin_at(-offset);
+        let right = self.sin_at(offset);
+        let amp = flush_denormal(sin_amp + noise * noise_energy);
+
+        (
+            flush_denormal(gains.0 * left * amp) as f32,
+            flush_denormal(gains.1 * right * amp) as f32,
+        )
+    }
+}
+
+pub const SCALE_MAX: usize = 32;
+
+//an octave repeating scale, degrees are stored as sorted fractions of an octave above the root
+#[derive(Clone, Copy)]
+pub struct Scale {
+    root: f64,
+    degrees: [f64; SCALE_MAX],
+    len: usize,
+}
+
+impl Scale {
+    //ratios are relative to the root frequency, anything past SCALE_MAX is ignored
+    pub fn new(root: f64, ratios: &[f64]) -> Self {
+        let mut degrees = [0f64; SCALE_MAX];
+        let mut len = 0;
+        for r in ratios.iter().filter(|r| **r > 0f64).take(SCALE_MAX) {
+            let d = r.log2();
+            degrees[len] = d - d.floor();
+            len += 1;
+        }
+        degrees[0..len].sort_by(|a, b| a.partial_cmp(b).unwrap());
+        Self { root, degrees, len }
+    }
+
+    pub fn from_midi(notes: &[f64]) -> Self {
+        let ratios: Vec<f64> = notes.iter().map(|n| (n / 12f64).exp2()).collect();
+        //midi note 0
+        Self::new(440f64 * (-69f64 / 12f64).exp2(), &ratios)
+    }
+
+    //move freq toward the nearest scale pitch, strength 1 snaps completely
+    pub fn quantize(&self, freq: f64, strength: f64) -> f64 {
+        if self.len == 0 || freq <= 0f64 || strength == 0f64 {
+            return freq;
+        }
+        let x = (freq / self.root).log2();
+        let oct = x.floor();
+        let fract = x - oct;
+        //the first degree an octave up covers fractions above the last degree
+        let nearest = self.degrees[0..self.len]
+            .iter()
+            .cloned()
+            .chain(std::iter::once(self.degrees[0] + 1f64))
+            .chain(std::iter::once(self.degrees[self.len - 1] - 1f64))
+            .fold(self.degrees[0], |n, d| {
+                if (d - fract).abs() < (n - fract).abs() {
+                    d
+                } else {
+                    n
+                }
+            });
+        freq * (strength * (nearest - fract)).exp2()
+    }
+}
+
+//move freq toward the nearest integer multiple of f0, strength 1 locks to it
+pub fn harmonize(freq: f64, f0: f64, strength: f64) -> f64 {
+    if f0 <= 0f64 || freq <= 0f64 || strength == 0f64 {
+        return freq;
+    }
+    let target = (freq / f0).round().max(1f64) * f0;
+    freq * (target / freq).powf(strength)
+}
+
+//how to interpolate between analysis frames
+#[derive(Clone, Copy, PartialEq)]
+pub enum Interp {
+    None,
+    Linear,
+    Cubic,
+}
+
+impl Interp {
+    //y holds the frames before, at, and the two after the position
+    pub fn apply(self, y: &[f64; 4], frac: f64) -> f64 {
+        match self {
+            Interp::None => {
+                if frac < 0.5f64 {
+                    y[1]
+                } else {
+                    y[2]
+                }
+            }
+            Interp::Linear => lerp(y[1], y[2], frac),
+            Interp::Cubic => {
+                //4 point, 3rd order hermite
+                let c1 = 0.5f64 * (y[2] - y[0]);
+                let c2 = y[0] - 2.5f64 * y[1] + 2f64 * y[2] - 0.5f64 * y[3];
+                let c3 = 0.5f64 * (y[3] - y[0]) + 1.5f64 * (y[1] - y[2]);
+                ((c3 * frac + c2) * frac + c1) * frac + y[1]
+            }
+        }
+    }
+}
+
+//the amplitude curve between two frames
+#[derive(Clone, Copy, PartialEq)]
+pub enum AmpInterp {
+    //whatever Interp is in use
+    Follow,
+    Linear,
+    //linear in decibels
+    Exp,
+    //raised cosine
+    Cosine,
+}
+
+impl AmpInterp {
+    pub fn apply(self, a0: f64, a1: f64, frac: f64) -> f64 {
+        match self {
+            AmpInterp::Follow | AmpInterp::Linear => lerp(a0, a1, frac),
+            AmpInterp::Exp => db_to_amp(lerp(amp_to_db(a0), amp_to_db(a1), frac)),
+            AmpInterp::Cosine => lerp(
+                a0,
+                a1,
+                0.5f64 - 0.5f64 * (std::f64::consts::PI * frac).cos(),
+            ),
+        }
+    }
+}
+
+//what to do with positions outside of the data
+#[derive(Clone, Copy, PartialEq)]
+pub enum OobMode {
+    Clamp,
+    Wrap,
+    Mirror,
+    Silence,
+}
+
+impl OobMode {
+    //map a position, in frames, into 0..=last, None means silence
+    pub fn apply(self, pos: f64, last: f64) -> Option<f64> {
+        if pos >= 0f64 && pos < last {
+            return Some(pos);
+        }
+        match self {
+            OobMode::Clamp => Some(pos.max(0f64).min(last)),
+            OobMode::Wrap => Some(pos.rem_euclid(last)),
+            OobMode::Mirror => {
+                let p = pos.rem_euclid(2f64 * last);
+                Some(if p > last { 2f64 * last - p } else { p })
+            }
+            OobMode::Silence => None,
+        }
+    }
+}
+
+//decibels at or below this are silence
+pub const DB_FLOOR: f64 = -120f64;
+
+//linear amplitude to decibels, silence is DB_FLOOR
+pub fn amp_to_db(amp: f64) -> f64 {
+    if amp <= 0f64 {
+        DB_FLOOR
+    } else {
+        (20f64 * amp.log10()).max(DB_FLOOR)
+    }
+}
+
+//the most, in dB either way, that eq_loudness_gain boosts or cuts
+const EQ_LOUDNESS_MAX_DB: f64 = 24f64;
+
+//the a-weighting magnitude at freq, unity at 1kHz, a rough inverse of the equal loudness contours
+pub fn a_weight(freq: f64) -> f64 {
+    let f2 = freq * freq;
+    let r = 12194f64.powi(2) * f2 * f2
+        / ((f2 + 20.6f64.powi(2))
+            * ((f2 + 107.7f64.powi(2)) * (f2 + 737.9f64.powi(2))).sqrt()
+            * (f2 + 12194f64.powi(2)));
+    //+2dB puts 1kHz at unity
+    r * 1.2589f64
+}
+
+//the gain that keeps a partial moved from one frequency to another about as loud, amount 0..1
+pub fn eq_loudness_gain(from: f64, to: f64, amount: f64) -> f64 {
+    if amount == 0f64 || from <= 0f64 || to <= 0f64 {
+        return 1f64;
+    }
+    let limit = db_to_amp(EQ_LOUDNESS_MAX_DB);
+    (a_weight(from) / a_weight(to))
+        .powf(amount)
+        .max(1f64 / limit)
+        .min(limit)
+}
+
+//decibels to linear amplitude, 0 dB is unity
+pub fn db_to_amp(db: f64) -> f64 {
+    if db <= DB_FLOOR {
+        0f64
+    } else {
+        (db / 20f64 * std::f64::consts::LN_10).exp()
+    }
+}
+
+pub fn lerp(x0: f64, x1: f64, frac: f64) -> f64 {
+    x0 + (x1 - x0) * frac
+}
+
+//the two frame indices and the fraction to interpolate between them with, clamped to the
+//frames. a single frame is interpolated with itself
+pub fn frame_pos(pos: f64, frames: usize) -> (usize, usize, f64) {
+    let last = frames.saturating_sub(1);
+    let p0 = pos.floor() as isize;
+    if p0 < 0 || last == 0 {
+        (0, std::cmp::min(1, last), 0f64)
+    } else if p0 as usize >= last {
+        (last - 1, last, 1f64)
+    } else {
+        (p0 as usize, p0 as usize + 1, pos.fract())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMES: AdsrTimes = AdsrTimes {
+        attack: 4f64,
+        decay: 4f64,
+        sustain: 0.5f64,
+        release: 4f64,
+    };
+
+    fn run(a: &mut Adsr, samples: usize) -> Vec<f64> {
+        (0..samples).map(|_| a.process(&TIMES)).collect()
+    }
+
+    #[test]
+    fn adsr_stages() {
+        let mut a = Adsr::default();
+        assert_eq!(run(&mut a, 2), vec![0f64, 0f64]);
+        a.on();
+        assert_eq!(run(&mut a, 4), vec![0.25f64, 0.5f64, 0.75f64, 1f64]);
+        assert!(a.stage == AdsrStage::Decay);
+        assert_eq!(run(&mut a, 4), vec![0.875f64, 0.75f64, 0.625f64, 0.5f64]);
+        assert!(a.stage == AdsrStage::Sustain);
+        assert_eq!(run(&mut a, 3), vec![0.5f64; 3]);
+        a.off();
+        assert_eq!(run(&mut a, 4), vec![0.375f64, 0.25f64, 0.125f64, 0f64]);
+        assert!(a.stage == AdsrStage::Off);
+        //off when already off stays silent
+        a.off();
+        assert_eq!(run(&mut a, 2), vec![0f64, 0f64]);
+    }
+
+    #[test]
+    fn adsr_retrigger_from_level() {
+        let mut a = Adsr::default();
+        a.on();
+        run(&mut a, 11);
+        a.off();
+        assert_eq!(run(&mut a, 1), vec![0.375f64]);
+        //the attack picks up from the release level rather than jumping to 0
+        a.on();
+        assert_eq!(run(&mut a, 3), vec![0.625f64, 0.875f64, 1f64]);
+        assert!(a.stage == AdsrStage::Decay);
+    }
+
+    #[test]
+    fn adsr_release_from_attack() {
+        let mut a = Adsr::default();
+        a.on();
+        assert_eq!(run(&mut a, 2), vec![0.25f64, 0.5f64]);
+        //the release falls from the attack level over the release time, skipping the decay
+        a.off();
+        assert_eq!(run(&mut a, 4), vec![0.375f64, 0.25f64, 0.125f64, 0f64]);
+        assert!(a.stage == AdsrStage::Off);
+    }
+
+    //the frequency of a, possibly fractional, midi note
+    fn midi(note: f64) -> f64 {
+        440f64 * ((note - 69f64) / 12f64).exp2()
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9 * b.abs().max(1f64)
+    }
+
+    #[test]
+    fn scale_snaps_to_degrees() {
+        let major = Scale::from_midi(&[0f64, 2f64, 4f64, 5f64, 7f64, 9f64, 11f64]);
+        assert!(close(major.quantize(midi(61.4f64), 1f64), midi(62f64)));
+        assert!(close(major.quantize(midi(70.7f64), 1f64), midi(71f64)));
+        assert!(close(major.quantize(midi(33.2f64), 1f64), midi(33f64)));
+        //above the last degree snaps to the first degree an octave up
+        assert!(close(major.quantize(midi(71.8f64), 1f64), midi(72f64)));
+        //below the first degree snaps to the last degree an octave down
+        let sparse = Scale::from_midi(&[7f64, 11f64]);
+        assert!(close(sparse.quantize(midi(61f64), 1f64), midi(59f64)));
+        //pitches on the scale stay put
+        assert!(close(major.quantize(midi(64f64), 1f64), midi(64f64)));
+    }
+
+    #[test]
+    fn scale_strength() {
+        let major = Scale::from_midi(&[0f64, 2f64, 4f64, 5f64, 7f64, 9f64, 11f64]);
+        let f = midi(61.4f64);
+        assert_eq!(major.quantize(f, 0f64), f);
+        assert!(close(major.quantize(f, 1f64), midi(62f64)));
+        //part way moves part of the distance in pitch
+        assert!(close(major.quantize(f, 0.5f64), midi(61.7f64)));
+        //an empty scale and silence are left alone
+        assert_eq!(Scale::new(100f64, &[]).quantize(f, 1f64), f);
+        assert_eq!(major.quantize(0f64, 1f64), 0f64);
+    }
+
+    #[test]
+    fn harmonize_locks_to_multiples() {
+        assert!(close(harmonize(205f64, 100f64, 1f64), 200f64));
+        assert!(close(harmonize(349f64, 100f64, 1f64), 300f64));
+        assert_eq!(harmonize(205f64, 100f64, 0f64), 205f64);
+        //part way moves part of the distance in pitch
+        assert!(close(
+            harmonize(205f64, 100f64, 0.5f64),
+            (205f64 * 200f64).sqrt()
+        ));
+        //nothing locks below the fundamental
+        assert!(close(harmonize(30f64, 100f64, 1f64), 100f64));
+        //without a fundamental nothing moves
+        assert_eq!(harmonize(205f64, 0f64, 1f64), 205f64);
+    }
+
+    #[test]
+    fn a_weighting() {
+        assert!(amp_to_db(a_weight(1000f64)).abs() < 0.01f64);
+        assert!((amp_to_db(a_weight(100f64)) + 19.1f64).abs() < 0.1f64);
+        assert!((amp_to_db(a_weight(10000f64)) + 2.5f64).abs() < 0.1f64);
+    }
+
+    #[test]
+    fn eq_loudness() {
+        assert_eq!(eq_loudness_gain(1000f64, 100f64, 0f64), 1f64);
+        assert!(close(eq_loudness_gain(440f64, 440f64, 1f64), 1f64));
+        //moving down where the ear is less sensitive boosts, moving back up cuts as much
+        let boost = amp_to_db(eq_loudness_gain(1000f64, 100f64, 1f64));
+        assert!((boost - 19.1f64).abs() < 0.1f64);
+        assert!((amp_to_db(eq_loudness_gain(100f64, 1000f64, 1f64)) + boost).abs() < 1e-9);
+        assert!((amp_to_db(eq_loudness_gain(1000f64, 100f64, 0.5f64)) - boost / 2f64).abs() < 1e-9);
+        //the gain is limited either way
+        assert!(close(
+            eq_loudness_gain(1000f64, 20f64, 1f64),
+            db_to_amp(EQ_LOUDNESS_MAX_DB)
+        ));
+        assert!(close(
+            eq_loudness_gain(20f64, 1000f64, 1f64),
+            db_to_amp(-EQ_LOUDNESS_MAX_DB)
+        ));
+    }
+}
@@ -0,0 +1,503 @@
+use crate::data::{
+    band_of, bands_from_noise, check_import_size, frame_samps, tracks_duration, AtsData, Peak,
+    TrackPoint, NOISE_BANDS,
+};
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+//imports without a sample rate or duration
+const IMPORT_SR: f64 = 44100f64;
+const IMPORT_FRAME_SECONDS: f64 = 0.01f64;
+
+fn header_fields(data: &AtsData) -> [(&'static str, f64); 9] {
+    let h = &data.header;
+    [
+        ("sr", h.sr),
+        ("frame_size", h.fs),
+        ("window_size", h.ws),
+        ("partials", h.par),
+        ("frames", h.fra),
+        ("amp_max", h.ma),
+        ("freq_max", h.mf),
+        ("dur", h.dur),
+        ("type", h.typ),
+    ]
+}
+
+//a table of `frame,time,partial,freq,amp,noise,phase` rows preceded by the header as # comments,
+//band energies go in a second table, <stem>_bands.csv, of `frame,band,energy` rows
+pub fn write_csv<P: AsRef<Path>>(data: &AtsData, path: P) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let mut f = BufWriter::new(File::create(path)?);
+    writeln!(f, "# source {}", data.source)?;
+    for (k, v) in header_fields(data).iter() {
+        writeln!(f, "# {} {}", k, v)?;
+    }
+    writeln!(f, "frame,time,partial,freq,amp,noise,phase")?;
+    for (i, frame) in data.frames.iter().enumerate() {
+        let t = data.frame_time(i);
+        for (j, p) in frame.iter().enumerate() {
+            writeln!(
+                f,
+                "{},{},{},{},{},{},{}",
+                i,
+                t,
+                j,
+                p.freq,
+                p.amp,
+                p.noise_energy.unwrap_or(0f64),
+                p.phase.unwrap_or(0f64)
+            )?;
+        }
+    }
+    f.flush()?;
+    if let Some(noise) = &data.noise {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let bands = path.with_file_name(format!("{}_bands.csv", stem));
+        let mut f = BufWriter::new(File::create(bands)?);
+        writeln!(f, "frame,band,energy")?;
+        for (i, energies) in noise.iter().enumerate() {
+            for (b, e) in energies.iter().enumerate() {
+                writeln!(f, "{},{},{}", i, b, e)?;
+            }
+        }
+        f.flush()?;
+    }
+    Ok(())
+}
+
+//the header, frame times, a track of freq, amp, noise and phase for every partial, and the band
+//energies of every frame
+pub fn write_json<P: AsRef<Path>>(data: &AtsData, path: P) -> std::io::Result<()> {
+    let mut header = serde_json::Map::new();
+    for (k, v) in header_fields(data).iter() {
+        header.insert(k.to_string(), json!(v));
+    }
+    let track = |get: &dyn Fn(&Peak) -> f64, p: usize| -> Vec<f64> {
+        data.frames.iter().map(|f| get(&f[p])).collect()
+    };
+    let partials: Vec<serde_json::Value> = (0..data.partials())
+        .map(|p| {
+            json!({
+                "freq": track(&|pk| pk.freq, p),
+                "amp": track(&|pk| pk.amp, p),
+                "noise": track(&|pk| pk.noise_energy.unwrap_or(0f64), p),
+                "phase": track(&|pk| pk.phase.unwrap_or(0f64), p),
+            })
+        })
+        .collect();
+    let times: Vec<f64> = (0..data.frames.len()).map(|i| data.frame_time(i)).collect();
+    let bands: Option<Vec<Vec<f64>>> = data
+        .noise
+        .as_ref()
+        .map(|n| n.iter().map(|e| e.to_vec()).collect());
+    let doc = json!({
+        "source": data.source,
+        "header": header,
+        "times": times,
+        "partials": partials,
+        "bands": bands,
+    });
+    let mut f = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut f, &doc)?;
+    f.flush()
+}
+
+//a point of imported data
+struct Row {
+    frame: usize,
+    partial: usize,
+    freq: f64,
+    amp: f64,
+    noise: Option<f64>,
+}
+
+//data from rows, missing points are silent. without bands the band energies are found from the
+//peaks' noise
+fn build(
+    rows: Vec<Row>,
+    bands: Option<Vec<[f64; NOISE_BANDS]>>,
+    sr: Option<f64>,
+    dur: Option<f64>,
+    source: String,
+) -> Result<AtsData, String> {
+    let frame_count = rows
+        .iter()
+        .map(|r| r.frame.saturating_add(1))
+        .max()
+        .unwrap_or(0);
+    let partials = rows
+        .iter()
+        .map(|r| r.partial.saturating_add(1))
+        .max()
+        .unwrap_or(0);
+    if frame_count == 0 {
+        return Err(format!("no points in {}", source));
+    }
+    check_import_size(frame_count, partials).map_err(|e| format!("{}: {}", source, e))?;
+    let sr = sr.unwrap_or(IMPORT_SR);
+    let dur = dur.unwrap_or(frame_count as f64 * IMPORT_FRAME_SECONDS);
+    if sr <= 0f64 || dur <= 0f64 || !sr.is_finite() || !dur.is_finite() {
+        return Err(format!(
+            "{} needs a positive sample rate and duration, found {} and {}",
+            source, sr, dur
+        ));
+    }
+    let with_noise = bands.is_some() || rows.iter().any(|r| r.noise.is_some());
+    let mut frames: Vec<Vec<Peak>> = (0..frame_count)
+        .map(|_| {
+            (0..partials)
+                .map(|_| Peak {
+                    amp: 0f64,
+                    freq: 0f64,
+                    noise_energy: if with_noise { Some(0f64) } else { None },
+                    phase: None,
+                    band: 0,
+                })
+                .collect()
+        })
+        .collect();
+    for r in rows.iter() {
+        let p = &mut frames[r.frame][r.partial];
+        p.freq = r.freq;
+        p.amp = r.amp;
+        p.band = band_of(r.freq);
+        if with_noise {
+            p.noise_energy = Some(r.noise.unwrap_or(0f64));
+        }
+    }
+    let frames: Vec<Box<[Peak]>> = frames.into_iter().map(|f| f.into_boxed_slice()).collect();
+    let noise = if let Some(bands) = bands {
+        if bands.len() != frame_count {
+            return Err(format!(
+                "{} has {} frames of bands for {} frames",
+                source,
+                bands.len(),
+                frame_count
+            ));
+        }
+        Some(bands)
+    } else if with_noise {
+        let ws = 2f64 * frame_samps(frame_count, dur, sr);
+        Some(bands_from_noise(&frames, ws))
+    } else {
+        None
+    };
+    Ok(AtsData::from_peaks(
+        frames,
+        noise.map(|n| n.into_boxed_slice()),
+        dur,
+        sr,
+        source,
+    ))
+}
+
+//rows of `frame,partial,freq,amp[,noise]`, or any columns named in a header line like the one
+//write_csv makes, whose `# sr` and `# dur` comments are also read
+pub fn read_csv<P: AsRef<Path>>(path: P) -> Result<AtsData, String> {
+    let path = path.as_ref();
+    let source = path.to_string_lossy().into_owned();
+    let text =
+        std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", source, e))?;
+    let mut columns: Vec<String> = ["frame", "partial", "freq", "amp", "noise"]
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+    let (mut sr, mut dur) = (None, None);
+    let mut rows = Vec::new();
+    let mut first = true;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(comment) = line.strip_prefix('#') {
+            let mut words = comment.split_whitespace();
+            match (
+                words.next(),
+                words.next().and_then(|v| v.parse::<f64>().ok()),
+            ) {
+                (Some("sr"), Some(v)) => sr = Some(v),
+                (Some("dur"), Some(v)) => dur = Some(v),
+                _ => (),
+            }
+            continue;
+        }
+        let cells: Vec<&str> = line.split(',').map(|c| c.trim()).collect();
+        if first && cells.iter().any(|c| c.parse::<f64>().is_err()) {
+            columns = cells.iter().map(|c| c.to_lowercase()).collect();
+            first = false;
+            continue;
+        }
+        first = false;
+        let get = |name: &str| -> Result<Option<f64>, String> {
+            match columns.iter().position(|c| c == name) {
+                Some(i) if i < cells.len() => cells[i]
+                    .parse::<f64>()
+                    .map(Some)
+                    .map_err(|_| format!("{} line {}: bad {} {}", source, n + 1, name, cells[i])),
+                _ => Ok(None),
+            }
+        };
+        let need = |name: &str| -> Result<f64, String> {
+            get(name)?.ok_or_else(|| format!("{} line {}: no {}", source, n + 1, name))
+        };
+        let index = |name: &str| -> Result<usize, String> {
+            let v = need(name)?;
+            if v >= 0f64 && v.fract() == 0f64 {
+                Ok(v as usize)
+            } else {
+                Err(format!("{} line {}: bad {} {}", source, n + 1, name, v))
+            }
+        };
+        rows.push(Row {
+            frame: index("frame")?,
+            partial: index("partial")?,
+            freq: need("freq")?,
+            amp: need("amp")?,
+            noise: get("noise")?,
+        });
+    }
+    build(rows, None, sr, dur, source)
+}
+
+//the layout write_json makes, only `partials` with `freq` and `amp` tracks is required
+pub fn read_json<P: AsRef<Path>>(path: P) -> Result<AtsData, String> {
+    let path = path.as_ref();
+    let source = path.to_string_lossy().into_owned();
+    let text =
+        std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", source, e))?;
+    let doc: serde_json::Value =
+        serde_json::from_str(&text).map_err(|e| format!("failed to parse {}: {}", source, e))?;
+    let floats = |v: &serde_json::Value| -> Option<Vec<f64>> {
+        v.as_array()
+            .and_then(|a| a.iter().map(|x| x.as_f64()).collect::<Option<Vec<f64>>>())
+    };
+    let partials = doc["partials"]
+        .as_array()
+        .ok_or_else(|| format!("{} has no partials", source))?;
+    let mut rows = Vec::new();
+    for (j, p) in partials.iter().enumerate() {
+        let freq =
+            floats(&p["freq"]).ok_or_else(|| format!("{} partial {} has no freq", source, j))?;
+        let amp =
+            floats(&p["amp"]).ok_or_else(|| format!("{} partial {} has no amp", source, j))?;
+        if freq.len() != amp.len() {
+            return Err(format!(
+                "{} partial {} freq and amp lengths differ",
+                source, j
+            ));
+        }
+        let noise = floats(&p["noise"]);
+        for (i, (f, a)) in freq.iter().zip(amp.iter()).enumerate() {
+            rows.push(Row {
+                frame: i,
+                partial: j,
+                freq: *f,
+                amp: *a,
+                noise: noise.as_ref().and_then(|n| n.get(i).cloned()),
+            });
+        }
+    }
+    let bands = match doc["bands"].as_array() {
+        Some(frames) => Some(
+            frames
+                .iter()
+                .map(|b| {
+                    let v = floats(b).filter(|v| v.len() == NOISE_BANDS)?;
+                    let mut e = [0f64; NOISE_BANDS];
+                    e.copy_from_slice(&v);
+                    Some(e)
+                })
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| format!("{} bands need {} values per frame", source, NOISE_BANDS))?,
+        ),
+        None => None,
+    };
+    build(
+        rows,
+        bands,
+        doc["header"]["sr"].as_f64(),
+        doc["header"]["dur"].as_f64(),
+        source,
+    )
+}
+
+//spear's par-text-frame-format, a line per frame of `time count` followed by count points of
+//the columns named by point-type
+pub fn read_spear<P: AsRef<Path>>(path: P) -> Result<AtsData, String> {
+    let path = path.as_ref();
+    let source = path.to_string_lossy().into_owned();
+    let text =
+        std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", source, e))?;
+    let mut lines = text.lines().map(|l| l.trim()).filter(|l| !l.is_empty());
+    if lines.next() != Some("par-text-frame-format") {
+        return Err(format!("{} is not in par-text-frame-format", source));
+    }
+    let mut columns: Vec<&str> = vec!["index", "frequency", "amplitude"];
+    for line in &mut lines {
+        if line == "frame-data" {
+            break;
+        }
+        let mut words = line.split_whitespace();
+        if words.next() == Some("point-type") {
+            columns = words.collect();
+        }
+    }
+    let column = |name: &str| {
+        columns
+            .iter()
+            .position(|c| *c == name)
+            .ok_or_else(|| format!("{} has no {} points", source, name))
+    };
+    let (index, freq, amp) = (column("index")?, column("frequency")?, column("amplitude")?);
+    let stride = columns.len();
+    let mut times = Vec::new();
+    let mut frames = Vec::new();
+    for (n, line) in lines.enumerate() {
+        let values = line
+            .split_whitespace()
+            .map(|v| v.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| format!("{} frame {}: bad value", source, n))?;
+        let count = values.get(1).cloned().unwrap_or(-1f64);
+        if count < 0f64 || values.len() != 2 + count as usize * stride {
+            return Err(format!("{} frame {}: point count doesn't match", source, n));
+        }
+        times.push(values[0]);
+        frames.push(
+            values[2..]
+                .chunks(stride)
+                .map(|p| TrackPoint {
+                    id: p[index] as u64,
+                    freq: p[freq],
+                    amp: p[amp],
+                    phase: None,
+                    noise: None,
+                })
+                .collect::<Vec<_>>(),
+        );
+    }
+    if frames.is_empty() {
+        return Err(format!("no frames in {}", source));
+    }
+    AtsData::from_tracks(&frames, tracks_duration(&times), IMPORT_SR, source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data() -> AtsData {
+        let frames = vec![
+            vec![(440f64, 0.5f64), (880f64, 0.25f64)],
+            vec![(441f64, 0.4f64), (882f64, 0.2f64)],
+        ];
+        AtsData::synthetic(&frames, 0.2f64, 44100f64, "text".into())
+    }
+
+    #[test]
+    fn csv_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("d.csv");
+        write_csv(&data(), &path).unwrap();
+        let s = std::fs::read_to_string(&path).unwrap();
+        let rows: Vec<&str> = s.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(rows[0], "frame,time,partial,freq,amp,noise,phase");
+        assert_eq!(rows.len(), 5);
+        assert!(rows[4].starts_with("1,"));
+        assert!(rows[4].contains(",1,882,0.2,"));
+        //no noise, no bands
+        assert!(!dir.path().join("d_bands.csv").exists());
+    }
+
+    #[test]
+    fn json_tracks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("d.json");
+        write_json(&data(), &path).unwrap();
+        let v: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(v["header"]["partials"], json!(2f64));
+        assert_eq!(v["partials"][1]["freq"], json!([880f64, 882f64]));
+        assert!(v["bands"].is_null());
+    }
+
+    #[test]
+    fn import_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let d = data();
+        let csv = dir.path().join("d.csv");
+        let json = dir.path().join("d.json");
+        write_csv(&d, &csv).unwrap();
+        write_json(&d, &json).unwrap();
+        for r in [read_csv(&csv).unwrap(), read_json(&json).unwrap()].iter() {
+            assert_eq!(r.partials(), 2);
+            assert_eq!(r.frames.len(), 2);
+            assert_eq!(r.frames[1][1].freq, 882f64);
+            assert_eq!(r.frames[0][0].amp, 0.5f64);
+            assert_eq!(r.header.sr, d.header.sr);
+            assert!((r.header.dur - d.header.dur).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn import_plain_csv_with_noise() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("p.csv");
+        std::fs::write(&path, "0,0,440,0.5,0.1\n1,1,880,0.25\n").unwrap();
+        let d = read_csv(&path).unwrap();
+        assert!(d.has_noise());
+        assert_eq!(d.frames[0][1].amp, 0f64);
+        assert_eq!(d.frames[0][0].noise_energy, Some(0.1f64));
+        //the band energy matches the peak's noise
+        let band = band_of(440f64);
+        assert!((d.band_rms(0, band) - 0.1f64).abs() < 1e-9);
+        std::fs::write(&path, "0,0,440\n").unwrap();
+        assert!(read_csv(&path).is_err());
+    }
+
+    #[test]
+    fn import_rejects_bad_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("b.csv");
+        //indices this big must fail before the frames are allocated
+        std::fs::write(&path, "1000000000,1000000000,440,0.1\n").unwrap();
+        assert!(read_csv(&path).is_err());
+        std::fs::write(&path, "0,0,440,0.1\n0,1,880,0.1\n").unwrap();
+        assert!(read_csv(&path).is_err());
+        std::fs::write(&path, "# dur 0\n0,0,440,0.1\n1,0,440,0.1\n").unwrap();
+        assert!(read_csv(&path).is_err());
+        std::fs::write(&path, "# dur 0.1\n0,0,440,0.1\n1,0,440,0.1\n").unwrap();
+        assert!(read_csv(&path).is_ok());
+    }
+
+    #[test]
+    fn spear_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("s.txt");
+        let spear = "par-text-frame-format\n\
+            point-type index frequency amplitude\n\
+            partials-count 3\n\
+            frame-count 3\n\
+            frame-data\n\
+            0.0 2 0 440 0.5 1 880 0.25\n\
+            0.01 1 0 441 0.5\n\
+            0.02 2 0 442 0.5 7 1000 0.1\n";
+        std::fs::write(&path, spear).unwrap();
+        let d = read_spear(&path).unwrap();
+        assert_eq!(d.frames.len(), 3);
+        //7 is born too soon after 1 dies to take its track
+        assert_eq!(d.partials(), 3);
+        assert_eq!(d.frames[2][2].freq, 1000f64);
+        assert!((d.header.dur - 0.03f64).abs() < 1e-9);
+        std::fs::write(
+            &path,
+            "par-text-frame-format\nframe-data\n0.0 2 0 440 0.5\n",
+        )
+        .unwrap();
+        assert!(read_spear(&path).is_err());
+    }
+}